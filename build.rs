@@ -0,0 +1,72 @@
+//! Embeds the git commit and the build time, reported by `GET /api/version`.
+
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    println!("cargo:rustc-env=JOB_HUB_GIT_COMMIT={}", git_commit());
+    println!(
+        "cargo:rustc-env=JOB_HUB_BUILD_TIMESTAMP={}",
+        build_timestamp()
+    );
+
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    watch_git_head();
+}
+
+/// `GIT_COMMIT` wins, so builds without a `.git` directory (e.g. docker) can still report it.
+fn git_commit() -> String {
+    if let Ok(commit) = std::env::var("GIT_COMMIT") {
+        if !commit.trim().is_empty() {
+            return commit.trim().to_string();
+        }
+    }
+
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// Seconds since the unix epoch. `SOURCE_DATE_EPOCH` is used for reproducible builds.
+fn build_timestamp() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0)
+        })
+}
+
+/// Run again when a commit is checked out or made on the current branch.
+fn watch_git_head() {
+    let head = Path::new(".git/HEAD");
+    if !head.exists() {
+        return;
+    }
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+
+    let Ok(content) = std::fs::read_to_string(head) else {
+        return;
+    };
+
+    if let Some(reference) = content.trim().strip_prefix("ref: ") {
+        let reference = Path::new(".git").join(reference);
+        if reference.exists() {
+            println!("cargo:rerun-if-changed={}", reference.display());
+        }
+    }
+}
@@ -1,22 +1,411 @@
-use clap::Parser;
-use std::net::SocketAddr;
+use crate::server::{
+    connection_manager::SlowClientPolicy, io_chunks::IoChunkMode, shutdown::DownloadShutdownPolicy,
+};
+use clap::{Args, Parser, Subcommand};
+use std::{collections::HashSet, net::SocketAddr, path::PathBuf};
+
+/// Api tokens shorter than this are accepted, but warned about on startup.
+pub const MIN_RECOMMENDED_API_TOKEN_LEN: usize = 16;
 
 #[derive(Parser)]
-#[command(author, about, version)]
+#[command(author, about, version, subcommand_negates_reqs = true)]
 pub struct CliArgs {
+    /// Starts the server if not given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// The address to bind the server to
     #[clap(long, env = "SOCKET_ADDRESS", default_value = "127.0.0.1:3000")]
     pub socket_address: SocketAddr,
 
+    /// Serve `/health`, `/ready` and `/api/metrics` without authentication on this address as well
+    #[clap(long, env = "ADMIN_ADDR")]
+    pub admin_addr: Option<SocketAddr>,
+
+    /// PEM file with the TLS certificate chain. Serves HTTPS instead of HTTP, together with `--tls-key`
+    #[clap(long, env = "TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of `--tls-cert`
+    #[clap(long, env = "TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
     /// The public domains to use for the API
     #[clap(long, env = "SERVER_URLS", value_delimiter = ',')]
     pub server_urls: Vec<String>,
 
     /// The API token to use for authentication
-    #[clap(long, env = "API_TOKEN")]
-    pub api_token: String,
+    #[clap(long, env = "API_TOKEN", required_unless_present_any = ["api_tokens", "api_token_file"], value_parser = parse_api_token)]
+    pub api_token: Option<String>,
+
+    /// File to read the API token from, so it does not show up in process listings. Takes precedence over `--api-token`
+    #[clap(long, env = "API_TOKEN_FILE")]
+    pub api_token_file: Option<PathBuf>,
+
+    /// API tokens to use for authentication. Every one of them is accepted, which allows rotating tokens without downtime
+    #[clap(long, env = "API_TOKENS", value_delimiter = ',', value_parser = parse_api_token)]
+    pub api_tokens: Vec<String>,
+
+    /// Token of the admin routes, like evicting completed tasks, stats and metrics. The api tokens are not accepted there then.
+    /// Without it, the api tokens are accepted on the admin routes too
+    #[clap(long, env = "ADMIN_TOKEN", value_parser = parse_api_token)]
+    pub admin_token: Option<String>,
+
+    /// Directory with the static files served outside of `/api`, e.g. `index.html`. Defaults to the `assets` directory of the source tree
+    #[clap(long, env = "ASSETS_DIR")]
+    pub assets_dir: Option<PathBuf>,
+
+    /// Do not serve static files at all. Everything outside of the api is a JSON 404
+    #[clap(long, env = "NO_STATIC", conflicts_with = "assets_dir")]
+    pub no_static: bool,
 
     /// The directory where the projects are located
-    #[clap(long, env = "PROJECTS_DIR", default_value = "projects")]
+    #[clap(long, env = "PROJECTS_DIR", default_value = "projects", value_parser = parse_projects_dir)]
     pub projects_dir: String,
+
+    /// Origins browser clients may call the api from, e.g. `https://app.example.com`. Every origin is allowed if empty
+    #[clap(long, env = "CORS_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub cors_allowed_origins: Vec<String>,
+
+    /// The labels allowed as a task's `metric_label`
+    #[clap(long, env = "METRIC_LABEL_ALLOWLIST", value_delimiter = ',')]
+    pub metric_label_allowlist: Vec<String>,
+
+    /// The hosts a task's `callback_url` may point to. Without any, callback urls are rejected
+    #[clap(long, env = "CALLBACK_HOST_ALLOWLIST", value_delimiter = ',')]
+    pub callback_host_allowlist: Vec<String>,
+
+    /// What to do with WebSocket clients that can not keep up with the broadcast
+    #[clap(long, env = "WS_SLOW_CLIENT_POLICY", value_enum, default_value_t = SlowClientPolicy::Drop)]
+    pub ws_slow_client_policy: SlowClientPolicy,
+
+    /// Seconds a client has to finish sending the headers of a request, e.g. the WebSocket upgrade, before it is disconnected
+    #[clap(long, env = "HEADER_READ_TIMEOUT_SECS", default_value_t = 10)]
+    pub header_read_timeout_secs: u64,
+
+    /// Seconds a WebSocket client has to answer the first ping, or send a message, before it is disconnected
+    #[clap(long, env = "WS_HANDSHAKE_TIMEOUT_SECS", default_value_t = 10)]
+    pub ws_handshake_timeout_secs: u64,
+
+    /// What running downloads do when the server shuts down
+    #[clap(long, env = "DOWNLOAD_SHUTDOWN_POLICY", value_enum, default_value_t = DownloadShutdownPolicy::Abort)]
+    pub download_shutdown_policy: DownloadShutdownPolicy,
+
+    /// Seconds a running download may take to finish after shutdown, if the policy is `finish`
+    #[clap(long, env = "DOWNLOAD_SHUTDOWN_GRACE_SECS", default_value_t = 30)]
+    pub download_shutdown_grace_secs: u64,
+
+    /// Seconds requests in progress may take to finish after shutdown before they are abandoned
+    #[clap(long, env = "SHUTDOWN_TIMEOUT_SECS", default_value_t = 30)]
+    pub shutdown_timeout_secs: u64,
+
+    /// Seconds a canceled task may take to exit after the terminate signal before it is killed. `0` kills right away
+    #[clap(long, env = "CANCEL_GRACE_SECS", default_value_t = 10)]
+    pub cancel_grace_secs: u64,
+
+    /// CPU seconds the OS process of a task may use before it is killed. Only applied on Linux
+    #[clap(long, env = "TASK_CPU_QUOTA")]
+    pub task_cpu_quota: Option<u64>,
+
+    /// Bytes of memory the OS process of a task may map. Allocations beyond it fail. Only applied on Linux
+    #[clap(long, env = "TASK_MEMORY_LIMIT")]
+    pub task_memory_limit: Option<u64>,
+
+    /// How often a failed download is retried. Only network and server errors are retried
+    #[clap(long, env = "DOWNLOAD_MAX_RETRIES", default_value_t = 3)]
+    pub download_max_retries: u32,
+
+    /// Milliseconds to wait before the first retry of a download. Doubled for every further retry
+    #[clap(long, env = "DOWNLOAD_RETRY_BACKOFF_MS", default_value_t = 500)]
+    pub download_retry_backoff_ms: u64,
+
+    /// Requests to the api with a larger body in bytes are rejected with `413 Payload Too Large`
+    #[clap(long, env = "MAX_REQUEST_BODY_BYTES", default_value_t = 2 * 1024 * 1024)]
+    pub max_request_body_bytes: usize,
+
+    /// How many api requests and WebSocket connections are handled at once. Further requests are rejected with `503 Service Unavailable`.
+    /// `0` disables the limit
+    #[clap(long, env = "MAX_CONNECTIONS", default_value_t = 1024)]
+    pub max_connections: usize,
+
+    /// Api requests whose handler takes longer are answered with `504 Gateway Timeout`. `0` disables the timeout.
+    /// The WebSocket, task streams, project downloads and long-polling the status are not timed
+    #[clap(long, env = "REQUEST_TIMEOUT_SECS", default_value_t = 30)]
+    pub request_timeout_secs: u64,
+
+    /// How many tasks a chat may start per minute. `0` disables the limit
+    #[clap(long, env = "TASK_RATE_PER_MIN", default_value_t = 0)]
+    pub task_rate_per_min: u32,
+
+    /// How many tasks run at once. Further tasks are queued and started by their `priority`. `0` disables the limit
+    #[clap(long, env = "MAX_CONCURRENT_TASKS", default_value_t = 0)]
+    pub max_concurrent_tasks: usize,
+
+    /// How many of the most recent IO chunks of a task are kept for clients that connect late
+    #[clap(long, env = "TASK_OUTPUT_BUFFER_CHUNKS", default_value_t = 256)]
+    pub task_output_buffer_chunks: usize,
+
+    /// How many bytes of the most recent IO of a task are kept for clients that connect late. The oldest output is dropped first
+    #[clap(long, env = "MAX_TASK_OUTPUT_BYTES", default_value_t = 1024 * 1024)]
+    pub max_task_output_bytes: usize,
+
+    /// How the IO of a task is split into chunks sent to WebSocket clients
+    #[clap(long, env = "IO_CHUNK_MODE", value_enum, default_value_t = IoChunkMode::Raw)]
+    pub io_chunk_mode: IoChunkMode,
+
+    /// Size in bytes of a single read from a task's stdout or stderr
+    #[clap(long, env = "IO_BUFFER_BYTES", default_value_t = 8192)]
+    pub io_buffer_bytes: usize,
+
+    /// Write the stdout and stderr of tasks to `task_<boot id>_<id>/stdout.log`, `task_<boot id>_<id>/stderr.log` and the interleaved `task_<boot id>_<id>/combined.log` in the projects directory
+    #[clap(long, env = "PERSIST_TASK_OUTPUT")]
+    pub persist_task_output: bool,
+
+    /// Gzip the persisted task output, as `stdout.log.gz`, `stderr.log.gz` and `combined.log.gz`
+    #[clap(long, env = "COMPRESS_TASK_OUTPUT", requires = "persist_task_output")]
+    pub compress_task_output: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a single command like a task of the server and exit with its exit code, without starting the server
+    Run(RunArgs),
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Seconds the command may run before it is killed
+    #[clap(long, default_value_t = 600)]
+    pub timeout_secs: u64,
+
+    /// The command to run
+    pub command: String,
+
+    /// Arguments of the command. Put them after `--` if they start with `-`
+    #[clap(trailing_var_arg = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiTokenFileError {
+    #[error("Failed to read api token file: {0}")]
+    Read(#[source] std::io::Error),
+    #[error("The api token file is empty")]
+    Empty,
+}
+
+impl CliArgs {
+    /// Replace `--api-token` with the token in `--api-token-file`, if given.
+    ///
+    /// Surrounding whitespace, e.g. the trailing newline of the file, is trimmed.
+    pub fn read_api_token_file(&mut self) -> Result<(), ApiTokenFileError> {
+        let Some(path) = &self.api_token_file else {
+            return Ok(());
+        };
+
+        let token = std::fs::read_to_string(path).map_err(ApiTokenFileError::Read)?;
+        let token = token.trim();
+
+        if token.is_empty() {
+            return Err(ApiTokenFileError::Empty);
+        }
+
+        self.api_token = Some(token.to_string());
+
+        Ok(())
+    }
+
+    /// `--assets-dir`, or the `assets` directory next to `Cargo.toml` at compile time. `None` with `--no-static`.
+    pub fn assets_dir(&self) -> Option<PathBuf> {
+        if self.no_static {
+            return None;
+        }
+
+        Some(
+            self.assets_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets")),
+        )
+    }
+
+    /// `--api-token` and `--api-tokens` combined.
+    pub fn api_tokens(&self) -> HashSet<String> {
+        self.api_token
+            .iter()
+            .chain(self.api_tokens.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// How many of the api tokens are shorter than [`MIN_RECOMMENDED_API_TOKEN_LEN`].
+    pub fn short_api_tokens(&self) -> usize {
+        self.api_tokens()
+            .iter()
+            .filter(|token| token.len() < MIN_RECOMMENDED_API_TOKEN_LEN)
+            .count()
+    }
+}
+
+/// An empty token would let every request with an empty `api_key` through.
+fn parse_api_token(token: &str) -> Result<String, String> {
+    if token.trim().is_empty() {
+        return Err(String::from(
+            "The api token must not be empty or whitespace",
+        ));
+    }
+
+    Ok(token.to_string())
+}
+
+fn parse_projects_dir(projects_dir: &str) -> Result<String, String> {
+    if projects_dir.trim().is_empty() {
+        return Err(String::from("The projects directory must not be empty"));
+    }
+
+    Ok(projects_dir.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::state::{ApiState, ApiStateConfig};
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[test]
+    fn empty_api_tokens_are_rejected() {
+        assert!(CliArgs::try_parse_from(["job_hub", "--api-token", ""]).is_err());
+        assert!(CliArgs::try_parse_from(["job_hub", "--api-token", "   "]).is_err());
+        assert!(
+            CliArgs::try_parse_from(["job_hub", "--api-tokens", "a-long-enough-token,"]).is_err()
+        );
+        assert!(CliArgs::try_parse_from([
+            "job_hub",
+            "--api-token",
+            "token",
+            "--projects-dir",
+            " "
+        ])
+        .is_err());
+
+        let cli_args = CliArgs::try_parse_from(["job_hub", "--api-token", "a-long-enough-token"])
+            .expect("Failed to parse args");
+        assert_eq!(cli_args.short_api_tokens(), 0);
+    }
+
+    #[test]
+    fn api_token_is_read_from_file() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let token_file = dir.path().join("api_token");
+        std::fs::write(&token_file, "token-from-the-file\n").unwrap();
+
+        let mut cli_args = CliArgs::try_parse_from([
+            "job_hub",
+            "--api-token",
+            "token-from-the-command-line",
+            "--api-token-file",
+            token_file.to_str().unwrap(),
+        ])
+        .expect("Failed to parse args");
+        cli_args
+            .read_api_token_file()
+            .expect("Failed to read api token file");
+
+        let api_state = ApiState::new(
+            cli_args.api_tokens(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        assert!(api_state.api_token_valid("token-from-the-file"));
+        assert!(!api_state.api_token_valid("token-from-the-command-line"));
+
+        std::fs::write(&token_file, " \n").unwrap();
+        assert!(matches!(
+            cli_args.read_api_token_file(),
+            Err(ApiTokenFileError::Empty)
+        ));
+    }
+
+    #[test]
+    fn short_api_tokens_are_counted() {
+        let cli_args = CliArgs::try_parse_from([
+            "job_hub",
+            "--api-token",
+            "short",
+            "--api-tokens",
+            "a-long-enough-token,also-short",
+        ])
+        .expect("Failed to parse args");
+
+        assert_eq!(cli_args.short_api_tokens(), 2);
+    }
+
+    async fn root_response(cli_args: &CliArgs) -> axum::response::Response {
+        crate::routes::root(cli_args.assets_dir())
+            .with_state(ApiState::new(
+                cli_args.api_tokens(),
+                String::from("projects"),
+                ApiStateConfig::default(),
+            ))
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn custom_assets_dir_is_served() {
+        let cli_args = CliArgs::try_parse_from(["job_hub", "--api-token", "token"])
+            .expect("Failed to parse args");
+        assert_eq!(
+            cli_args.assets_dir(),
+            Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets"))
+        );
+
+        let assets_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        std::fs::write(assets_dir.path().join("index.html"), "custom index").unwrap();
+
+        let cli_args = CliArgs::try_parse_from([
+            "job_hub",
+            "--api-token",
+            "token",
+            "--assets-dir",
+            assets_dir.path().to_str().unwrap(),
+        ])
+        .expect("Failed to parse args");
+
+        let response = root_response(&cli_args).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"custom index");
+    }
+
+    #[tokio::test]
+    async fn no_static_answers_the_root_with_a_json_404() {
+        let cli_args = CliArgs::try_parse_from(["job_hub", "--api-token", "token", "--no-static"])
+            .expect("Failed to parse args");
+        assert_eq!(cli_args.assets_dir(), None);
+
+        let response = root_response(&cli_args).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("Body is not JSON");
+        assert_eq!(body["err"]["type"], "NotFound");
+
+        assert!(CliArgs::try_parse_from([
+            "job_hub",
+            "--api-token",
+            "token",
+            "--no-static",
+            "--assets-dir",
+            "assets"
+        ])
+        .is_err());
+    }
 }
@@ -1,4 +1,5 @@
 pub mod cli_args;
 pub mod openapi;
 pub mod routes;
+pub mod run;
 pub mod server;
@@ -1,37 +1,40 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{path::Path, process::ExitCode};
 
 use anyhow::Context;
-use axum::{
-    extract::{Request, State},
-    http::HeaderMap,
-    middleware::{self, Next},
-    response::IntoResponse,
-    routing::{get, post, put},
-    Router,
-};
+use axum::{middleware, routing::get};
 use clap::Parser;
 use job_hub::{
-    cli_args::CliArgs,
+    cli_args::{CliArgs, Command, RunArgs, MIN_RECOMMENDED_API_TOKEN_LEN},
     openapi::build_openapi,
-    routes,
-    server::{response::ApiError, state::ApiState},
+    routes, run,
+    server::{
+        cors::cors_layer,
+        io_chunks::IoOptions,
+        middleware::{request_id, RecordLatency},
+        prometheus,
+        serve::{rustls_config, serve},
+        state::{prepare_projects_dir, ApiState, ApiStateConfig},
+        task::{DownloadRetryPolicy, ResourceLimits},
+    },
 };
 use tower::ServiceBuilder;
 use tower_http::{
     compression::CompressionLayer,
-    cors::CorsLayer,
     decompression::RequestDecompressionLayer,
-    services::ServeDir,
     trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
 };
 use utoipa_rapidoc::RapiDoc;
 use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
-fn init_tracing() -> anyhow::Result<()> {
+fn init_tracing<W>(writer: W) -> anyhow::Result<()>
+where
+    W: for<'writer> tracing_subscriber::fmt::MakeWriter<'writer> + Send + Sync + 'static,
+{
     tracing::subscriber::set_global_default(
         tracing_subscriber::fmt::Subscriber::builder()
             .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+            .with_writer(writer)
             .finish(),
     )
     .context("Failed to set global tracing subscriber")?;
@@ -40,59 +43,92 @@ fn init_tracing() -> anyhow::Result<()> {
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     dotenv::dotenv().ok();
 
+    let mut cli_args = CliArgs::parse();
+
+    if let Some(Command::Run(run_args)) = cli_args.command.take() {
+        return run_command(run_args, cli_args.cancel_grace_secs).await;
+    }
+
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "job_hub=trace,tower_http=trace");
     }
 
-    init_tracing()?;
-
-    let cli_args = CliArgs::parse();
-
-    let state = ApiState::new(cli_args.api_token, cli_args.projects_dir);
-
-    let api = Router::new()
-        .route(
-            "/request_chat_id",
-            get(routes::request_chat_id::request_chat_id),
-        )
-        .route("/cancel/:id", put(routes::cancel::cancel))
-        .route("/status/:id", get(routes::status::status))
-        .route("/list_log_files", get(routes::log_files::list_log_files))
-        .route(
-            "/download_zip_file",
-            post(routes::download_zip_file::download_zip_file),
-        )
-        .route(
-            "/get_log_file_text",
-            get(routes::log_files::get_log_file_text),
-        )
-        .route(
-            "/gs_log_to_locust_converter",
-            post(routes::gs_log_to_locust_converter::gs_log_to_locust_converter),
-        )
-        .layer(middleware::from_fn_with_state(
-            state.clone(),
-            validate_bearer_token,
-        ));
-
-    let assets_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
+    init_tracing(std::io::stdout)?;
+    prometheus::init();
+
+    cli_args.read_api_token_file()?;
+
+    let short_api_tokens = cli_args.short_api_tokens();
+    if short_api_tokens > 0 {
+        tracing::warn!(
+            short_api_tokens,
+            min_len = MIN_RECOMMENDED_API_TOKEN_LEN,
+            "Api tokens are short and easy to guess. Use longer tokens"
+        );
+    }
+
+    let api_tokens = cli_args.api_tokens();
+    let cors = cors_layer(&cli_args.cors_allowed_origins).context("Invalid CORS origin")?;
+    let assets_dir = cli_args.assets_dir();
+
+    let config = ApiStateConfig {
+        metric_label_allowlist: cli_args.metric_label_allowlist.into_iter().collect(),
+        callback_host_allowlist: cli_args.callback_host_allowlist.into_iter().collect(),
+        ws_slow_client_policy: cli_args.ws_slow_client_policy,
+        ws_handshake_timeout: std::time::Duration::from_secs(cli_args.ws_handshake_timeout_secs),
+        download_shutdown_policy: cli_args.download_shutdown_policy,
+        download_shutdown_grace: std::time::Duration::from_secs(
+            cli_args.download_shutdown_grace_secs,
+        ),
+        cancel_grace: std::time::Duration::from_secs(cli_args.cancel_grace_secs),
+        task_resource_limits: ResourceLimits {
+            cpu_secs: cli_args.task_cpu_quota,
+            memory_bytes: cli_args.task_memory_limit,
+        },
+        download_retry_policy: DownloadRetryPolicy {
+            max_retries: cli_args.download_max_retries,
+            backoff: std::time::Duration::from_millis(cli_args.download_retry_backoff_ms),
+        },
+        max_request_body_bytes: cli_args.max_request_body_bytes,
+        request_timeout: std::time::Duration::from_secs(cli_args.request_timeout_secs),
+        max_connections: cli_args.max_connections,
+        task_rate_per_min: cli_args.task_rate_per_min,
+        max_concurrent_tasks: cli_args.max_concurrent_tasks,
+        task_output_buffer_chunks: cli_args.task_output_buffer_chunks,
+        max_task_output_bytes: cli_args.max_task_output_bytes,
+        io_options: IoOptions {
+            chunk_mode: cli_args.io_chunk_mode,
+            buffer_bytes: cli_args.io_buffer_bytes,
+        },
+        persist_task_output: cli_args.persist_task_output,
+        compress_task_output: cli_args.compress_task_output,
+        admin_token: cli_args.admin_token,
+    };
+
+    prepare_projects_dir(Path::new(&cli_args.projects_dir))
+        .context("Projects directory unusable")?;
+
+    let state = ApiState::new(api_tokens, cli_args.projects_dir, config);
+
+    let api = routes::api(state.clone());
 
     let server_urls = cli_args.server_urls;
     let openapi = build_openapi(server_urls);
 
-    let app = Router::new()
-        .fallback_service(ServeDir::new(assets_dir).append_index_html_on_directories(true))
+    let app = routes::root(assets_dir)
         .nest("/api", api)
-        .route("/health", get(|| async { "ok" }))
-        .with_state(state)
+        .merge(routes::prometheus(state.clone()))
+        .route("/health", get(routes::health::health))
+        .with_state(state.clone())
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", openapi.clone()))
         .merge(Redoc::with_url("/redoc", openapi))
         .merge(RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"))
         .layer(
             ServiceBuilder::new()
+                .layer(middleware::from_fn(request_id))
                 .layer(
                     TraceLayer::new_for_http()
                         .make_span_with(
@@ -101,58 +137,107 @@ async fn main() -> anyhow::Result<()> {
                                 .include_headers(true),
                         )
                         .on_request(DefaultOnRequest::new().level(tracing::Level::INFO))
-                        .on_response(DefaultOnResponse::new().level(tracing::Level::INFO)),
+                        .on_response(RecordLatency::new(
+                            DefaultOnResponse::new().level(tracing::Level::INFO),
+                        )),
                 )
                 .layer(RequestDecompressionLayer::new())
                 .layer(CompressionLayer::new())
-                .layer(CorsLayer::permissive()),
+                .layer(cors),
         );
 
+    let tls = match (&cli_args.tls_cert, &cli_args.tls_key) {
+        (Some(cert), Some(key)) => Some(
+            rustls_config(cert, key)
+                .await
+                .context("Failed to load TLS certificate")?,
+        ),
+        _ => None,
+    };
+
+    let shutdown_timeout = std::time::Duration::from_secs(cli_args.shutdown_timeout_secs);
+    let header_read_timeout = std::time::Duration::from_secs(cli_args.header_read_timeout_secs);
+
+    if let Some(admin_addr) = cli_args.admin_addr {
+        tracing::info!(%admin_addr, "Starting admin server");
+
+        let listener = tokio::net::TcpListener::bind(&admin_addr)
+            .await
+            .context("Admin bind failed")?;
+
+        let admin = routes::admin().with_state(state.clone());
+
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                let shutdown = async move { state.shutdown_started().await };
+
+                if let Err(err) = serve(
+                    listener,
+                    admin,
+                    None,
+                    shutdown,
+                    shutdown_timeout,
+                    header_read_timeout,
+                )
+                .await
+                {
+                    tracing::error!(?err, "Admin server failed");
+                }
+            }
+        });
+    }
+
     let addr = cli_args.socket_address;
 
-    tracing::info!(%addr, "Starting server");
+    tracing::info!(%addr, tls = tls.is_some(), "Starting server");
 
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
         .context("Bind failed")?;
 
-    axum::serve(
+    serve(
         listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
+        app,
+        tls,
+        {
+            let state = state.clone();
+            async move {
+                shutdown_signal().await;
+                state.shutdown();
+            }
+        },
+        shutdown_timeout,
+        header_read_timeout,
     )
-    .with_graceful_shutdown(shutdown_signal())
     .await
     .context("Server failed")?;
 
-    Ok(())
+    state.drain_tasks().await;
+
+    Ok(ExitCode::SUCCESS)
 }
 
-async fn validate_bearer_token(
-    State(state): State<ApiState>,
-    headers: HeaderMap,
-    request: Request,
-    next: Next,
-) -> Result<impl IntoResponse, ApiError> {
-    let api_key = headers
-        .get("api_key")
-        .ok_or_else(|| {
-            tracing::warn!("api_key header not present");
-            ApiError::ApiKeyMissing
-        })?
-        .to_str()
-        .map_err(|_| {
-            tracing::warn!("Failed to convert api_key header into str");
-            ApiError::ApiKeyMissing
-        })?;
-
-    if !state.api_token_valid(api_key) {
-        tracing::warn!(%api_key, "Invalid api_key");
-        return Err(ApiError::ApiKeyInvalid);
+/// `job_hub run`: the output of the command goes to stdout and stderr, so the logs only go to stderr.
+async fn run_command(run_args: RunArgs, cancel_grace_secs: u64) -> anyhow::Result<ExitCode> {
+    if std::env::var_os("RUST_LOG").is_none() {
+        std::env::set_var("RUST_LOG", "job_hub=warn");
     }
 
-    let res = next.run(request).await;
+    init_tracing(std::io::stderr)?;
+
+    let status = run::run_task(
+        run_args.command,
+        run_args.args,
+        std::time::Duration::from_secs(run_args.timeout_secs),
+        std::time::Duration::from_secs(cancel_grace_secs),
+        shutdown_signal(),
+    )
+    .await;
+
+    tracing::debug!(?status, "Command finished");
 
-    Ok(res)
+    Ok(ExitCode::from(run::exit_code(&status)))
 }
 
 async fn shutdown_signal() {
@@ -4,7 +4,7 @@
 //!
 use utoipa::{
     openapi::{
-        security::{ApiKey, ApiKeyValue, SecurityScheme},
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
         OpenApi as OpenApiDoc, OpenApiBuilder, Server,
     },
     OpenApi,
@@ -15,31 +15,78 @@ use utoipa::{
 #[openapi(
     paths(
         crate::routes::gs_log_to_locust_converter::gs_log_to_locust_converter,
+        crate::routes::converters::list_converters,
         crate::routes::cancel::cancel,
+        crate::routes::resubmit::resubmit,
         crate::routes::status::status,
+        crate::routes::status::status_bulk,
+        crate::routes::task_output::task_output,
+        crate::routes::task_stream::task_stream,
+        crate::routes::task_events::task_events,
+        crate::routes::tasks::list_tasks,
+        crate::routes::tasks::delete_task,
         crate::routes::request_chat_id::request_chat_id,
         crate::routes::download_zip_file::download_zip_file,
         crate::routes::log_files::list_log_files,
         crate::routes::log_files::get_log_file_text,
+        crate::routes::log_files::head_log_file,
+        crate::routes::log_files::tail,
+        crate::routes::metrics::metrics,
+        crate::routes::prometheus::prometheus_metrics,
+        crate::routes::stats::stats,
+        crate::routes::version::version,
+        crate::routes::healthz::healthz,
+        crate::routes::evict_completed::evict_completed,
+        crate::routes::project::delete_project,
+        crate::routes::project::download_project,
+        crate::routes::project::download_project_files,
+        crate::routes::ws::ws,
     ),
     components(schemas(
         crate::server::task::Status,
         crate::server::task::DownloadZipFileStatus,
         crate::server::task::ProcessStatus,
-        crate::server::task::FailOperation,
         crate::server::task::ExitedStatus,
+        crate::server::task::FailureKind,
         crate::routes::gs_log_to_locust_converter::GsLogToLocustConverterOkResponse,
         crate::routes::gs_log_to_locust_converter::GsLogToLocustConverterErrorResponse,
+        crate::routes::converters::ListConvertersResponse,
+        crate::routes::converters::ConverterInfo,
         crate::routes::cancel::CancelOkReponse,
         crate::routes::cancel::CancelErrorReponse,
+        crate::routes::resubmit::ResubmitOkResponse,
+        crate::routes::resubmit::ResubmitErrorResponse,
         crate::routes::status::StatusOkReponse,
-        crate::routes::status::StatusErrorReponse,
+        crate::routes::status::StatusBulkBody,
+        crate::routes::status::StatusBulkOkResponse,
+        crate::routes::tasks::ListTasksResponse,
+        crate::routes::tasks::DeleteTaskOkResponse,
+        crate::routes::tasks::DeleteTaskErrorResponse,
+        crate::server::state::TaskSummary,
         crate::routes::request_chat_id::RequestChatIdReponse,
         crate::routes::download_zip_file::DownloadZipFileOkReponse,
         crate::routes::download_zip_file::DownloadZipFileErrorReponse,
+        crate::routes::download_zip_file::DownloadZipFileDryRunResponse,
         crate::routes::log_files::ListLogfilesOkResponse,
         crate::routes::log_files::ListLogfilesErrorResponse,
+        crate::server::state::FileSort,
+        crate::server::state::FileInfo,
+        crate::routes::log_files::ListedFiles,
         crate::routes::log_files::GetLogFileErrorResponse,
+        crate::routes::log_files::TailOkResponse,
+        crate::routes::log_files::TailErrorResponse,
+        crate::routes::metrics::MetricsResponse,
+        crate::routes::stats::StatsResponse,
+        crate::routes::version::VersionResponse,
+        crate::routes::healthz::HealthzResponse,
+        crate::routes::evict_completed::EvictCompletedResponse,
+        crate::server::metrics::TaskMetric,
+        crate::server::metrics::TaskKind,
+        crate::routes::project::DeleteProjectOkResponse,
+        crate::routes::project::DeleteProjectErrorResponse,
+        crate::routes::project::DownloadProjectErrorResponse,
+        crate::routes::project::DownloadProjectFilesBody,
+        crate::routes::project::DownloadProjectFilesManifest,
     ))
 )]
 struct ApiDoc;
@@ -52,6 +99,18 @@ pub fn build_openapi(server_urls: Vec<String>) -> OpenApiDoc {
             "api_key",
             SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("api_key"))),
         );
+        components.add_security_scheme(
+            "bearer_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        // The chat id is read from the query, like the `chat_id` parameter of the paths.
+        components.add_security_scheme(
+            "chat_id",
+            SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::with_description(
+                "chat_id",
+                "Chat id. generated using the `/api/request_chat_id` endpoint.",
+            ))),
+        );
         components
     });
 
@@ -63,3 +122,74 @@ pub fn build_openapi(server_urls: Vec<String>) -> OpenApiDoc {
         ))
         .build()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn security_schemes_are_registered() {
+        let openapi = build_openapi(vec![String::from("http://localhost:3000")]);
+        let spec: serde_json::Value =
+            serde_json::from_str(&openapi.to_json().expect("Failed to serialize spec"))
+                .expect("Spec is not JSON");
+
+        let schemes = &spec["components"]["securitySchemes"];
+
+        assert_eq!(schemes["api_key"]["type"], "apiKey");
+        assert_eq!(schemes["api_key"]["in"], "header");
+        assert_eq!(schemes["bearer_token"]["scheme"], "bearer");
+        assert_eq!(schemes["chat_id"]["type"], "apiKey");
+        assert_eq!(schemes["chat_id"]["in"], "query");
+        assert_eq!(schemes["chat_id"]["name"], "chat_id");
+    }
+
+    #[test]
+    fn key_endpoints_have_examples() {
+        let openapi = build_openapi(Vec::new());
+        let spec: serde_json::Value =
+            serde_json::from_str(&openapi.to_json().expect("Failed to serialize spec"))
+                .expect("Spec is not JSON");
+
+        let paths = &spec["paths"];
+
+        for (path, method, status) in [
+            ("/api/download_zip_file", "post", "201"),
+            ("/api/download_zip_file", "post", "400"),
+            ("/api/gs_log_to_locust_converter", "post", "201"),
+            ("/api/gs_log_to_locust_converter", "post", "400"),
+            ("/api/list_log_files", "get", "200"),
+            ("/api/get_log_file_text", "get", "200"),
+            ("/api/tail", "get", "200"),
+            ("/api/cancel/{id}", "put", "200"),
+            ("/api/status/{id}", "get", "200"),
+        ] {
+            let content = &paths[path][method]["responses"][status]["content"];
+            let media_type = content
+                .as_object()
+                .and_then(|content| content.values().next())
+                .unwrap_or_else(|| panic!("{method} {path} {status} has no content"));
+
+            assert!(
+                !media_type["example"].is_null() || !media_type["examples"].is_null(),
+                "{method} {path} {status} has no example"
+            );
+        }
+
+        for (path, method) in [
+            ("/api/download_zip_file", "post"),
+            ("/api/gs_log_to_locust_converter", "post"),
+            ("/api/list_log_files", "get"),
+        ] {
+            let parameters = paths[path][method]["parameters"]
+                .as_array()
+                .unwrap_or_else(|| panic!("{method} {path} has no parameters"));
+            let project_name = parameters
+                .iter()
+                .find(|parameter| parameter["name"] == "project_name")
+                .unwrap_or_else(|| panic!("{method} {path} has no project_name"));
+
+            assert_eq!(project_name["example"], "my-project", "{method} {path}");
+        }
+    }
+}
@@ -1,18 +1,32 @@
-use crate::server::{extractors::chat_id::ChatId, state::ApiState};
+use crate::server::{
+    extractors::{chat_id::ChatId, query::Query},
+    state::ApiState,
+    task::Status,
+};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+#[derive(Deserialize)]
+pub struct CancelQuery {
+    /// Wait for the task to terminate
+    #[serde(default)]
+    wait: bool,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct CancelOkReponse {
     /// Task id that was scheduled for cancellation
     #[schema(example = "0")]
     id: String,
+    /// Status of the task after waiting for it. Only set with `wait`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<Status>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -33,33 +47,115 @@ impl IntoResponse for CancelErrorReponse {
 }
 
 /// Schedule a task for cancellation
+///
+/// With `wait`, the response is held until the task terminated, for up to the cancel grace of the server plus the time to kill the task, and carries its status.
 #[utoipa::path(
     put,
     path = "/api/cancel/{id}", 
     params(
-        ("id" = String, Path, description = "Task id. generated using the `/api/download_zip_file` endpoint."),
-        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+        ("id" = String, Path, description = "Task id. generated using the `/api/download_zip_file` endpoint.", example = "0"),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint."),
+        ("wait" = Option<bool>, Query, description = "Wait for the task to terminate, up to the cancel grace of the server plus the time to kill it, and return its status. Defaults to false."),
     ),
     tag = "task",
     responses(
-        (status = 200, description = "Task was scheduled for cancellation", body = CancelOkReponse, example = json!(CancelOkReponse{id: String::from("some-id")})),
+        (status = 200, description = "Task was scheduled for cancellation", body = CancelOkReponse, example = json!(CancelOkReponse{id: String::from("some-id"), status: None})),
         (status = 404, description = "Task not found for this chat id", body = CancelErrorReponse, example = json!(CancelErrorReponse::NotFound)),
         (status = 400, description = "Chat id missing. Api key missing"),
         (status = 401, description = "Api key invalid"),
     ),
     security(
         ("api_key" = []),
+        ("bearer_token" = []),
     ),
 )]
 pub async fn cancel(
     State(state): State<ApiState>,
     Path(id): Path<String>,
     ChatId(chat_id): ChatId,
+    Query(query): Query<CancelQuery>,
 ) -> Result<CancelOkReponse, CancelErrorReponse> {
+    if query.wait {
+        let status = state
+            .cancel_task_and_wait(&id, &chat_id, state.cancel_wait())
+            .await
+            .ok_or(CancelErrorReponse::NotFound)?;
+
+        return Ok(CancelOkReponse {
+            id,
+            status: Some(status),
+        });
+    }
+
     let _ = state
         .cancel_task(&id, &chat_id)
         .await
         .ok_or(CancelErrorReponse::NotFound)?;
 
-    Ok(CancelOkReponse { id })
+    Ok(CancelOkReponse { id, status: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{state::ApiStateConfig, task::DownloadZipFileStatus};
+    use axum::{body::Body, http::Request, routing::put, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn cancel_with_wait_returns_the_terminal_status() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        // Accepts the download request and never answers, so the task keeps running.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+
+        let download_url = url::Url::parse(&format!("http://{addr}/file.zip")).unwrap();
+        let id = state
+            .run_download_task(
+                String::from("chat"),
+                download_url,
+                String::from("project"),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let app = Router::new()
+            .route("/cancel/:id", put(cancel))
+            .with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::put(format!("/cancel/{id}?chat_id=chat&wait=true"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(
+            body["status"],
+            serde_json::to_value(Status::Download(DownloadZipFileStatus::Canceled)).unwrap()
+        );
+    }
 }
@@ -0,0 +1,60 @@
+use crate::server::converter::{converters, DEFAULT_TARGET};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct ConverterInfo {
+    /// Value of the `target` query parameter of `/api/gs_log_to_locust_converter`
+    #[schema(example = "locust")]
+    target: &'static str,
+    #[schema(example = "Converts GS log files to the Locust log format")]
+    description: &'static str,
+    /// Whether this converter is used when no `target` is given
+    default: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListConvertersResponse {
+    converters: Vec<ConverterInfo>,
+}
+
+impl IntoResponse for ListConvertersResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+/// List the conversion targets
+///
+/// Every target accepted by `/api/gs_log_to_locust_converter`.
+#[utoipa::path(
+    get,
+    path = "/api/converters",
+    tag = "task",
+    responses(
+        (status = 200, description = "Available converters", body = ListConvertersResponse),
+        (status = 400, description = "Api key missing"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn list_converters() -> ListConvertersResponse {
+    let converters = converters()
+        .iter()
+        .map(|converter| ConverterInfo {
+            target: converter.target(),
+            description: converter.description(),
+            default: converter.target() == DEFAULT_TARGET,
+        })
+        .collect();
+
+    ListConvertersResponse { converters }
+}
@@ -1,12 +1,13 @@
 use crate::server::{
-    extractors::{chat_id::ChatId, query::Query},
+    extractors::{chat_id::ChatId, idempotency_key::IdempotencyKey, query::Query},
+    labels::parse_labels,
     response::ApiError,
-    state::ApiState,
-    utils::GoogleConvertLinkError,
+    state::{ApiState, DownloadProbe, RunDownloadTaskError, TaskOptions},
+    utils::{retry_after_secs, GoogleConvertLinkError},
 };
 use axum::{
     extract::State,
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -25,9 +26,40 @@ pub struct DownloadZipFileOkReponse {
 pub enum DownloadZipFileErrorReponse {
     InvalidUrl,
     Convert(GoogleConvertLinkError),
+    MetricLabelNotAllowed,
+    CallbackUrlNotAllowed,
+    InvalidProjectName,
+    InvalidSha256,
+    InvalidLabels(String),
+    RateLimited { retry_after_secs: u64 },
     ServerError(ApiError),
 }
 
+impl From<RunDownloadTaskError> for DownloadZipFileErrorReponse {
+    fn from(err: RunDownloadTaskError) -> Self {
+        match err {
+            RunDownloadTaskError::MetricLabelNotAllowed => {
+                DownloadZipFileErrorReponse::MetricLabelNotAllowed
+            }
+            RunDownloadTaskError::CallbackUrlNotAllowed => {
+                DownloadZipFileErrorReponse::CallbackUrlNotAllowed
+            }
+            RunDownloadTaskError::InvalidProjectName => {
+                DownloadZipFileErrorReponse::InvalidProjectName
+            }
+            RunDownloadTaskError::InvalidSha256 => DownloadZipFileErrorReponse::InvalidSha256,
+            RunDownloadTaskError::RateLimited { retry_after } => {
+                DownloadZipFileErrorReponse::RateLimited {
+                    retry_after_secs: retry_after_secs(retry_after),
+                }
+            }
+            RunDownloadTaskError::IoError(err) => {
+                DownloadZipFileErrorReponse::ServerError(err.into())
+            }
+        }
+    }
+}
+
 impl IntoResponse for DownloadZipFileOkReponse {
     fn into_response(self) -> Response {
         (StatusCode::CREATED, Json(self)).into_response()
@@ -43,6 +75,27 @@ impl IntoResponse for DownloadZipFileErrorReponse {
             DownloadZipFileErrorReponse::Convert(_) => {
                 (StatusCode::BAD_REQUEST, Json(self)).into_response()
             }
+            DownloadZipFileErrorReponse::MetricLabelNotAllowed => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            DownloadZipFileErrorReponse::CallbackUrlNotAllowed => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            DownloadZipFileErrorReponse::InvalidProjectName => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            DownloadZipFileErrorReponse::InvalidSha256 => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            DownloadZipFileErrorReponse::InvalidLabels(_) => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            DownloadZipFileErrorReponse::RateLimited { retry_after_secs } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(self),
+            )
+                .into_response(),
             DownloadZipFileErrorReponse::ServerError(err) => err.into_response(),
         }
     }
@@ -54,34 +107,87 @@ pub struct DownloadZipFileQuery {
     project_name: String,
     /// Google drive share link for the zip file
     google_drive_share_link: String,
+    /// Optional label for the task metrics
+    metric_label: Option<String>,
+    /// Comma separated `key:value` labels of the task
+    labels: Option<String>,
+    /// Url to post the final status of the task to
+    callback_url: Option<String>,
+    /// Queued tasks with a higher priority run first. Defaults to `0`
+    priority: Option<i32>,
+    /// Expected sha256 of the zip file, hex encoded
+    sha256: Option<String>,
+    /// Only check the link, without downloading or scheduling a task
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DownloadZipFileDryRunResponse {
+    /// The link answered with a success status
+    reachable: bool,
+    /// Size of the file in bytes
+    #[schema(example = 1024)]
+    content_length: Option<u64>,
+    #[schema(example = "application/zip")]
+    content_type: Option<String>,
+}
+
+impl From<DownloadProbe> for DownloadZipFileDryRunResponse {
+    fn from(probe: DownloadProbe) -> Self {
+        Self {
+            reachable: probe.reachable,
+            content_length: probe.content_length,
+            content_type: probe.content_type,
+        }
+    }
+}
+
+impl IntoResponse for DownloadZipFileDryRunResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
 }
 
 /// Schedule a download of a zip file from a Google Drive link.
 ///
 /// This endpoint will schedule a task for running. The task will be executed asynchronously.
+/// Besides zip files, `.tar.gz` archives are extracted too. The format is detected by the file signature.
+/// With `dry_run=true` the link is only checked and nothing is scheduled.
 #[utoipa::path(
     post,
     path = "/api/download_zip_file", 
     params(
         ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint."),
-        ("project_name" = String, Query, description = "Name of the project."),
-        ("google_drive_share_link" = String, Query, description = "Google drive share link for the zip file.")
+        ("project_name" = String, Query, description = "Name of the project. Only alphanumerics, `-` and `_`.", example = "my-project"),
+        ("google_drive_share_link" = String, Query, description = "Google drive share link for the zip file.", example = "https://drive.google.com/file/d/1aBcD2eFgH3iJkL4mNoP5qRsT6uVwX7yZ/view?usp=sharing"),
+        ("metric_label" = Option<String>, Query, description = "Label for the task metrics. Must be one of the labels allowed by the server."),
+        ("labels" = Option<String>, Query, description = "Comma separated `key:value` labels of the task, e.g. `env:prod,team:load`. Used to filter `/api/tasks`."),
+        ("callback_url" = Option<String>, Query, description = "Url the final `id`, `status` and `exit_code` of the task are posted to as JSON once it is done. Its host must be allowed by the server."),
+        ("priority" = Option<i32>, Query, description = "If the server runs a limited number of tasks at once, queued tasks with a higher priority run first. Defaults to `0`."),
+        ("sha256" = Option<String>, Query, description = "Expected sha256 of the zip file, hex encoded. The task fails without extracting anything if the downloaded file does not match."),
+        ("dry_run" = Option<bool>, Query, description = "Only check that the link is reachable and report the file's size and type. No project directory is created and no task is scheduled."),
+        ("Idempotency-Key" = Option<String>, Header, description = "Retrying with the same key returns the task the first request started instead of starting another one.")
     ),
     tag = "download",
     responses(
+        (status = 200, description = "Dry run result", body = DownloadZipFileDryRunResponse, example = json!(DownloadZipFileDryRunResponse{reachable: true, content_length: Some(1024), content_type: Some(String::from("application/zip"))})),
         (status = 201, description = "Task was scheduled for running", body = DownloadZipFileOkReponse, example = json!(DownloadZipFileOkReponse{id: String::from("some-id")})),
-        (status = 400, description = "Chat id missing, Api key missing"),
+        (status = 400, description = "Chat id missing, Api key missing, Metric label not allowed, Callback url not allowed, Invalid project name, Invalid sha256", body = DownloadZipFileErrorReponse, example = json!(DownloadZipFileErrorReponse::InvalidProjectName)),
+        (status = 429, description = "Too many tasks started by this chat. See the `Retry-After` header", body = DownloadZipFileErrorReponse, example = json!(DownloadZipFileErrorReponse::RateLimited{retry_after_secs: 30})),
         (status = 401, description = "Api key invalid"),
     ),
     security(
         ("api_key" = []),
+        ("bearer_token" = []),
     ),
 )]
 pub async fn download_zip_file(
     State(state): State<ApiState>,
     ChatId(chat_id): ChatId,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
     Query(query): Query<DownloadZipFileQuery>,
-) -> Result<DownloadZipFileOkReponse, DownloadZipFileErrorReponse> {
+) -> Result<Response, DownloadZipFileErrorReponse> {
     let project_name = query.project_name;
     let google_drive_share_link = query.google_drive_share_link;
 
@@ -93,10 +199,40 @@ pub async fn download_zip_file(
     )
     .map_err(DownloadZipFileErrorReponse::Convert)?;
 
+    let labels = parse_labels(query.labels.as_deref().unwrap_or_default())
+        .map_err(|err| DownloadZipFileErrorReponse::InvalidLabels(err.to_string()))?;
+
+    let callback_url = query
+        .callback_url
+        .as_deref()
+        .map(url::Url::parse)
+        .transpose()
+        .map_err(|_| DownloadZipFileErrorReponse::CallbackUrlNotAllowed)?;
+
+    if query.dry_run {
+        let probe = state.probe_download(download_url).await;
+
+        return Ok(DownloadZipFileDryRunResponse::from(probe).into_response());
+    }
+
     let id = state
-        .run_download_task(chat_id, download_url, project_name)
-        .await
-        .map_err(|err| DownloadZipFileErrorReponse::ServerError(err.into()))?;
+        .start_task_idempotent(&chat_id.clone(), idempotency_key, || {
+            state.run_download_task(
+                chat_id,
+                download_url,
+                project_name,
+                query.sha256,
+                TaskOptions {
+                    metric_label: query.metric_label,
+                    labels,
+                    callback_url,
+                    priority: query.priority.unwrap_or_default(),
+                    priority_nice: None,
+                    interactive: false,
+                },
+            )
+        })
+        .await?;
 
-    Ok(DownloadZipFileOkReponse { id })
+    Ok(DownloadZipFileOkReponse { id }.into_response())
 }
@@ -0,0 +1,35 @@
+use crate::server::state::ApiState;
+use axum::{extract::State, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct EvictCompletedResponse {
+    /// Number of finished tasks removed from memory
+    #[schema(example = 3)]
+    evicted: usize,
+}
+
+/// Remove every finished task from memory now, instead of after its retention
+///
+/// Tasks that are queued or running are kept. Projects and persisted task output are not deleted.
+#[utoipa::path(
+    post,
+    path = "/api/admin/evict_completed",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Finished tasks evicted", body = EvictCompletedResponse),
+        (status = 400, description = "Api key missing"),
+        (status = 401, description = "Api key invalid"),
+        (status = 403, description = "Admin token required. The api key is not the admin token"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn evict_completed(State(state): State<ApiState>) -> Json<EvictCompletedResponse> {
+    Json(EvictCompletedResponse {
+        evicted: state.evict_completed_tasks().await,
+    })
+}
@@ -1,10 +1,13 @@
 use crate::server::{
-    extractors::{chat_id::ChatId, query::Query},
-    state::{ApiState, GsLogToLocstConverterError},
+    extractors::{chat_id::ChatId, idempotency_key::IdempotencyKey, query::Query},
+    labels::parse_labels,
+    response::ApiError,
+    state::{ApiState, ConverterError, TaskOptions},
+    utils::retry_after_secs,
 };
 use axum::{
     extract::State,
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -21,12 +24,46 @@ pub struct GsLogToLocustConverterOkResponse {
 #[derive(Serialize, ToSchema)]
 pub enum GsLogToLocustConverterErrorResponse {
     NotFound,
+    /// The project has no files to convert
+    Empty,
+    /// A query parameter could not be parsed
+    ParseFailed(String),
+    InvalidProjectName,
+    UnsupportedTarget,
+    MetricLabelNotAllowed,
+    CallbackUrlNotAllowed,
+    InvalidLabels(String),
+    RateLimited {
+        retry_after_secs: u64,
+    },
+    ServerError(ApiError),
 }
 
-impl From<GsLogToLocstConverterError> for GsLogToLocustConverterErrorResponse {
-    fn from(err: GsLogToLocstConverterError) -> Self {
+impl From<ConverterError> for GsLogToLocustConverterErrorResponse {
+    fn from(err: ConverterError) -> Self {
         match err {
-            GsLogToLocstConverterError::NotFound => GsLogToLocustConverterErrorResponse::NotFound,
+            ConverterError::NotFound => GsLogToLocustConverterErrorResponse::NotFound,
+            ConverterError::Empty => GsLogToLocustConverterErrorResponse::Empty,
+            ConverterError::InvalidProjectName => {
+                GsLogToLocustConverterErrorResponse::InvalidProjectName
+            }
+            ConverterError::UnsupportedTarget => {
+                GsLogToLocustConverterErrorResponse::UnsupportedTarget
+            }
+            ConverterError::MetricLabelNotAllowed => {
+                GsLogToLocustConverterErrorResponse::MetricLabelNotAllowed
+            }
+            ConverterError::CallbackUrlNotAllowed => {
+                GsLogToLocustConverterErrorResponse::CallbackUrlNotAllowed
+            }
+            ConverterError::RateLimited { retry_after } => {
+                GsLogToLocustConverterErrorResponse::RateLimited {
+                    retry_after_secs: retry_after_secs(retry_after),
+                }
+            }
+            ConverterError::IoError(err) => {
+                GsLogToLocustConverterErrorResponse::ServerError(err.into())
+            }
         }
     }
 }
@@ -43,6 +80,24 @@ impl IntoResponse for GsLogToLocustConverterErrorResponse {
             GsLogToLocustConverterErrorResponse::NotFound => {
                 (StatusCode::NOT_FOUND, Json(self)).into_response()
             }
+            GsLogToLocustConverterErrorResponse::Empty => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+            }
+            GsLogToLocustConverterErrorResponse::ParseFailed(_)
+            | GsLogToLocustConverterErrorResponse::InvalidProjectName
+            | GsLogToLocustConverterErrorResponse::UnsupportedTarget
+            | GsLogToLocustConverterErrorResponse::MetricLabelNotAllowed
+            | GsLogToLocustConverterErrorResponse::CallbackUrlNotAllowed
+            | GsLogToLocustConverterErrorResponse::InvalidLabels(_) => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            GsLogToLocustConverterErrorResponse::RateLimited { retry_after_secs } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(self),
+            )
+                .into_response(),
+            GsLogToLocustConverterErrorResponse::ServerError(err) => err.into_response(),
         }
     }
 }
@@ -51,36 +106,135 @@ impl IntoResponse for GsLogToLocustConverterErrorResponse {
 pub struct GsLogToLocustConverterQuery {
     /// Name of the project
     project_name: String,
+    /// Conversion target. Defaults to `locust`
+    target: Option<String>,
+    /// Optional label for the task metrics
+    metric_label: Option<String>,
+    /// Comma separated `key:value` labels of the task
+    labels: Option<String>,
+    /// Url to post the final status of the task to
+    callback_url: Option<String>,
+    /// Queued tasks with a higher priority run first. Defaults to `0`
+    priority: Option<i32>,
+    /// Niceness of the converter process
+    priority_nice: Option<i32>,
+    /// Keep the stdin of the converter process open for the WebSocket
+    #[serde(default)]
+    interactive: bool,
 }
 
-/// Converts the format of log files given in the GS log format to the format used by locust (Locust log format). 
+/// Converts the format of log files given in the GS log format to the format used by locust (Locust log format).
 #[utoipa::path(
     post,
     path = "/api/gs_log_to_locust_converter", 
     params(
         ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint"),
-        ("project_name" = String, Query, description = "Name of the project.")
+        ("project_name" = String, Query, description = "Name of the project. Only alphanumerics, `-` and `_`.", example = "my-project"),
+        ("target" = Option<String>, Query, description = "Conversion target. Defaults to `locust`.", example = "locust"),
+        ("metric_label" = Option<String>, Query, description = "Label for the task metrics. Must be one of the labels allowed by the server."),
+        ("labels" = Option<String>, Query, description = "Comma separated `key:value` labels of the task, e.g. `env:prod,team:load`. Used to filter `/api/tasks`."),
+        ("callback_url" = Option<String>, Query, description = "Url the final `id`, `status` and `exit_code` of the task are posted to as JSON once it is done. Its host must be allowed by the server."),
+        ("priority" = Option<i32>, Query, description = "If the server runs a limited number of tasks at once, queued tasks with a higher priority run first. Defaults to `0`."),
+        ("priority_nice" = Option<i32>, Query, description = "OS scheduling niceness of the converter process, from `0` to `19`. Higher values run at a lower priority, e.g. for background jobs. Added to the niceness of the server, values out of range are clamped. Only applied on Unix.", example = 10),
+        ("interactive" = Option<bool>, Query, description = "Keep the stdin of the converter process open, so the chat can write to it with the `Stdin` WebSocket message. Otherwise it reads EOF right away. Defaults to false."),
+        ("Idempotency-Key" = Option<String>, Header, description = "Retrying with the same key returns the task the first request started instead of starting another one.")
     ),
     tag = "convert",
     responses(
         (status = 201, description = "Task was scheduled for running", body = GsLogToLocustConverterOkResponse, example = json!(GsLogToLocustConverterOkResponse{id: String::from("some-id")})),
-        (status = 400, description = "Chat id missing, Api key missing"),
+        (status = 404, description = "Project not found", body = GsLogToLocustConverterErrorResponse, example = json!(GsLogToLocustConverterErrorResponse::NotFound)),
+        (status = 422, description = "Project has no files to convert", body = GsLogToLocustConverterErrorResponse, example = json!(GsLogToLocustConverterErrorResponse::Empty)),
+        (status = 400, description = "Chat id missing, Api key missing, Query parameter invalid, Invalid project name, Unsupported target, Metric label not allowed, Callback url not allowed", body = GsLogToLocustConverterErrorResponse, example = json!(GsLogToLocustConverterErrorResponse::UnsupportedTarget)),
+        (status = 429, description = "Too many tasks started by this chat. See the `Retry-After` header", body = GsLogToLocustConverterErrorResponse, example = json!(GsLogToLocustConverterErrorResponse::RateLimited{retry_after_secs: 30})),
         (status = 401, description = "Api key invalid"),
+        (status = 500, description = "Project could not be read", body = GsLogToLocustConverterErrorResponse),
     ),
     security(
         ("api_key" = []),
+        ("bearer_token" = []),
     ),
 )]
 pub async fn gs_log_to_locust_converter(
     State(state): State<ApiState>,
     ChatId(chat_id): ChatId,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
     Query(query): Query<GsLogToLocustConverterQuery>,
 ) -> Result<GsLogToLocustConverterOkResponse, GsLogToLocustConverterErrorResponse> {
     let project_name = query.project_name;
 
+    let labels = parse_labels(query.labels.as_deref().unwrap_or_default())
+        .map_err(|err| GsLogToLocustConverterErrorResponse::InvalidLabels(err.to_string()))?;
+
+    let callback_url = query
+        .callback_url
+        .as_deref()
+        .map(url::Url::parse)
+        .transpose()
+        .map_err(|err| {
+            GsLogToLocustConverterErrorResponse::ParseFailed(format!("callback_url: {err}"))
+        })?;
+
     let id = state
-        .run_gs_log_to_locust_converter_task(chat_id, project_name)
+        .start_task_idempotent(&chat_id.clone(), idempotency_key, || {
+            state.run_gs_log_to_locust_converter_task(
+                chat_id,
+                project_name,
+                query.target,
+                TaskOptions {
+                    metric_label: query.metric_label,
+                    labels,
+                    callback_url,
+                    priority: query.priority.unwrap_or_default(),
+                    priority_nice: query.priority_nice,
+                    interactive: query.interactive,
+                },
+            )
+        })
         .await?;
 
     Ok(GsLogToLocustConverterOkResponse { id })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(err: ConverterError) -> StatusCode {
+        GsLogToLocustConverterErrorResponse::from(err)
+            .into_response()
+            .status()
+    }
+
+    #[test]
+    fn converter_errors_map_to_their_status() {
+        assert_eq!(status(ConverterError::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status(ConverterError::Empty),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            status(ConverterError::UnsupportedTarget),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(ConverterError::InvalidProjectName),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(ConverterError::RateLimited {
+                retry_after: std::time::Duration::from_secs(1)
+            }),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status(ConverterError::IoError(std::io::Error::other("disk"))),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            GsLogToLocustConverterErrorResponse::ParseFailed(String::from("callback_url"))
+                .into_response()
+                .status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
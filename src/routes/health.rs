@@ -0,0 +1,16 @@
+use crate::server::state::ApiState;
+use axum::{extract::State, http::StatusCode};
+
+/// The server is up.
+pub async fn health() -> &'static str {
+    "ok"
+}
+
+/// The server accepts new tasks. Fails once the server is shutting down.
+pub async fn ready(State(state): State<ApiState>) -> (StatusCode, &'static str) {
+    if state.is_shutting_down() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down");
+    }
+
+    (StatusCode::OK, "ok")
+}
@@ -0,0 +1,70 @@
+use crate::server::state::{ApiState, Health};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthzResponse {
+    /// Every subsystem is healthy
+    healthy: bool,
+    /// Files can be written to the projects directory
+    projects_dir_writable: bool,
+    /// The server is not shutting down, so new tasks are started
+    task_runner_available: bool,
+    /// No tasks wait for the limit of concurrently running tasks
+    tasks_within_limit: bool,
+    /// Queued and running tasks
+    #[schema(example = 2)]
+    active_tasks: u64,
+}
+
+impl From<Health> for HealthzResponse {
+    fn from(health: Health) -> Self {
+        Self {
+            healthy: health.healthy(),
+            projects_dir_writable: health.projects_dir_writable,
+            task_runner_available: health.task_runner_available,
+            tasks_within_limit: health.tasks_within_limit,
+            active_tasks: health.active_tasks,
+        }
+    }
+}
+
+impl IntoResponse for HealthzResponse {
+    fn into_response(self) -> Response {
+        let status = if self.healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        (status, Json(self)).into_response()
+    }
+}
+
+/// Get the health of the subsystems
+///
+/// Unlike `/health`, the projects directory, the task runner and the task limit are checked.
+#[utoipa::path(
+    get,
+    path = "/api/healthz",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Every subsystem is healthy", body = HealthzResponse),
+        (status = 503, description = "A subsystem is unhealthy", body = HealthzResponse),
+        (status = 400, description = "Api key missing"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn healthz(State(state): State<ApiState>) -> HealthzResponse {
+    state.health().await.into()
+}
@@ -1,11 +1,12 @@
 //! Routes and responses for downloading log files
 use crate::server::{
     extractors::{chat_id::ChatId, query::Query},
-    state::{ApiState, GetFileError, ListFilesError},
+    state::{ApiState, FileInfo, FileSort, GetFileError, ListFilesError, TailError},
 };
 use axum::{
+    body::Body,
     extract::State,
-    http::StatusCode,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -14,13 +15,26 @@ use utoipa::ToSchema;
 
 #[derive(Serialize, ToSchema)]
 pub struct ListLogfilesOkResponse {
-    /// List of names of available log files
-    files: Vec<String>,
+    /// Available log files
+    files: ListedFiles,
+    /// Number of available log files, regardless of `offset` and `limit`
+    #[schema(example = 2)]
+    total: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum ListedFiles {
+    /// Names of the files, if `names_only` was requested
+    Names(Vec<String>),
+    Infos(Vec<FileInfo>),
 }
 
 #[derive(Serialize, ToSchema)]
 pub enum ListLogfilesErrorResponse {
     NotFound,
+    InvalidPattern,
+    InvalidProjectName,
     ServerError,
 }
 
@@ -30,6 +44,10 @@ impl IntoResponse for ListLogfilesErrorResponse {
             ListLogfilesErrorResponse::NotFound => {
                 (StatusCode::NOT_FOUND, Json(self)).into_response()
             }
+            ListLogfilesErrorResponse::InvalidPattern
+            | ListLogfilesErrorResponse::InvalidProjectName => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
             ListLogfilesErrorResponse::ServerError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
             }
@@ -41,6 +59,8 @@ impl From<ListFilesError> for ListLogfilesErrorResponse {
     fn from(err: ListFilesError) -> Self {
         match err {
             ListFilesError::NotFound => ListLogfilesErrorResponse::NotFound,
+            ListFilesError::InvalidPattern => ListLogfilesErrorResponse::InvalidPattern,
+            ListFilesError::InvalidProjectName => ListLogfilesErrorResponse::InvalidProjectName,
             ListFilesError::IoError(_) => ListLogfilesErrorResponse::ServerError,
         }
     }
@@ -56,24 +76,42 @@ impl IntoResponse for ListLogfilesOkResponse {
 pub struct ListFilesQuery {
     /// Name of the project
     project_name: String,
+    /// Glob pattern the file names must match
+    pattern: Option<String>,
+    /// Number of files to skip
+    offset: Option<usize>,
+    /// Maximum number of files to return
+    limit: Option<usize>,
+    /// Order of the files
+    sort: Option<FileSort>,
+    /// Return only the names of the files
+    #[serde(default)]
+    names_only: bool,
 }
 
-/// List available log files
+/// List available log files with their size and modification time
 #[utoipa::path(
     get,
     path = "/api/list_log_files", 
     tag = "files",
     params(
         ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint"),
-        ("project_name" = String, Query, description = "Name of the project"),
+        ("project_name" = String, Query, description = "Name of the project", example = "my-project"),
+        ("pattern" = Option<String>, Query, description = "Glob pattern the file names must match, e.g. `*.log`", example = "*.log"),
+        ("offset" = Option<usize>, Query, description = "Number of files to skip. Defaults to 0"),
+        ("limit" = Option<usize>, Query, description = "Maximum number of files to return. Defaults to all"),
+        ("sort" = Option<FileSort>, Query, description = "Order of the files. Defaults to `name`"),
+        ("names_only" = Option<bool>, Query, description = "Return only the names of the files instead of their metadata. Defaults to false"),
     ),
     responses(
-        (status = 200, description = "List of names of available log files", body = ListLogfilesOkResponse, example = json!(ListLogfilesOkResponse{files: vec![String::from("file_1.log"), String::from("file_2.log")]})),
-        (status = 400, description = "Chat id missing. Api key missing"),
+        (status = 200, description = "List of available log files", body = ListLogfilesOkResponse, example = json!(ListLogfilesOkResponse{files: ListedFiles::Infos(vec![FileInfo{name: String::from("file_1.log"), size_bytes: 1024, modified: Some(1707000000)}, FileInfo{name: String::from("file_2.log"), size_bytes: 2048, modified: Some(1707000100)}]), total: 2})),
+        (status = 404, description = "Project not found", body = ListLogfilesErrorResponse, example = json!(ListLogfilesErrorResponse::NotFound)),
+        (status = 400, description = "Chat id missing. Api key missing. Invalid pattern. Invalid project name", body = ListLogfilesErrorResponse, example = json!(ListLogfilesErrorResponse::InvalidPattern)),
         (status = 401, description = "Api key invalid"),
     ),
     security(
         ("api_key" = []),
+        ("bearer_token" = []),
     ),
 )]
 pub async fn list_log_files(
@@ -81,14 +119,35 @@ pub async fn list_log_files(
     ChatId(_chat_id): ChatId,
     Query(query): Query<ListFilesQuery>,
 ) -> Result<ListLogfilesOkResponse, ListLogfilesErrorResponse> {
-    let files = state.list_files(query.project_name).await?;
+    let page = state
+        .list_files(
+            query.project_name,
+            query.pattern.as_deref(),
+            query.sort.unwrap_or_default(),
+            query.offset.unwrap_or_default(),
+            query.limit,
+        )
+        .await?;
 
-    Ok(ListLogfilesOkResponse { files })
+    let files = if query.names_only {
+        ListedFiles::Names(page.files.into_iter().map(|file| file.name).collect())
+    } else {
+        ListedFiles::Infos(page.files)
+    };
+
+    Ok(ListLogfilesOkResponse {
+        files,
+        total: page.total,
+    })
 }
 
 #[derive(Serialize, ToSchema)]
 pub enum GetLogFileErrorResponse {
     NotFound,
+    /// The file is not valid UTF-8. Request it with `lossy=true` to replace the invalid bytes
+    InvalidUtf8,
+    /// The project name or file name leaves the project, e.g. with `..`
+    InvalidName,
     ServerError,
 }
 
@@ -98,6 +157,12 @@ impl IntoResponse for GetLogFileErrorResponse {
             GetLogFileErrorResponse::NotFound => {
                 (StatusCode::NOT_FOUND, Json(self)).into_response()
             }
+            GetLogFileErrorResponse::InvalidUtf8 => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+            }
+            GetLogFileErrorResponse::InvalidName => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
             GetLogFileErrorResponse::ServerError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
             }
@@ -109,6 +174,8 @@ impl From<GetFileError> for GetLogFileErrorResponse {
     fn from(err: GetFileError) -> Self {
         match err {
             GetFileError::NotFound => GetLogFileErrorResponse::NotFound,
+            GetFileError::InvalidUtf8 => GetLogFileErrorResponse::InvalidUtf8,
+            GetFileError::InvalidName => GetLogFileErrorResponse::InvalidName,
             GetFileError::IoError(_) => GetLogFileErrorResponse::ServerError,
         }
     }
@@ -120,33 +187,749 @@ pub struct GetLogFileQuery {
     project_name: String,
     /// Name of the log file to download
     file_name: String,
+    /// Replace invalid UTF-8 instead of failing
+    #[serde(default)]
+    lossy: bool,
 }
 
-/// Download a log file as text/plain
+/// `Content-Encoding` of log files that are stored compressed, derived from the file extension
+fn precompressed_encoding(file_name: &str) -> Option<&'static str> {
+    let extension = std::path::Path::new(file_name).extension()?.to_str()?;
+
+    match extension {
+        "gz" => Some("gzip"),
+        "zst" => Some("zstd"),
+        _ => None,
+    }
+}
+
+/// `Content-Type` of a text log file, derived from the file extension
+fn text_content_type(file_name: &str) -> &'static str {
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str());
+
+    match extension {
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("json") => "application/json",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+/// `Content-Disposition` for sending a file under its name.
+///
+/// Quotes, backslashes, control and non ASCII characters are replaced in the quoted `filename`.
+/// If any were, the exact name follows percent-encoded in `filename*`.
+fn content_disposition(disposition: &str, file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+
+    if fallback == file_name {
+        return format!("{disposition}; filename=\"{fallback}\"");
+    }
+
+    let encoded: String = file_name
+        .bytes()
+        .map(|byte| match byte {
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                char::from(byte).to_string()
+            }
+            byte if byte.is_ascii_alphanumeric() => char::from(byte).to_string(),
+            byte => format!("%{byte:02X}"),
+        })
+        .collect();
+
+    format!("{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// Strong `ETag` of a file, derived from its size and modification time.
+///
+/// A file sent with a `Content-Encoding` is a different representation than the file sent as is, so the `encoding` is part of the tag.
+fn file_etag(metadata: &std::fs::Metadata, encoding: Option<&str>) -> HeaderValue {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    let etag = match encoding {
+        Some(encoding) => format!(
+            "\"{:x}-{:x}-{encoding}\"",
+            metadata.len(),
+            modified.as_nanos()
+        ),
+        None => format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos()),
+    };
+
+    HeaderValue::from_str(&etag).expect("Hex digits and quotes are a valid header value")
+}
+
+/// Whether `If-None-Match` matches `etag`, either by value or by `*`. Weak tags match too
+fn none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().unwrap_or_default();
+
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Whether `Accept-Encoding` accepts `encoding`, either by name or by `*`, without `q=0`
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    let mut wildcard = false;
+
+    let accepted = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+
+    for accepted in accepted {
+        let mut parts = accepted.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let rejected = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+
+        if name.eq_ignore_ascii_case(encoding) {
+            return !rejected;
+        }
+
+        if name == "*" {
+            wildcard = !rejected;
+        }
+    }
+
+    wildcard
+}
+
+/// Download a log file as text
+///
+/// The `Content-Type` follows the file extension: `text/csv` for `.csv`, `application/json` for `.json` and `text/plain` otherwise.
+/// Log files stored compressed (`.gz`, `.zst`) are sent as they are with the matching `Content-Encoding`,
+/// if the client accepts it. Otherwise they are sent as an attachment.
+///
+/// The response carries an `ETag`. A request with a matching `If-None-Match` is answered with `304 Not Modified`.
+/// Responses for compressed log files vary by `Accept-Encoding`, and so does their `ETag`.
 #[utoipa::path(
     get,
     path = "/api/get_log_file_text", 
     params(
         ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint"),
-        ("project_name" = String, Query, description = "Name of the project"),
-        ("file_name" = String, Query, description = "Name of the log file to download")
+        ("project_name" = String, Query, description = "Name of the project", example = "my-project"),
+        ("file_name" = String, Query, description = "Name of the log file to download", example = "file_1.log"),
+        ("lossy" = Option<bool>, Query, description = "Replace invalid UTF-8 with `U+FFFD` instead of failing. Defaults to false"),
     ),
     tag = "files",
     responses(
-        (status = 200, description = "Log file", body = String),
-        (status = 400, description = "Chat id missing. Api key missing. Query invalid"),
+        (status = 200, description = "Log file", body = String, example = json!("line 1\nline 2\n")),
+        (status = 304, description = "Log file unchanged since the `ETag` in `If-None-Match`"),
+        (status = 404, description = "Project/File not found", body = GetLogFileErrorResponse, example = json!(GetLogFileErrorResponse::NotFound)),
+        (status = 422, description = "Log file is not valid UTF-8 and `lossy` is not set", body = GetLogFileErrorResponse, example = json!(GetLogFileErrorResponse::InvalidUtf8)),
+        (status = 400, description = "Chat id missing. Api key missing. Query invalid. Invalid project or file name", body = GetLogFileErrorResponse, example = json!(GetLogFileErrorResponse::InvalidName)),
         (status = 401, description = "Api key invalid"),
     ),
     security(
         ("api_key" = []),
+        ("bearer_token" = []),
     ),
 )]
 pub async fn get_log_file_text(
     State(state): State<ApiState>,
     ChatId(_chat_id): ChatId,
     Query(query): Query<GetLogFileQuery>,
-) -> Result<String, GetLogFileErrorResponse> {
-    let file = state.get_file(query.project_name, query.file_name).await?;
+    headers: HeaderMap,
+) -> Result<Response, GetLogFileErrorResponse> {
+    let metadata = state
+        .file_metadata(query.project_name.clone(), query.file_name.clone())
+        .await?;
+    let precompressed = precompressed_encoding(&query.file_name);
+    let encoding = precompressed.filter(|encoding| accepts_encoding(&headers, encoding));
+    let etag = file_etag(&metadata, encoding);
+
+    let mut response = if none_match(&headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        log_file_response(&state, query, &headers).await?
+    };
+    response.headers_mut().insert(header::ETAG, etag);
+
+    if precompressed.is_some() {
+        response.headers_mut().insert(
+            header::VARY,
+            HeaderValue::from_name(header::ACCEPT_ENCODING),
+        );
+    }
+
+    Ok(response)
+}
+
+async fn log_file_response(
+    state: &ApiState,
+    query: GetLogFileQuery,
+    headers: &HeaderMap,
+) -> Result<Response, GetLogFileErrorResponse> {
+    let Some(encoding) = precompressed_encoding(&query.file_name) else {
+        let content_type = text_content_type(&query.file_name);
+        let content_disposition = content_disposition("inline", &query.file_name);
+        let file = state
+            .get_file(query.project_name, query.file_name, query.lossy)
+            .await?;
+
+        return Ok((
+            [
+                (header::CONTENT_TYPE, String::from(content_type)),
+                (header::CONTENT_DISPOSITION, content_disposition),
+            ],
+            file,
+        )
+            .into_response());
+    };
+
+    // The type of the file that was compressed, e.g. `text/csv` for `run.csv.gz`
+    let content_type = std::path::Path::new(&query.file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(text_content_type)
+        .unwrap_or("text/plain; charset=utf-8");
+    let content_disposition = content_disposition("attachment", &query.file_name);
+    let file = state
+        .get_file_bytes(query.project_name, query.file_name)
+        .await?;
+
+    if !accepts_encoding(headers, encoding) {
+        return Ok((
+            [
+                (
+                    header::CONTENT_TYPE,
+                    String::from("application/octet-stream"),
+                ),
+                (header::CONTENT_DISPOSITION, content_disposition),
+            ],
+            file,
+        )
+            .into_response());
+    }
+
+    // The `CompressionLayer` leaves responses with a `Content-Encoding` alone,
+    // so the file is not compressed a second time.
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_ENCODING, encoding),
+        ],
+        file,
+    )
+        .into_response())
+}
+
+/// Check whether a log file exists and get its size, without downloading it
+#[utoipa::path(
+    head,
+    path = "/api/get_log_file_text",
+    params(
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint"),
+        ("project_name" = String, Query, description = "Name of the project", example = "my-project"),
+        ("file_name" = String, Query, description = "Name of the log file", example = "file_1.log")
+    ),
+    tag = "files",
+    responses(
+        (status = 200, description = "Log file exists. `Content-Length` is its size in bytes, `ETag` the one of the download without `Accept-Encoding`"),
+        (status = 404, description = "Project/File not found"),
+        (status = 400, description = "Chat id missing. Api key missing. Query invalid. Invalid project or file name"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn head_log_file(
+    State(state): State<ApiState>,
+    ChatId(_chat_id): ChatId,
+    Query(query): Query<GetLogFileQuery>,
+) -> Result<Response, GetLogFileErrorResponse> {
+    let content_type = match precompressed_encoding(&query.file_name) {
+        Some(_) => "application/octet-stream",
+        None => text_content_type(&query.file_name),
+    };
+
+    let metadata = state
+        .file_metadata(query.project_name, query.file_name)
+        .await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::CONTENT_LENGTH, HeaderValue::from(metadata.len())),
+            (header::ETAG, file_etag(&metadata, None)),
+        ],
+        Body::empty(),
+    )
+        .into_response())
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TailOkResponse {
+    /// Name of the most recently modified file of the project
+    #[schema(example = "file_2.log")]
+    file_name: String,
+    /// Last lines of the file
+    lines: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub enum TailErrorResponse {
+    NotFound,
+    InvalidProjectName,
+    ServerError,
+}
+
+impl IntoResponse for TailOkResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+impl IntoResponse for TailErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            TailErrorResponse::NotFound => (StatusCode::NOT_FOUND, Json(self)).into_response(),
+            TailErrorResponse::InvalidProjectName => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            TailErrorResponse::ServerError => {
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
+            }
+        }
+    }
+}
+
+impl From<TailError> for TailErrorResponse {
+    fn from(err: TailError) -> Self {
+        match err {
+            TailError::NotFound => TailErrorResponse::NotFound,
+            TailError::InvalidProjectName => TailErrorResponse::InvalidProjectName,
+            TailError::IoError(_) => TailErrorResponse::ServerError,
+        }
+    }
+}
+
+fn default_tail_lines() -> usize {
+    100
+}
+
+#[derive(Deserialize)]
+pub struct TailQuery {
+    /// Name of the project. `project_name` is accepted too, like on the other file routes
+    #[serde(alias = "project_name")]
+    project: String,
+    /// Number of lines to return. At most [`crate::server::state::MAX_TAIL_LINES`]
+    #[serde(default = "default_tail_lines")]
+    lines: usize,
+}
+
+/// Get the last lines of the most recently modified log file of a project
+#[utoipa::path(
+    get,
+    path = "/api/tail",
+    params(
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint"),
+        ("project" = String, Query, description = "Name of the project. Only alphanumerics, `-` and `_`. Also accepted as `project_name`", example = "my-project"),
+        ("lines" = Option<usize>, Query, description = "Number of lines to return. Defaults to 100, at most 10000", example = 100)
+    ),
+    tag = "files",
+    responses(
+        (status = 200, description = "Last lines of the newest log file", body = TailOkResponse, example = json!(TailOkResponse{file_name: String::from("file_2.log"), lines: vec![String::from("line 1"), String::from("line 2")]})),
+        (status = 404, description = "Project not found or empty", body = TailErrorResponse, example = json!(TailErrorResponse::NotFound)),
+        (status = 400, description = "Chat id missing. Api key missing. Query invalid. Invalid project name", body = TailErrorResponse, example = json!(TailErrorResponse::InvalidProjectName)),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn tail(
+    State(state): State<ApiState>,
+    ChatId(_chat_id): ChatId,
+    Query(query): Query<TailQuery>,
+) -> Result<TailOkResponse, TailErrorResponse> {
+    let tailed = state.tail_newest_file(query.project, query.lines).await?;
+
+    Ok(TailOkResponse {
+        file_name: tailed.file_name,
+        lines: tailed.lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::state::ApiStateConfig;
+    use axum::{http::Request, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::compression::CompressionLayer;
+
+    #[tokio::test]
+    async fn gz_log_file_is_not_compressed_twice() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+
+        // gzip magic bytes followed by some payload
+        let gz = b"\x1f\x8b\x08\x00some compressed log".to_vec();
+        std::fs::write(project_dir.join("run.log.gz"), &gz).unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route("/get_log_file_text", get(get_log_file_text))
+            .with_state(state)
+            .layer(CompressionLayer::new());
+
+        let request = Request::builder()
+            .uri("/get_log_file_text?chat_id=chat&project_name=project&file_name=run.log.gz")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        assert_eq!(body.as_ref(), gz.as_slice());
+    }
+
+    #[tokio::test]
+    async fn gz_log_file_has_an_etag_per_encoding() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("run.log.gz"), b"\x1f\x8b\x08\x00log").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route("/get_log_file_text", get(get_log_file_text))
+            .with_state(state);
+
+        let get_log = |accept_encoding: Option<&str>, if_none_match: Option<&HeaderValue>| {
+            let mut request = Request::builder()
+                .uri("/get_log_file_text?chat_id=chat&project_name=project&file_name=run.log.gz");
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+            }
+            if let Some(if_none_match) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, if_none_match);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let identity = app.clone().oneshot(get_log(None, None)).await.unwrap();
+        let gzip = app
+            .clone()
+            .oneshot(get_log(Some("gzip"), None))
+            .await
+            .unwrap();
+
+        for response in [&identity, &gzip] {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        }
+        assert_ne!(
+            identity.headers()[header::ETAG],
+            gzip.headers()[header::ETAG]
+        );
+
+        // The tag of the file as is does not match the gzip encoded one.
+        let response = app
+            .clone()
+            .oneshot(get_log(
+                Some("gzip"),
+                Some(&identity.headers()[header::ETAG]),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(get_log(Some("gzip"), Some(&gzip.headers()[header::ETAG])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+    }
+
+    #[tokio::test]
+    async fn csv_log_file_is_sent_as_csv() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("stats.csv"), "a,b\n1,2\n").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
 
-    Ok(file)
+        let app = Router::new()
+            .route("/get_log_file_text", get(get_log_file_text))
+            .with_state(state);
+
+        let request = Request::builder()
+            .uri("/get_log_file_text?chat_id=chat&project_name=project&file_name=stats.csv")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "inline; filename=\"stats.csv\""
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_is_only_sent_lossily() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("run.log"), b"line 1\n\xff\xfeline 2\n").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route("/get_log_file_text", get(get_log_file_text))
+            .with_state(state);
+
+        let get = |query: &str| {
+            let request = Request::builder()
+                .uri(format!(
+                    "/get_log_file_text?chat_id=chat&project_name=project&file_name=run.log{query}"
+                ))
+                .body(Body::empty())
+                .unwrap();
+
+            app.clone().oneshot(request)
+        };
+
+        let response = get("").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"\"InvalidUtf8\"");
+
+        let response = get("&lossy=true").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            "line 1\n\u{FFFD}\u{FFFD}line 2\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn head_reports_the_size_of_existing_files_only() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("run.log"), "line 1\nline 2\n").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route(
+                "/get_log_file_text",
+                get(get_log_file_text).head(head_log_file),
+            )
+            .with_state(state);
+
+        let head = |file_name: &str| {
+            Request::head(format!(
+                "/get_log_file_text?chat_id=chat&project_name=project&file_name={file_name}"
+            ))
+            .body(Body::empty())
+            .unwrap()
+        };
+
+        let response = app.clone().oneshot(head("run.log")).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "14");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        let response = app.oneshot(head("missing.log")).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn files_outside_the_projects_are_not_served() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let projects_dir = root.path().join("projects");
+        std::fs::create_dir_all(projects_dir.join("project")).unwrap();
+        std::fs::write(root.path().join("secret.txt"), "secret").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route(
+                "/get_log_file_text",
+                get(get_log_file_text).head(head_log_file),
+            )
+            .route("/list_log_files", get(list_log_files))
+            .with_state(state);
+
+        for (project_name, file_name) in [("..", "secret.txt"), ("project", "..%2Fsecret.txt")] {
+            let uri = format!(
+                "/get_log_file_text?chat_id=chat&project_name={project_name}&file_name={file_name}"
+            );
+
+            for request in [Request::get(&uri), Request::head(&uri)] {
+                let response = app
+                    .clone()
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+            }
+        }
+
+        let response = app
+            .oneshot(
+                Request::get("/list_log_files?chat_id=chat&project_name=..")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn unchanged_log_file_is_not_sent_again() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("run.log"), "line 1\n").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route("/get_log_file_text", get(get_log_file_text))
+            .with_state(state);
+
+        let get_log = |if_none_match: Option<&HeaderValue>| {
+            let mut request = Request::builder()
+                .uri("/get_log_file_text?chat_id=chat&project_name=project&file_name=run.log");
+            if let Some(if_none_match) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, if_none_match);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = app.clone().oneshot(get_log(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+
+        let response = app.clone().oneshot(get_log(Some(&etag))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        // A changed file is sent again.
+        std::fs::write(project_dir.join("run.log"), "line 1\nline 2\n").unwrap();
+
+        let response = app.oneshot(get_log(Some(&etag))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag);
+    }
+
+    #[test]
+    fn file_names_are_escaped_in_the_content_disposition() {
+        assert_eq!(
+            content_disposition("inline", "run 1.log"),
+            "inline; filename=\"run 1.log\""
+        );
+        assert_eq!(
+            content_disposition("inline", "a\";b.log"),
+            "inline; filename=\"a_;b.log\"; filename*=UTF-8''a%22%3Bb.log"
+        );
+        assert_eq!(
+            content_disposition("attachment", "läuft\r\n.log.gz"),
+            "attachment; filename=\"l_uft__.log.gz\"; filename*=UTF-8''l%C3%A4uft%0D%0A.log.gz"
+        );
+    }
+
+    #[test]
+    fn accept_encoding_is_parsed() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, "br, gzip;q=0.5".parse().unwrap());
+        assert!(accepts_encoding(&headers, "gzip"));
+        assert!(!accepts_encoding(&headers, "zstd"));
+
+        headers.insert(header::ACCEPT_ENCODING, "gzip;q=0, *".parse().unwrap());
+        assert!(!accepts_encoding(&headers, "gzip"));
+        assert!(accepts_encoding(&headers, "zstd"));
+    }
 }
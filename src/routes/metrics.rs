@@ -0,0 +1,51 @@
+use crate::server::{metrics::TaskMetric, state::ApiState};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct MetricsResponse {
+    /// Number of connected WebSocket clients
+    #[schema(example = 2)]
+    connection_count: usize,
+    /// Number of messages dropped because a client could not keep up
+    #[schema(example = 0)]
+    dropped_messages: u64,
+    /// Task counters by kind and metric label
+    tasks: Vec<TaskMetric>,
+}
+
+impl IntoResponse for MetricsResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+/// Get server metrics
+#[utoipa::path(
+    get,
+    path = "/api/metrics",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Server metrics", body = MetricsResponse, example = json!(MetricsResponse{connection_count: 2, dropped_messages: 0, tasks: vec![]})),
+        (status = 400, description = "Api key missing"),
+        (status = 401, description = "Api key invalid"),
+        (status = 403, description = "Admin token required. The api key is not the admin token"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn metrics(State(state): State<ApiState>) -> MetricsResponse {
+    MetricsResponse {
+        connection_count: state.connection_count().await,
+        dropped_messages: state.dropped_messages(),
+        tasks: state.task_metrics().await,
+    }
+}
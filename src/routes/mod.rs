@@ -1,6 +1,1146 @@
 pub mod cancel;
+pub mod converters;
 pub mod download_zip_file;
+pub mod evict_completed;
 pub mod gs_log_to_locust_converter;
+pub mod health;
+pub mod healthz;
 pub mod log_files;
+pub mod metrics;
+pub mod project;
+pub mod prometheus;
 pub mod request_chat_id;
+pub mod resubmit;
+pub mod stats;
 pub mod status;
+pub mod task_events;
+pub mod task_output;
+pub mod task_stream;
+pub mod tasks;
+pub mod version;
+pub mod ws;
+
+use crate::server::{
+    middleware::{
+        expose_matched_path, limit_connections, request_timeout, validate_admin_token,
+        validate_bearer_token,
+    },
+    response::ApiError,
+    state::ApiState,
+};
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+use std::path::{Path, PathBuf};
+use tower_http::{limit::RequestBodyLimitLayer, services::ServeDir};
+
+/// The routes nested under `/api`. All of them require an api key.
+///
+/// Except for the long-lived ones, their handlers are subject to the request timeout.
+pub fn api(state: ApiState) -> Router<ApiState> {
+    let long_lived = Router::new()
+        .route("/status/:id", get(status::status))
+        // Wait for the task to terminate, which may take longer than the request timeout.
+        .route("/cancel/:id", put(cancel::cancel))
+        .route("/task/:id", delete(tasks::delete_task))
+        .route("/task_stream/:id", get(task_stream::task_stream))
+        .route("/task_events/:id", get(task_events::task_events))
+        .route(
+            "/download_project/:project_name",
+            get(project::download_project).post(project::download_project_files),
+        )
+        .route("/ws", get(ws::ws));
+
+    Router::new()
+        .route("/request_chat_id", get(request_chat_id::request_chat_id))
+        .route("/resubmit/:id", post(resubmit::resubmit))
+        .route("/status_bulk", post(status::status_bulk))
+        .route("/task_output/:id", get(task_output::task_output))
+        .route("/tasks", get(tasks::list_tasks))
+        .route("/list_log_files", get(log_files::list_log_files))
+        .route(
+            "/download_zip_file",
+            post(download_zip_file::download_zip_file),
+        )
+        .route(
+            "/get_log_file_text",
+            get(log_files::get_log_file_text).head(log_files::head_log_file),
+        )
+        .route("/tail", get(log_files::tail))
+        .route(
+            "/gs_log_to_locust_converter",
+            post(gs_log_to_locust_converter::gs_log_to_locust_converter),
+        )
+        .route("/converters", get(converters::list_converters))
+        .route("/healthz", get(healthz::healthz))
+        .route("/version", get(version::version))
+        .route("/project/:project_name", delete(project::delete_project))
+        .merge(admin_api(state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_timeout,
+        ))
+        .merge(long_lived)
+        .route_layer(middleware::from_fn(expose_matched_path))
+        // Without it, unknown paths fall through to the static files of the outer router.
+        .fallback(not_found)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            validate_bearer_token,
+        ))
+        // Outside of the authentication, so oversized bodies are rejected before anything else.
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(state.max_request_body_bytes()))
+        .layer(middleware::from_fn_with_state(state, limit_connections))
+}
+
+/// The routes for operators rather than chats. They require the admin token, if one is configured.
+fn admin_api(state: ApiState) -> Router<ApiState> {
+    Router::new()
+        .route(
+            "/admin/evict_completed",
+            post(evict_completed::evict_completed),
+        )
+        .route("/metrics", get(metrics::metrics))
+        .route("/stats", get(stats::stats))
+        .route_layer(middleware::from_fn_with_state(state, validate_admin_token))
+}
+
+async fn not_found() -> ApiError {
+    ApiError::NotFound
+}
+
+/// The static files in `assets_dir`, served for everything outside of the api.
+pub fn assets(assets_dir: impl AsRef<Path>) -> ServeDir {
+    ServeDir::new(assets_dir).append_index_html_on_directories(true)
+}
+
+/// The router everything else is added to. Unmatched paths are served from `assets_dir`, or are a JSON 404 without it.
+pub fn root(assets_dir: Option<PathBuf>) -> Router<ApiState> {
+    match assets_dir {
+        Some(assets_dir) => Router::new().fallback_service(assets(assets_dir)),
+        None => Router::new().fallback(not_found),
+    }
+}
+
+/// Prometheus metrics at `/metrics`, next to `/api`. Requires the admin token, like the admin routes of the api.
+pub fn prometheus(state: ApiState) -> Router<ApiState> {
+    Router::new()
+        .route("/metrics", get(prometheus::prometheus_metrics))
+        .layer(middleware::from_fn_with_state(state, validate_admin_token))
+}
+
+/// Health, readiness and metrics without authentication, for a separate admin port.
+pub fn admin() -> Router<ApiState> {
+    Router::new()
+        .route("/health", get(health::health))
+        .route("/ready", get(health::ready))
+        .route("/api/metrics", get(metrics::metrics))
+        .route("/metrics", get(prometheus::prometheus_metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::state::ApiStateConfig;
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use std::collections::HashSet;
+    use tower::ServiceExt;
+
+    async fn request_chat_id_status(headers: &[(&str, &str)]) -> StatusCode {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let mut request = Request::get("/request_chat_id");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn api_key_header_authenticates() {
+        assert_eq!(
+            request_chat_id_status(&[("api_key", "token")]).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            request_chat_id_status(&[("api_key", "wrong")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn authorization_bearer_authenticates() {
+        assert_eq!(
+            request_chat_id_status(&[("authorization", "Bearer token")]).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            request_chat_id_status(&[("authorization", "Bearer wrong")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_authorization_is_rejected() {
+        for authorization in ["token", "Basic token", "Bearer ", "Bearertoken"] {
+            assert_eq!(
+                request_chat_id_status(&[("authorization", authorization), ("api_key", "token")])
+                    .await,
+                StatusCode::BAD_REQUEST,
+                "{authorization}"
+            );
+        }
+    }
+
+    async fn json_body(response: axum::response::Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        serde_json::from_slice(&body).expect("Error body is not JSON")
+    }
+
+    #[tokio::test]
+    async fn unknown_task_status_is_a_json_error() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::get("/status/unknown?chat_id=chat")
+                    .header("api_key", "token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            json_body(response).await,
+            serde_json::json!({"err": {"type": "NotFound"}, "msg": "Not found"})
+        );
+    }
+
+    #[tokio::test]
+    async fn version_is_the_crate_version() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::get("/version")
+                    .header("api_key", "token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = json_body(response).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["git_commit"].is_string());
+        assert!(body["build_timestamp"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn admin_routes_require_the_admin_token() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig {
+                admin_token: Some(String::from("admin-token")),
+                ..Default::default()
+            },
+        );
+
+        let request = |method: &str, uri: &str, token: &str| {
+            let app = api(state.clone()).with_state(state.clone());
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("api_key", token)
+                .body(Body::empty())
+                .unwrap();
+
+            async move { app.oneshot(request).await.unwrap() }
+        };
+
+        for (method, uri) in [
+            ("POST", "/admin/evict_completed"),
+            ("GET", "/stats"),
+            ("GET", "/metrics"),
+        ] {
+            let response = request(method, uri, "token").await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{uri}");
+            assert_eq!(
+                json_body(response).await["err"]["type"],
+                "AdminTokenRequired"
+            );
+
+            let response = request(method, uri, "admin-token").await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+
+        let response = request("POST", "/admin/evict_completed", "admin-token").await;
+        assert_eq!(json_body(response).await["evicted"], 0);
+
+        let response = request("GET", "/version", "token").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn admin_routes_accept_the_api_token_without_an_admin_token() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::post("/admin/evict_completed")
+                    .header("api_key", "token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn locust_converter_is_listed_as_the_default() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::get("/converters")
+                    .header("api_key", "token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = json_body(response).await;
+        let converters = body["converters"].as_array().expect("No converters");
+        let locust = converters
+            .iter()
+            .find(|converter| converter["target"] == "locust")
+            .expect("Locust converter not listed");
+        assert_eq!(locust["default"], true);
+        assert!(locust["description"].is_string());
+    }
+
+    #[tokio::test]
+    async fn unwritable_projects_dir_is_unhealthy() {
+        let healthz = |projects_dir: String| async move {
+            let state = ApiState::new(
+                HashSet::from([String::from("token")]),
+                projects_dir,
+                ApiStateConfig::default(),
+            );
+
+            let response = api(state.clone())
+                .with_state(state)
+                .oneshot(
+                    Request::get("/healthz")
+                        .header("api_key", "token")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            (response.status(), json_body(response).await)
+        };
+
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+
+        let (status, body) = healthz(projects_dir.path().to_string_lossy().to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["healthy"], true);
+        assert_eq!(body["projects_dir_writable"], true);
+        assert_eq!(std::fs::read_dir(projects_dir.path()).unwrap().count(), 0);
+
+        // Nothing can be written below a file, not even as root.
+        let file = projects_dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+
+        let (status, body) = healthz(file.to_string_lossy().to_string()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["healthy"], false);
+        assert_eq!(body["projects_dir_writable"], false);
+        assert_eq!(body["task_runner_available"], true);
+    }
+
+    #[tokio::test]
+    async fn unknown_api_route_is_a_json_error() {
+        let assets_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        std::fs::write(assets_dir.path().join("index.html"), "<html></html>").unwrap();
+
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = root(Some(assets_dir.path().to_path_buf()))
+            .nest("/api", api(state.clone()))
+            .with_state(state);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/api/does-not-exist")
+                    .header("api_key", "token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json_body(response).await["err"]["type"], "NotFound");
+
+        // Everything outside of the api is still served from the assets.
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn invalid_api_key_is_a_json_error() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::get("/status/unknown?chat_id=chat")
+                    .header("api_key", "wrong")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(json_body(response).await["err"]["type"], "ApiKeyInvalid");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn task_creation_is_rate_limited_per_chat() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        std::fs::create_dir(projects_dir.path().join("project")).unwrap();
+        std::fs::write(projects_dir.path().join("project").join("run.log"), "log").unwrap();
+
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig {
+                task_rate_per_min: 1,
+                ..Default::default()
+            },
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let convert = |chat_id: &str| {
+            Request::post(format!(
+                "/gs_log_to_locust_converter?chat_id={chat_id}&project_name=project"
+            ))
+            .header("api_key", "token")
+            .body(Body::empty())
+            .unwrap()
+        };
+
+        let response = app.clone().oneshot(convert("chat")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = app.clone().oneshot(convert("chat")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "60");
+
+        let response = app.clone().oneshot(convert("other_chat")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        tokio::time::advance(std::time::Duration::from_secs(60)).await;
+
+        let response = app.oneshot(convert("chat")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn tasks_are_filtered_by_label() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        std::fs::create_dir(projects_dir.path().join("project")).unwrap();
+        std::fs::write(projects_dir.path().join("project").join("run.log"), "log").unwrap();
+
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        for labels in ["env:prod,team:load", "env:dev"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::post(format!(
+                        "/gs_log_to_locust_converter?chat_id=chat&project_name=project&labels={labels}"
+                    ))
+                    .header("api_key", "token")
+                    .body(Body::empty())
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        let list = |query: &str| {
+            Request::get(format!("/tasks?chat_id=chat{query}"))
+                .header("api_key", "token")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(list("")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json_body(response).await["tasks"].as_array().unwrap().len(),
+            2
+        );
+
+        let response = app.clone().oneshot(list("&label=env:prod")).await.unwrap();
+        let tasks = json_body(response).await["tasks"].clone();
+        assert_eq!(tasks.as_array().unwrap().len(), 1);
+        assert_eq!(tasks[0]["labels"]["team"], "load");
+
+        let response = app.clone().oneshot(list("&label=env")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .oneshot(
+                Request::post(
+                    "/gs_log_to_locust_converter?chat_id=chat&project_name=project&labels=env",
+                )
+                .header("api_key", "token")
+                .body(Body::empty())
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn over_limit_body_is_rejected() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig {
+                max_request_body_bytes: 16,
+                ..Default::default()
+            },
+        );
+
+        let app = api(state.clone()).with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::post("/gs_log_to_locust_converter?chat_id=chat&project_name=project")
+                    .header("api_key", "token")
+                    .header("content-length", "17")
+                    .body(Body::from(vec![0; 17]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    /// Serves the api on a local port, with the connect info a WebSocket connection needs.
+    async fn serve(state: ApiState) -> std::net::SocketAddr {
+        serve_with_header_read_timeout(state, std::time::Duration::from_secs(10)).await
+    }
+
+    async fn serve_with_header_read_timeout(
+        state: ApiState,
+        header_read_timeout: std::time::Duration,
+    ) -> std::net::SocketAddr {
+        let app = Router::new()
+            .nest("/api", api(state.clone()))
+            .with_state(state);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        tokio::spawn(crate::server::serve::serve(
+            listener,
+            app,
+            None,
+            std::future::pending(),
+            std::time::Duration::from_secs(10),
+            header_read_timeout,
+        ));
+
+        addr
+    }
+
+    async fn connect_ws(
+        addr: std::net::SocketAddr,
+        api_key: &str,
+    ) -> Result<
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        tokio_tungstenite::tungstenite::Error,
+    > {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let mut request = format!("ws://{addr}/api/ws?chat_id=chat")
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("api_key", api_key.parse().unwrap());
+
+        tokio_tungstenite::connect_async(request)
+            .await
+            .map(|(stream, _)| stream)
+    }
+
+    #[tokio::test]
+    async fn ws_connection_receives_broadcast_chunks() {
+        use crate::server::ws::{IoType, ServerMessage, TaskIoChunk};
+        use futures::StreamExt;
+
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let addr = serve(state.clone()).await;
+
+        let mut stream = connect_ws(addr, "token").await.expect("Failed to connect");
+
+        // The connection is registered after the upgrade completed on the server.
+        while state.connection_count().await == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        state
+            .connection_manager()
+            .broadcast(
+                "chat",
+                ServerMessage::TaskIoChunk(TaskIoChunk {
+                    id: String::from("0"),
+                    chunk: String::from("hello"),
+                    io_type: IoType::Stdout,
+                }),
+            )
+            .await;
+
+        // Skips the ping the server sends after the upgrade. Reading answers it.
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let message = stream
+                    .next()
+                    .await
+                    .expect("Connection closed")
+                    .expect("Failed to receive message");
+
+                if !message.is_ping() {
+                    break message;
+                }
+            }
+        })
+        .await
+        .expect("No message received");
+
+        let message: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(
+            message,
+            serde_json::json!({
+                "server_message": "TaskIoChunk",
+                "content": {"id": "0", "chunk": "hello", "io_type": "Stdout"}
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn ws_connection_requires_api_key() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let addr = serve(state.clone()).await;
+
+        match connect_ws(addr, "wrong").await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            }
+            other => panic!("Expected an HTTP error, got {other:?}"),
+        }
+
+        assert_eq!(state.connection_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn open_ws_connection_counts_against_the_connection_limit() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig {
+                max_connections: 1,
+                ..Default::default()
+            },
+        );
+
+        let addr = serve(state.clone()).await;
+
+        let mut stream = connect_ws(addr, "token").await.expect("Failed to connect");
+        while state.connection_count().await == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let client = reqwest::Client::new();
+        let request_chat_id = || {
+            client
+                .get(format!("http://{addr}/api/request_chat_id"))
+                .header("api_key", "token")
+                .send()
+        };
+
+        let response = request_chat_id().await.expect("Request failed");
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        stream.close(None).await.expect("Failed to close");
+
+        // The slot is free once the server closed the connection.
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while request_chat_id().await.expect("Request failed").status()
+                != reqwest::StatusCode::OK
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Connection slot was not released");
+    }
+
+    #[tokio::test]
+    async fn ws_upgrade_request_stalled_halfway_is_timed_out() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let addr =
+            serve_with_header_read_timeout(state.clone(), std::time::Duration::from_millis(200))
+                .await;
+
+        let mut tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tcp.write_all(
+            b"GET /api/ws?chat_id=chat HTTP/1.1\r\nhost: localhost\r\nupgrade: websocket\r\n",
+        )
+        .await
+        .unwrap();
+
+        // The server closes the connection instead of waiting for the rest of the headers.
+        let mut response = Vec::new();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            tcp.read_to_end(&mut response),
+        )
+        .await
+        .expect("Stalled upgrade request was not timed out")
+        .ok();
+
+        assert_eq!(state.connection_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn ws_client_not_answering_the_ping_is_disconnected() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig {
+                ws_handshake_timeout: std::time::Duration::from_millis(200),
+                ..Default::default()
+            },
+        );
+
+        let addr = serve(state.clone()).await;
+
+        // Not reading the stream, the client does not answer the ping.
+        let mut stream = connect_ws(addr, "token").await.expect("Failed to connect");
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        assert_eq!(state.connection_count().await, 0);
+
+        let messages: Vec<Message> = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            (&mut stream)
+                .filter_map(|message| async { message.ok() })
+                .collect(),
+        )
+        .await
+        .expect("Connection was not closed");
+        assert!(messages.iter().any(Message::is_close), "{messages:?}");
+    }
+
+    #[tokio::test]
+    async fn closed_ws_connections_are_removed() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let addr = serve(state.clone()).await;
+
+        let wait_for_count = |count: usize| {
+            let state = state.clone();
+            async move {
+                tokio::time::timeout(std::time::Duration::from_secs(5), async {
+                    while state.connection_count().await != count {
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    }
+                })
+                .await
+                .unwrap_or_else(|_| panic!("Connection count did not reach {count}"));
+            }
+        };
+
+        let mut closing = connect_ws(addr, "token").await.expect("Failed to connect");
+        let dropping = connect_ws(addr, "token").await.expect("Failed to connect");
+        wait_for_count(2).await;
+
+        // A clean close
+        closing.close(None).await.expect("Failed to close");
+        wait_for_count(1).await;
+
+        // The client going away without a close frame
+        drop(dropping);
+        wait_for_count(0).await;
+    }
+
+    #[tokio::test]
+    async fn admin_port_serves_metrics_next_to_the_api() {
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let api_addr = serve(state.clone()).await;
+
+        let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let admin_addr = admin_listener.local_addr().unwrap();
+        let admin = admin().with_state(state.clone());
+        tokio::spawn(async move { axum::serve(admin_listener, admin).await });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{admin_addr}/api/metrics"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let metrics: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(metrics["connection_count"], 0);
+
+        // The api still requires an api key.
+        let response = client
+            .get(format!("http://{api_addr}/api/metrics"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let response = client
+            .get(format!("http://{api_addr}/api/request_chat_id"))
+            .header("api_key", "token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let ready_url = format!("http://{admin_addr}/ready");
+
+        let response = client.get(&ready_url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        state.shutdown();
+
+        let response = client.get(&ready_url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn prometheus_metrics_are_scraped() {
+        use crate::server::task::DownloadRetryPolicy;
+
+        crate::server::prometheus::init();
+
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig {
+                download_retry_policy: DownloadRetryPolicy {
+                    max_retries: 0,
+                    backoff: std::time::Duration::ZERO,
+                },
+                ..Default::default()
+            },
+        );
+
+        let _connection = state
+            .connection_manager()
+            .add_connection(String::from("chat"))
+            .await;
+
+        state
+            .run_download_task(
+                String::from("chat"),
+                url::Url::parse("http://127.0.0.1:1/file.zip").unwrap(),
+                String::from("project"),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let app = prometheus(state.clone()).with_state(state);
+
+        let scrape = || async {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::get("/metrics")
+                        .header("api_key", "token")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        // The task is counted as finished shortly after it failed to connect.
+        let metrics = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            loop {
+                let metrics = scrape().await;
+                if metrics.contains("job_hub_tasks_failed_total") {
+                    return metrics;
+                }
+
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The failed task was not counted");
+
+        for name in [
+            "job_hub_tasks_started_total{kind=\"download_zip_file\"}",
+            "job_hub_tasks_finished_total{kind=\"download_zip_file\"}",
+            "job_hub_tasks_failed_total{kind=\"download_zip_file\"}",
+            "job_hub_tasks_active",
+            "job_hub_ws_connections",
+            "job_hub_downloaded_bytes_total",
+        ] {
+            assert!(metrics.contains(name), "{name} missing in\n{metrics}");
+        }
+    }
+
+    #[tokio::test]
+    async fn task_events_are_streamed_as_ndjson() {
+        use crate::server::task::DownloadRetryPolicy;
+
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig {
+                download_retry_policy: DownloadRetryPolicy {
+                    max_retries: 0,
+                    backoff: std::time::Duration::ZERO,
+                },
+                ..Default::default()
+            },
+        );
+
+        let id = state
+            .run_download_task(
+                String::from("chat"),
+                url::Url::parse("http://127.0.0.1:1/file.zip").unwrap(),
+                String::from("project"),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let app = api(state.clone()).with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::get(format!("/task_events/{id}?chat_id=chat"))
+                    .header("api_key", "token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+
+        let body = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            axum::body::to_bytes(response.into_body(), usize::MAX),
+        )
+        .await
+        .expect("Stream did not end")
+        .unwrap();
+        let body = String::from_utf8(body.to_vec()).expect("Stream is not UTF-8");
+
+        assert!(body.ends_with('\n'));
+
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Line is not JSON"))
+            .collect();
+
+        let (last, messages) = lines.split_last().expect("No lines");
+        assert!(messages
+            .iter()
+            .all(|message| message["server_message"] != "Terminated"));
+        assert_eq!(last["server_message"], "Terminated");
+        assert_eq!(last["content"]["type"], "Failed");
+    }
+
+    #[tokio::test]
+    async fn request_latency_is_recorded_by_route() {
+        use crate::server::middleware::RecordLatency;
+        use tower_http::trace::TraceLayer;
+
+        crate::server::prometheus::init();
+
+        let state = ApiState::new(
+            HashSet::from([String::from("token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .nest("/api", api(state.clone()))
+            .with_state(state)
+            .layer(TraceLayer::new_for_http().on_response(RecordLatency::default()));
+
+        for path in [
+            "/api/status/first?chat_id=chat",
+            "/api/status/second?chat_id=chat",
+        ] {
+            app.clone()
+                .oneshot(
+                    Request::get(path)
+                        .header("api_key", "token")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let rendered = crate::server::prometheus::handle().render();
+
+        let count: u64 = rendered
+            .lines()
+            .find_map(|line| {
+                line.strip_prefix(
+                    "job_hub_http_request_duration_seconds_count{route=\"/api/status/:id\"} ",
+                )
+            })
+            .expect("Latency of the route not recorded")
+            .parse()
+            .unwrap();
+        assert!(count >= 2);
+
+        assert!(rendered.contains(
+            "job_hub_http_request_duration_seconds{route=\"/api/status/:id\",quantile=\"0.99\"}"
+        ));
+        assert!(!rendered.contains("route=\"/api/status/first"));
+    }
+}
@@ -0,0 +1,485 @@
+//! Routes and responses for managing project directories
+use crate::server::{
+    archive::{self, ArchiveEntry, ArchiveSource},
+    extractors::chat_id::ChatId,
+    state::{ApiState, DeleteProjectError, DownloadProjectError},
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct DeleteProjectOkResponse {
+    /// Name of the deleted project
+    #[schema(example = "project")]
+    project_name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub enum DeleteProjectErrorResponse {
+    InvalidProjectName,
+    NotFound,
+    InUse,
+    ServerError,
+}
+
+impl IntoResponse for DeleteProjectOkResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+impl IntoResponse for DeleteProjectErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            DeleteProjectErrorResponse::InvalidProjectName => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            DeleteProjectErrorResponse::NotFound => {
+                (StatusCode::NOT_FOUND, Json(self)).into_response()
+            }
+            DeleteProjectErrorResponse::InUse => (StatusCode::CONFLICT, Json(self)).into_response(),
+            DeleteProjectErrorResponse::ServerError => {
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
+            }
+        }
+    }
+}
+
+impl From<DeleteProjectError> for DeleteProjectErrorResponse {
+    fn from(err: DeleteProjectError) -> Self {
+        match err {
+            DeleteProjectError::InvalidProjectName => {
+                DeleteProjectErrorResponse::InvalidProjectName
+            }
+            DeleteProjectError::NotFound => DeleteProjectErrorResponse::NotFound,
+            DeleteProjectError::InUse => DeleteProjectErrorResponse::InUse,
+            DeleteProjectError::IoError(err) => {
+                tracing::error!(?err, "Failed to delete project");
+
+                DeleteProjectErrorResponse::ServerError
+            }
+        }
+    }
+}
+
+/// Delete a project directory and all of its files.
+///
+/// Projects that are used by a task that is not done yet can not be deleted.
+#[utoipa::path(
+    delete,
+    path = "/api/project/{project_name}",
+    params(
+        ("project_name" = String, Path, description = "Name of the project."),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    tag = "files",
+    responses(
+        (status = 200, description = "Project was deleted", body = DeleteProjectOkResponse, example = json!(DeleteProjectOkResponse{project_name: String::from("project")})),
+        (status = 404, description = "Project not found", body = DeleteProjectErrorResponse, example = json!(DeleteProjectErrorResponse::NotFound)),
+        (status = 409, description = "Project is used by a running task", body = DeleteProjectErrorResponse, example = json!(DeleteProjectErrorResponse::InUse)),
+        (status = 400, description = "Chat id missing. Api key missing. Invalid project name"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn delete_project(
+    State(state): State<ApiState>,
+    Path(project_name): Path<String>,
+    ChatId(_chat_id): ChatId,
+) -> Result<DeleteProjectOkResponse, DeleteProjectErrorResponse> {
+    state.delete_project(&project_name).await?;
+
+    Ok(DeleteProjectOkResponse { project_name })
+}
+
+#[derive(Serialize, ToSchema)]
+pub enum DownloadProjectErrorResponse {
+    InvalidProjectName,
+    InvalidFileName(String),
+    /// The file name is taken by the manifest of the archive
+    ReservedFileName(String),
+    NotFound,
+    ServerError,
+}
+
+impl IntoResponse for DownloadProjectErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            DownloadProjectErrorResponse::InvalidProjectName
+            | DownloadProjectErrorResponse::InvalidFileName(_)
+            | DownloadProjectErrorResponse::ReservedFileName(_) => {
+                (StatusCode::BAD_REQUEST, Json(self)).into_response()
+            }
+            DownloadProjectErrorResponse::NotFound => {
+                (StatusCode::NOT_FOUND, Json(self)).into_response()
+            }
+            DownloadProjectErrorResponse::ServerError => {
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
+            }
+        }
+    }
+}
+
+impl From<DownloadProjectError> for DownloadProjectErrorResponse {
+    fn from(err: DownloadProjectError) -> Self {
+        match err {
+            DownloadProjectError::InvalidProjectName => {
+                DownloadProjectErrorResponse::InvalidProjectName
+            }
+            DownloadProjectError::InvalidFileName(file_name) => {
+                DownloadProjectErrorResponse::InvalidFileName(file_name)
+            }
+            DownloadProjectError::NotFound => DownloadProjectErrorResponse::NotFound,
+            DownloadProjectError::IoError(err) => {
+                tracing::error!(?err, "Failed to read project");
+
+                DownloadProjectErrorResponse::ServerError
+            }
+        }
+    }
+}
+
+/// Download a project directory as a zip archive.
+///
+/// The archive is built while it is sent.
+#[utoipa::path(
+    get,
+    path = "/api/download_project/{project_name}",
+    params(
+        ("project_name" = String, Path, description = "Name of the project."),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    tag = "files",
+    responses(
+        (status = 200, description = "Zip archive of the project", content_type = "application/zip"),
+        (status = 404, description = "Project not found", body = DownloadProjectErrorResponse, example = json!(DownloadProjectErrorResponse::NotFound)),
+        (status = 400, description = "Chat id missing. Api key missing. Invalid project name"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn download_project(
+    State(state): State<ApiState>,
+    Path(project_name): Path<String>,
+    ChatId(_chat_id): ChatId,
+) -> Result<Response, DownloadProjectErrorResponse> {
+    let entries = state.project_archive_entries(&project_name).await?;
+
+    Ok(zip_response(&project_name, entries))
+}
+
+/// Name of the archive entry listing the selected files.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(Deserialize, ToSchema)]
+pub struct DownloadProjectFilesBody {
+    /// File names relative to the project directory
+    #[schema(example = json!(["file_1.log", "nested/file_2.log"]))]
+    files: Vec<String>,
+}
+
+/// Written as the last entry of an archive of selected files.
+#[derive(Serialize, ToSchema)]
+pub struct DownloadProjectFilesManifest {
+    /// Selected files that are in the archive
+    included: Vec<String>,
+    /// Selected files that do not exist in the project
+    missing: Vec<String>,
+}
+
+/// Download selected files of a project as a zip archive.
+///
+/// Files that do not exist are not an error. They are listed as `missing` in the `manifest.json` entry at the end of the archive.
+/// A file named like the manifest can not be selected.
+#[utoipa::path(
+    post,
+    path = "/api/download_project/{project_name}",
+    params(
+        ("project_name" = String, Path, description = "Name of the project."),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    request_body = DownloadProjectFilesBody,
+    tag = "files",
+    responses(
+        (status = 200, description = "Zip archive of the selected files and a manifest", content_type = "application/zip"),
+        (status = 404, description = "Project not found", body = DownloadProjectErrorResponse, example = json!(DownloadProjectErrorResponse::NotFound)),
+        (status = 400, description = "Chat id missing. Api key missing. Invalid project name. Invalid file name. File name taken by the manifest", body = DownloadProjectErrorResponse, example = json!(DownloadProjectErrorResponse::InvalidFileName(String::from("../file.log")))),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn download_project_files(
+    State(state): State<ApiState>,
+    Path(project_name): Path<String>,
+    ChatId(_chat_id): ChatId,
+    Json(body): Json<DownloadProjectFilesBody>,
+) -> Result<Response, DownloadProjectErrorResponse> {
+    let selected = state
+        .selected_archive_entries(&project_name, body.files)
+        .await?;
+
+    // Otherwise the archive had two entries of that name.
+    if let Some(file_name) = selected
+        .entries
+        .iter()
+        .map(|entry| &entry.name)
+        .chain(&selected.missing)
+        .find(|file_name| *file_name == MANIFEST_FILE_NAME)
+    {
+        return Err(DownloadProjectErrorResponse::ReservedFileName(
+            file_name.clone(),
+        ));
+    }
+
+    let manifest = DownloadProjectFilesManifest {
+        included: selected
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect(),
+        missing: selected.missing,
+    };
+
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(|err| {
+        tracing::error!(?err, "Failed to serialize manifest");
+
+        DownloadProjectErrorResponse::ServerError
+    })?;
+
+    let mut entries = selected.entries;
+    entries.push(ArchiveEntry {
+        name: String::from(MANIFEST_FILE_NAME),
+        source: ArchiveSource::Bytes(manifest),
+    });
+
+    Ok(zip_response(&project_name, entries))
+}
+
+fn zip_response(project_name: &str, entries: Vec<ArchiveEntry>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, String::from("application/zip")),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{project_name}.zip\""),
+            ),
+        ],
+        archive::zip_stream(entries),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::state::ApiStateConfig;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use std::io::Read;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn download_project_zips_all_files() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir_all(project_dir.join("nested")).unwrap();
+        std::fs::write(project_dir.join("a.log"), "first file").unwrap();
+        std::fs::write(project_dir.join("nested/b.log"), "second file").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route("/download_project/:project_name", get(download_project))
+            .with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::get("/download_project/project?chat_id=chat")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+
+        let mut names: Vec<String> = archive.file_names().map(String::from).collect();
+        names.sort();
+        assert_eq!(names, ["a.log", "nested/b.log"]);
+
+        let mut content = String::new();
+        archive
+            .by_name("nested/b.log")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "second file");
+    }
+
+    /// The project directory is returned as well, the files are read while the body streams.
+    async fn post_selected(files: serde_json::Value) -> (Response, tempfile::TempDir) {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir_all(project_dir.join("nested")).unwrap();
+        std::fs::write(project_dir.join("a.log"), "first file").unwrap();
+        std::fs::write(project_dir.join("nested/b.log"), "second file").unwrap();
+        std::fs::write(project_dir.join("c.log"), "not selected").unwrap();
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let app = Router::new()
+            .route(
+                "/download_project/:project_name",
+                axum::routing::post(download_project_files),
+            )
+            .with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::post("/download_project/project?chat_id=chat")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "files": files }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        (response, projects_dir)
+    }
+
+    async fn download_selected(
+        files: serde_json::Value,
+    ) -> zip::ZipArchive<std::io::Cursor<axum::body::Bytes>> {
+        let (response, _projects_dir) = post_selected(files).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap()
+    }
+
+    fn manifest(
+        archive: &mut zip::ZipArchive<std::io::Cursor<axum::body::Bytes>>,
+    ) -> serde_json::Value {
+        let mut manifest = String::new();
+        archive
+            .by_name(MANIFEST_FILE_NAME)
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+
+        serde_json::from_str(&manifest).unwrap()
+    }
+
+    #[tokio::test]
+    async fn download_project_files_zips_selected_files() {
+        let mut archive = download_selected(serde_json::json!(["a.log", "nested/b.log"])).await;
+
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(names.len(), 3);
+        assert!(!names.contains(&"c.log"));
+
+        assert_eq!(
+            manifest(&mut archive),
+            serde_json::json!({ "included": ["a.log", "nested/b.log"], "missing": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn download_project_files_reports_missing_files() {
+        let mut archive = download_selected(serde_json::json!(["a.log", "missing.log"])).await;
+
+        assert!(archive.by_name("a.log").is_ok());
+        assert!(archive.by_name("missing.log").is_err());
+
+        assert_eq!(
+            manifest(&mut archive),
+            serde_json::json!({ "included": ["a.log"], "missing": ["missing.log"] })
+        );
+    }
+
+    #[tokio::test]
+    async fn download_project_files_zips_a_file_selected_twice_once() {
+        let mut archive = download_selected(serde_json::json!([
+            "a.log",
+            "nested//b.log",
+            "a.log",
+            "nested/b.log"
+        ]))
+        .await;
+
+        assert_eq!(archive.len(), 3);
+        assert_eq!(
+            manifest(&mut archive),
+            serde_json::json!({ "included": ["a.log", "nested/b.log"], "missing": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn download_project_files_rejects_the_manifest_name() {
+        let (response, _projects_dir) =
+            post_selected(serde_json::json!(["a.log", MANIFEST_FILE_NAME])).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn download_project_rejects_traversal() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+
+        let state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let err = state
+            .project_archive_entries("..")
+            .await
+            .expect_err("Traversal must be rejected");
+
+        assert!(matches!(err, DownloadProjectError::InvalidProjectName));
+
+        std::fs::create_dir(projects_dir.path().join("project")).unwrap();
+
+        let err = state
+            .selected_archive_entries("project", vec![String::from("../secret")])
+            .await
+            .expect_err("Traversal must be rejected");
+
+        assert!(matches!(err, DownloadProjectError::InvalidFileName(_)));
+    }
+}
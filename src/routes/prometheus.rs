@@ -0,0 +1,32 @@
+use crate::server::prometheus;
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// Get server metrics in the Prometheus text format
+///
+/// Task counters by kind, active tasks, WebSocket connections, downloaded bytes and request latency quantiles by route.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain", example = json!("# TYPE job_hub_tasks_started_total counter\njob_hub_tasks_started_total{kind=\"download_zip_file\"} 3\n")),
+        (status = 400, description = "Api key missing"),
+        (status = 401, description = "Api key invalid"),
+        (status = 403, description = "Admin token required. The api key is not the admin token"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn prometheus_metrics() -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        prometheus::handle().render(),
+    )
+        .into_response()
+}
@@ -35,6 +35,7 @@ impl IntoResponse for RequestChatIdReponse {
     ),
     security(
         ("api_key" = []),
+        ("bearer_token" = []),
     ),
 )]
 pub async fn request_chat_id(State(state): State<ApiState>) -> RequestChatIdReponse {
@@ -0,0 +1,111 @@
+use crate::server::{
+    extractors::chat_id::ChatId,
+    response::ApiError,
+    state::{ApiState, ConverterError, ResubmitTaskError, RunDownloadTaskError},
+    utils::retry_after_secs,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct ResubmitOkResponse {
+    /// Id of the new task
+    #[schema(example = "1")]
+    id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "error", content = "content")]
+pub enum ResubmitErrorResponse {
+    NotFound,
+    /// The parameters of the task are not accepted anymore, e.g. its project was deleted
+    Rejected(String),
+    RateLimited {
+        retry_after_secs: u64,
+    },
+    ServerError(ApiError),
+}
+
+impl From<ResubmitTaskError> for ResubmitErrorResponse {
+    fn from(err: ResubmitTaskError) -> Self {
+        match err {
+            ResubmitTaskError::NotFound => ResubmitErrorResponse::NotFound,
+            ResubmitTaskError::Download(RunDownloadTaskError::RateLimited { retry_after })
+            | ResubmitTaskError::Converter(ConverterError::RateLimited { retry_after }) => {
+                ResubmitErrorResponse::RateLimited {
+                    retry_after_secs: retry_after_secs(retry_after),
+                }
+            }
+            ResubmitTaskError::Download(RunDownloadTaskError::IoError(err))
+            | ResubmitTaskError::Converter(ConverterError::IoError(err)) => {
+                ResubmitErrorResponse::ServerError(err.into())
+            }
+            err => ResubmitErrorResponse::Rejected(err.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ResubmitOkResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::CREATED, Json(self)).into_response()
+    }
+}
+
+impl IntoResponse for ResubmitErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ResubmitErrorResponse::NotFound => (StatusCode::NOT_FOUND, Json(self)).into_response(),
+            ResubmitErrorResponse::Rejected(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+            }
+            ResubmitErrorResponse::RateLimited { retry_after_secs } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(self),
+            )
+                .into_response(),
+            ResubmitErrorResponse::ServerError(err) => err.into_response(),
+        }
+    }
+}
+
+/// Start a task again
+///
+/// The new task gets the parameters and options of the given task, e.g. its download url or conversion target, labels and priority.
+/// The given task may still be running. It is not changed.
+#[utoipa::path(
+    post,
+    path = "/api/resubmit/{id}",
+    params(
+        ("id" = String, Path, description = "Id of the task to start again.", example = "0"),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint."),
+    ),
+    tag = "task",
+    responses(
+        (status = 201, description = "New task was scheduled for running", body = ResubmitOkResponse, example = json!(ResubmitOkResponse{id: String::from("1")})),
+        (status = 404, description = "Task not found for this chat id", body = ResubmitErrorResponse, example = json!(ResubmitErrorResponse::NotFound)),
+        (status = 422, description = "The parameters of the task are not accepted anymore", body = ResubmitErrorResponse, example = json!(ResubmitErrorResponse::Rejected(String::from("Project not found")))),
+        (status = 429, description = "Too many tasks started by this chat. See the `Retry-After` header", body = ResubmitErrorResponse, example = json!(ResubmitErrorResponse::RateLimited{retry_after_secs: 30})),
+        (status = 400, description = "Chat id missing. Api key missing"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn resubmit(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    ChatId(chat_id): ChatId,
+) -> Result<ResubmitOkResponse, ResubmitErrorResponse> {
+    let id = state.resubmit_task(&id, &chat_id).await?;
+
+    Ok(ResubmitOkResponse { id })
+}
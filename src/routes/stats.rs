@@ -0,0 +1,54 @@
+use crate::server::{state::ApiState, stats::Stats};
+use axum::{extract::State, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct StatsResponse {
+    /// Number of tasks started since the server started
+    #[schema(example = 12)]
+    tasks_total: u64,
+    /// Number of tasks waiting to run
+    #[schema(example = 0)]
+    tasks_queued: u64,
+    /// Number of tasks running right now
+    #[schema(example = 1)]
+    tasks_running: u64,
+    /// Bytes downloaded by download tasks, including failed attempts
+    #[schema(example = 1048576)]
+    bytes_downloaded: u64,
+    #[schema(example = 3600)]
+    uptime_secs: u64,
+}
+
+impl From<Stats> for StatsResponse {
+    fn from(stats: Stats) -> Self {
+        Self {
+            tasks_total: stats.tasks_total,
+            tasks_queued: stats.tasks_queued,
+            tasks_running: stats.tasks_running,
+            bytes_downloaded: stats.bytes_downloaded,
+            uptime_secs: stats.uptime.as_secs(),
+        }
+    }
+}
+
+/// Get aggregate server statistics
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Server statistics", body = StatsResponse),
+        (status = 400, description = "Api key missing"),
+        (status = 401, description = "Api key invalid"),
+        (status = 403, description = "Admin token required. The api key is not the admin token"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn stats(State(state): State<ApiState>) -> Json<StatsResponse> {
+    Json(state.stats().into())
+}
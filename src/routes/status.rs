@@ -1,7 +1,8 @@
 use crate::server::{
-    extractors::chat_id::ChatId,
+    extractors::{chat_id::ChatId, query::Query},
+    response::ApiError,
     state::ApiState,
-    task::{ProcessStatus, Status},
+    task::{FailureKind, ProcessStatus, Status},
 };
 use axum::{
     extract::{Path, State},
@@ -9,18 +10,29 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
 use utoipa::ToSchema;
 
+/// Longest a status request is held open, whatever `wait` asks for.
+const MAX_WAIT_SECS: u64 = 60;
+
+#[derive(Deserialize)]
+pub struct StatusQuery {
+    /// Seconds to wait for the status of a running task to change
+    wait: Option<u64>,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct StatusOkReponse {
     /// Status of a given task
     status: Status,
-}
-
-#[derive(Serialize, ToSchema)]
-pub enum StatusErrorReponse {
-    NotFound,
+    /// Directory the task wrote its results to. Set once the task is done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    work_dir: Option<String>,
+    /// Files in `work_dir`, relative to it. Set once the task is done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifacts: Option<Vec<String>>,
 }
 
 impl IntoResponse for StatusOkReponse {
@@ -29,40 +41,112 @@ impl IntoResponse for StatusOkReponse {
     }
 }
 
-impl IntoResponse for StatusErrorReponse {
-    fn into_response(self) -> Response {
-        (StatusCode::NOT_FOUND, Json(self)).into_response()
-    }
-}
-
 /// Get the status of a task
+///
+/// With `wait`, the request is held open until the status changes or `wait` seconds passed, instead of polling in a loop.
 #[utoipa::path(
     get,
     path = "/api/status/{id}", 
     params(
         ("id" = String, Path, description = "Task id. generated using the `/api/download_zip_file` endpoint."),
-        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint."),
+        ("wait" = Option<u64>, Query, description = "Seconds to wait for the status to change before responding. At most 60.")
     ),
     tag = "task",
     responses(
-        (status = 200, description = "Status of a given task", body = StatusOkReponse, example = json!(StatusOkReponse{status: Status::Process(ProcessStatus::Running)})),
-        (status = 404, description = "Task not found for this chat id", body = StatusErrorReponse, example = json!(StatusErrorReponse::NotFound)),
-        (status = 400, description = "Chat id missing. Api key missing."),
+        (status = 200, description = "Status of a given task", body = StatusOkReponse, examples(
+            ("Running" = (summary = "A running OS process", value = json!(StatusOkReponse{status: Status::Process(ProcessStatus::Running), work_dir: None, artifacts: None}))),
+            ("Failed" = (summary = "A task that errored out", value = json!(StatusOkReponse{status: Status::Failed{reason: String::from("Command `locust` not found"), kind: FailureKind::Spawn}, work_dir: None, artifacts: None}))),
+        )),
+        (status = 404, description = "Task not found for this chat id", example = json!({"err": {"type": "NotFound"}, "msg": "Not found"})),
+        (status = 400, description = "Chat id missing. Api key missing. Query invalid"),
         (status = 401, description = "Api key invalid"),
     ),
     security(
         ("api_key" = []),
+        ("bearer_token" = []),
     ),
 )]
 pub async fn status(
     State(state): State<ApiState>,
     Path(id): Path<String>,
     ChatId(chat_id): ChatId,
-) -> Result<StatusOkReponse, StatusErrorReponse> {
-    let status = state
-        .task_status(&id, &chat_id)
-        .await
-        .ok_or(StatusErrorReponse::NotFound)?;
+    Query(query): Query<StatusQuery>,
+) -> Result<StatusOkReponse, ApiError> {
+    let details = match query.wait {
+        Some(wait) => {
+            let wait = Duration::from_secs(wait.min(MAX_WAIT_SECS));
+
+            state.wait_for_task_details(&id, &chat_id, wait).await
+        }
+        None => state.task_details(&id, &chat_id).await,
+    }
+    .ok_or(ApiError::NotFound)?;
+
+    Ok(StatusOkReponse {
+        status: details.status,
+        work_dir: details
+            .work_dir
+            .map(|work_dir| work_dir.to_string_lossy().to_string()),
+        artifacts: details.artifacts,
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct StatusBulkBody {
+    /// Task ids. generated using the `/api/download_zip_file` endpoint.
+    #[schema(example = json!(["1", "2"]))]
+    ids: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StatusBulkOkResponse {
+    /// Status by task id
+    statuses: HashMap<String, Status>,
+    /// Requested ids that are unknown or belong to another chat
+    not_found: Vec<String>,
+}
+
+impl IntoResponse for StatusBulkOkResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+/// Get the statuses of many tasks at once
+#[utoipa::path(
+    post,
+    path = "/api/status_bulk",
+    params(
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    request_body = StatusBulkBody,
+    tag = "task",
+    responses(
+        (status = 200, description = "Statuses of the tasks of this chat id", body = StatusBulkOkResponse, example = json!(StatusBulkOkResponse{statuses: HashMap::from([(String::from("1"), Status::Process(ProcessStatus::Running))]), not_found: vec![String::from("2")]})),
+        (status = 400, description = "Chat id missing. Api key missing. Body invalid"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn status_bulk(
+    State(state): State<ApiState>,
+    ChatId(chat_id): ChatId,
+    Json(body): Json<StatusBulkBody>,
+) -> StatusBulkOkResponse {
+    let statuses = state.task_statuses(&body.ids, &chat_id).await;
 
-    Ok(StatusOkReponse { status })
+    let not_found = body
+        .ids
+        .into_iter()
+        .filter(|id| !statuses.contains_key(id))
+        .collect();
+
+    StatusBulkOkResponse {
+        statuses,
+        not_found,
+    }
 }
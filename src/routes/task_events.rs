@@ -0,0 +1,73 @@
+use crate::server::{
+    extractors::chat_id::ChatId,
+    response::ApiError,
+    state::{ApiState, TaskEvent},
+};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+
+/// A line of the stream, without the trailing newline.
+///
+/// The terminal status is tagged like a `ServerMessage`, as `Terminated`, so every line has the same shape.
+fn event_line(event: TaskEvent) -> Result<Vec<u8>, serde_json::Error> {
+    match event {
+        TaskEvent::Message(message) => serde_json::to_vec(&message),
+        TaskEvent::Terminated(status) => serde_json::to_vec(&serde_json::json!({
+            "server_message": "Terminated",
+            "content": status,
+        })),
+    }
+}
+
+/// Stream the IO of a task as newline delimited JSON
+///
+/// For programmatic clients that would rather read a response body than handle server-sent events.
+/// Every line holds a `ServerMessage` of the task.
+/// The last line is a `Terminated` message holding the final status of the task, then the stream ends.
+#[utoipa::path(
+    get,
+    path = "/api/task_events/{id}",
+    params(
+        ("id" = String, Path, description = "Task id. generated using the `/api/gs_log_to_locust_converter` endpoint."),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    tag = "task",
+    responses(
+        (status = 200, description = "NDJSON stream of the task", content_type = "application/x-ndjson", example = json!("{\"server_message\":\"TaskIoChunk\",\"content\":{\"id\":\"0\",\"chunk\":\"line 1\\n\",\"io_type\":\"Stdout\"}}\n{\"server_message\":\"Terminated\",\"content\":{\"type\":\"Failed\",\"content\":{\"reason\":\"Command not found\"}}}\n")),
+        (status = 404, description = "Task not found for this chat id", example = json!({"err": {"type": "NotFound"}, "msg": "Not found"})),
+        (status = 400, description = "Chat id missing. Api key missing."),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn task_events(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    ChatId(chat_id): ChatId,
+) -> Result<Response, ApiError> {
+    let events = state
+        .task_events(&id, &chat_id)
+        .await
+        .ok_or(ApiError::NotFound)?;
+
+    let lines = events.map(|event| {
+        event_line(event).map(|mut line| {
+            line.push(b'\n');
+            line
+        })
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
@@ -0,0 +1,57 @@
+use crate::server::{
+    extractors::{chat_id::ChatId, query::Query},
+    response::ApiError,
+    state::{ApiState, TaskOutputError},
+    ws::IoType,
+};
+use axum::extract::{Path, State};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct TaskOutputQuery {
+    /// Defaults to stdout
+    io_type: Option<IoType>,
+}
+
+/// Get the output of a task as text/plain
+///
+/// Everything the task wrote to stdout or stderr so far, for clients that do not use the WebSocket.
+/// Unless task output is persisted on the server, only the most recent output is kept.
+#[utoipa::path(
+    get,
+    path = "/api/task_output/{id}",
+    params(
+        ("id" = String, Path, description = "Task id. generated using the `/api/gs_log_to_locust_converter` endpoint."),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint."),
+        ("io_type" = Option<String>, Query, description = "`stdout` or `stderr`. Defaults to `stdout`.")
+    ),
+    tag = "task",
+    responses(
+        (status = 200, description = "Output of the task", body = String),
+        (status = 404, description = "Task not found for this chat id", example = json!({"err": {"type": "NotFound"}, "msg": "Not found"})),
+        (status = 400, description = "Chat id missing. Api key missing. Query invalid"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn task_output(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    ChatId(chat_id): ChatId,
+    Query(query): Query<TaskOutputQuery>,
+) -> Result<String, ApiError> {
+    let io_type = query.io_type.unwrap_or(IoType::Stdout);
+
+    let output = state
+        .task_output(&id, &chat_id, io_type)
+        .await
+        .map_err(|err| match err {
+            TaskOutputError::NotFound => ApiError::NotFound,
+            TaskOutputError::IoError(err) => ApiError::from(err),
+        })?;
+
+    Ok(output)
+}
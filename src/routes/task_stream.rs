@@ -0,0 +1,52 @@
+use crate::server::{
+    extractors::chat_id::ChatId,
+    response::ApiError,
+    state::{ApiState, TaskEvent},
+};
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{Stream, StreamExt};
+
+/// Stream the IO of a task as server-sent events
+///
+/// An alternative to the WebSocket for clients behind proxies that do not support it.
+/// Every `message` event holds a `ServerMessage` of the task.
+/// The stream ends with a `terminated` event holding the final status of the task.
+#[utoipa::path(
+    get,
+    path = "/api/task_stream/{id}",
+    params(
+        ("id" = String, Path, description = "Task id. generated using the `/api/gs_log_to_locust_converter` endpoint."),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    tag = "task",
+    responses(
+        (status = 200, description = "Event stream of the task", content_type = "text/event-stream"),
+        (status = 404, description = "Task not found for this chat id", example = json!({"err": {"type": "NotFound"}, "msg": "Not found"})),
+        (status = 400, description = "Chat id missing. Api key missing."),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn task_stream(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    ChatId(chat_id): ChatId,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    let events = state
+        .task_events(&id, &chat_id)
+        .await
+        .ok_or(ApiError::NotFound)?;
+
+    let events = events.map(|event| match event {
+        TaskEvent::Message(message) => Event::default().event("message").json_data(message),
+        TaskEvent::Terminated(status) => Event::default().event("terminated").json_data(status),
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
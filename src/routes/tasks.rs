@@ -0,0 +1,151 @@
+use crate::server::{
+    extractors::{chat_id::ChatId, query::Query},
+    labels::parse_label,
+    response::ApiError,
+    state::{ApiState, DeleteTaskError, TaskSummary},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize)]
+pub struct ListTasksQuery {
+    /// Only tasks with this `key:value` label
+    label: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListTasksResponse {
+    pub tasks: Vec<TaskSummary>,
+}
+
+/// List the tasks of a chat
+///
+/// Tasks are ordered by id. Use `label` to only get the tasks started with that label.
+#[utoipa::path(
+    get,
+    path = "/api/tasks",
+    params(
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint."),
+        ("label" = Option<String>, Query, description = "Only tasks with this `key:value` label, e.g. `env:prod`.")
+    ),
+    tag = "task",
+    responses(
+        (status = 200, description = "Tasks of the chat", body = ListTasksResponse),
+        (status = 400, description = "Chat id missing. Api key missing. Query invalid"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn list_tasks(
+    State(state): State<ApiState>,
+    ChatId(chat_id): ChatId,
+    Query(query): Query<ListTasksQuery>,
+) -> Result<Json<ListTasksResponse>, ApiError> {
+    let label = query
+        .label
+        .as_deref()
+        .map(parse_label)
+        .transpose()
+        .map_err(|_| ApiError::QueryInvalid)?;
+
+    let tasks = state.list_tasks(&chat_id, label).await;
+
+    Ok(Json(ListTasksResponse { tasks }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeleteTaskOkResponse {
+    /// Id of the deleted task
+    #[schema(example = "0")]
+    id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub enum DeleteTaskErrorResponse {
+    NotFound,
+    ProjectInUse,
+    StillRunning,
+    ServerError,
+}
+
+impl IntoResponse for DeleteTaskOkResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+impl IntoResponse for DeleteTaskErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            DeleteTaskErrorResponse::NotFound => {
+                (StatusCode::NOT_FOUND, Json(self)).into_response()
+            }
+            DeleteTaskErrorResponse::ProjectInUse | DeleteTaskErrorResponse::StillRunning => {
+                (StatusCode::CONFLICT, Json(self)).into_response()
+            }
+            DeleteTaskErrorResponse::ServerError => {
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
+            }
+        }
+    }
+}
+
+impl From<DeleteTaskError> for DeleteTaskErrorResponse {
+    fn from(err: DeleteTaskError) -> Self {
+        match err {
+            DeleteTaskError::NotFound => DeleteTaskErrorResponse::NotFound,
+            DeleteTaskError::ProjectInUse => DeleteTaskErrorResponse::ProjectInUse,
+            DeleteTaskError::StillRunning => DeleteTaskErrorResponse::StillRunning,
+            DeleteTaskError::IoError(err) => {
+                tracing::error!(?err, "Failed to delete task");
+
+                DeleteTaskErrorResponse::ServerError
+            }
+        }
+    }
+}
+
+/// Cancel a task and delete it together with its project directory
+///
+/// A running task is canceled first and given the cancel grace, plus the time to kill it, to terminate.
+/// Tasks whose project is used by another task that is not done yet can not be deleted.
+#[utoipa::path(
+    delete,
+    path = "/api/task/{id}",
+    params(
+        ("id" = String, Path, description = "Task id. generated using the `/api/download_zip_file` endpoint.", example = "0"),
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    tag = "task",
+    responses(
+        (status = 200, description = "Task and its project were deleted", body = DeleteTaskOkResponse, example = json!(DeleteTaskOkResponse{id: String::from("0")})),
+        (status = 404, description = "Task not found for this chat id", body = DeleteTaskErrorResponse, example = json!(DeleteTaskErrorResponse::NotFound)),
+        (status = 409, description = "Project is used by another running task, or the task did not terminate in time", body = DeleteTaskErrorResponse, example = json!(DeleteTaskErrorResponse::StillRunning)),
+        (status = 400, description = "Chat id missing. Api key missing"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn delete_task(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    ChatId(chat_id): ChatId,
+) -> Result<DeleteTaskOkResponse, DeleteTaskErrorResponse> {
+    state
+        .delete_task(&id, &chat_id, state.cancel_wait())
+        .await?;
+
+    Ok(DeleteTaskOkResponse { id })
+}
@@ -0,0 +1,57 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct VersionResponse {
+    /// Version of the server
+    #[schema(example = "0.1.0")]
+    version: &'static str,
+    /// Commit the server was built from. `unknown` if it was not known at build time
+    #[schema(example = "871c0cf3a9a1e0d6b0b7d4f3c2e1a0b9c8d7e6f5")]
+    git_commit: &'static str,
+    /// Seconds since the unix epoch
+    #[schema(example = 1760400000)]
+    build_timestamp: u64,
+}
+
+impl VersionResponse {
+    fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("JOB_HUB_GIT_COMMIT"),
+            build_timestamp: env!("JOB_HUB_BUILD_TIMESTAMP")
+                .parse()
+                .expect("The build script writes a number"),
+        }
+    }
+}
+
+impl IntoResponse for VersionResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+/// Get the version of the server and when it was built
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Version and build info", body = VersionResponse),
+        (status = 400, description = "Api key missing"),
+        (status = 401, description = "Api key invalid"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn version() -> VersionResponse {
+    VersionResponse::current()
+}
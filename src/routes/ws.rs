@@ -0,0 +1,53 @@
+use crate::server::{extractors::chat_id::ChatId, middleware::ConnectionPermit, state::ApiState};
+use axum::{
+    extract::{ConnectInfo, State, WebSocketUpgrade},
+    response::Response,
+    Extension,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use std::net::SocketAddr;
+
+/// Open a WebSocket connection
+///
+/// The connection receives the IO of the tasks of its chat as `ServerMessage`s and may send `ClientMessage`s,
+/// e.g. to subscribe to a single task, to replay a task's recent output or to tail a file of a project.
+///
+/// The server pings the client after the upgrade. A client that neither answers it nor sends a message within
+/// the handshake timeout is disconnected.
+#[utoipa::path(
+    get,
+    path = "/api/ws",
+    params(
+        ("chat_id" = String, Query, description = "Chat id. generated using the `/api/request_chat_id` endpoint.")
+    ),
+    tag = "task",
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 400, description = "Chat id missing. Api key missing. Not a WebSocket upgrade request"),
+        (status = 401, description = "Api key invalid"),
+        (status = 503, description = "Too many connections"),
+    ),
+    security(
+        ("api_key" = []),
+        ("bearer_token" = []),
+    ),
+)]
+pub async fn ws(
+    State(state): State<ApiState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    permit: Option<Extension<ConnectionPermit>>,
+    ChatId(chat_id): ChatId,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let user_agent = user_agent.map(|TypedHeader(user_agent)| user_agent.to_string());
+
+    upgrade.on_upgrade(move |socket| async move {
+        // The connection counts against the connection limit until it closes.
+        let _permit = permit;
+
+        state
+            .accept_connection(socket, chat_id, addr, user_agent)
+            .await
+    })
+}
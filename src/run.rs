@@ -0,0 +1,141 @@
+use crate::server::task::{ExitedStatus, FailureKind, ProcessStatus, Status, Task, TaskStdin};
+use std::{future::Future, time::Duration};
+
+/// Run a single OS process the way the server runs a task, without the server.
+///
+/// It reads the stdin of this process. Its stdout and stderr are streamed to the stdout and stderr of this process.
+/// Once `cancel` completes, the process is canceled like a task canceled through the api.
+pub async fn run_task(
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+    cancel_grace: Duration,
+    cancel: impl Future<Output = ()>,
+) -> Status {
+    let (task, handle) = Task::new(String::from("0"));
+    let task = task.with_stdin(TaskStdin::Inherit);
+
+    // The process' output is copied by detached tasks. Reading it through pipes lets us wait until all of it is written.
+    let (stdout_tx, mut stdout_rx) = tokio::io::duplex(8192);
+    let (stderr_tx, mut stderr_rx) = tokio::io::duplex(8192);
+
+    let running = async {
+        let running = task.run_os_process(
+            command,
+            args,
+            timeout,
+            cancel_grace,
+            Some(stdout_tx),
+            Some(stderr_tx),
+        );
+        tokio::pin!(running);
+
+        tokio::select! {
+            _ = &mut running => {},
+            _ = cancel => {
+                handle.send_cancel_signal().await;
+                running.await;
+            }
+        }
+    };
+
+    let mut stdout = tokio::io::stdout();
+    let mut stderr = tokio::io::stderr();
+
+    let (_, stdout, stderr) = tokio::join!(
+        running,
+        tokio::io::copy(&mut stdout_rx, &mut stdout),
+        tokio::io::copy(&mut stderr_rx, &mut stderr),
+    );
+
+    if let Err(err) = stdout.and(stderr) {
+        tracing::error!(?err, "Failed to write the output of the command");
+    }
+
+    handle.status().await
+}
+
+/// Exit code of this process for the final status of [`run_task`].
+///
+/// Follows the shell conventions: the code of the process if it exited, `124` on timeout,
+/// `127` if it could not be started and `130` if it was canceled.
+pub fn exit_code(status: &Status) -> u8 {
+    match status {
+        Status::Process(ProcessStatus::Exited {
+            exit_status: ExitedStatus::Success,
+        }) => 0,
+        Status::Process(ProcessStatus::Exited {
+            exit_status: ExitedStatus::Failure { code },
+        }) => code
+            .and_then(|code| u8::try_from(code).ok())
+            .filter(|code| *code != 0)
+            .unwrap_or(1),
+        Status::Process(ProcessStatus::Timeout) => 124,
+        Status::Process(ProcessStatus::Canceled | ProcessStatus::Killed) => 130,
+        Status::Failed {
+            kind: FailureKind::Spawn,
+            ..
+        } => 127,
+        Status::Process(ProcessStatus::Created | ProcessStatus::Running)
+        | Status::Download(_)
+        | Status::Failed { .. } => 1,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::cli_args::{CliArgs, Command};
+    use clap::Parser;
+
+    async fn run_parsed(cli: &[&str]) -> u8 {
+        let cli_args = CliArgs::try_parse_from(cli).expect("Failed to parse args");
+
+        let Some(Command::Run(run_args)) = cli_args.command else {
+            panic!("Expected the run subcommand");
+        };
+
+        let status = run_task(
+            run_args.command,
+            run_args.args,
+            Duration::from_secs(run_args.timeout_secs),
+            Duration::from_secs(cli_args.cancel_grace_secs),
+            std::future::pending(),
+        )
+        .await;
+
+        exit_code(&status)
+    }
+
+    #[tokio::test]
+    async fn run_subcommand_exits_with_the_code_of_the_process() {
+        // No api token is needed without the server.
+        assert_eq!(run_parsed(&["job_hub", "run", "true"]).await, 0);
+        assert_eq!(
+            run_parsed(&["job_hub", "run", "--", "sh", "-c", "exit 3"]).await,
+            3
+        );
+        assert_eq!(
+            run_parsed(&["job_hub", "run", "--timeout-secs", "1", "sleep", "30"]).await,
+            124
+        );
+        assert_eq!(
+            run_parsed(&["job_hub", "run", "does-not-exist-job-hub"]).await,
+            127
+        );
+    }
+
+    #[tokio::test]
+    async fn canceled_run_exits_with_130() {
+        let status = run_task(
+            String::from("sleep"),
+            vec![String::from("30")],
+            Duration::from_secs(60),
+            Duration::from_secs(1),
+            tokio::time::sleep(Duration::from_millis(100)),
+        )
+        .await;
+
+        assert_eq!(exit_code(&status), 130);
+    }
+}
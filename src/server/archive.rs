@@ -0,0 +1,84 @@
+use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use axum::body::Body;
+use futures::StreamExt;
+use std::path::PathBuf;
+use tokio_util::{compat::TokioAsyncReadCompatExt, io::ReaderStream};
+
+/// Size of the in memory pipe between the zip writer and the response body.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// A file to add to a zip archive.
+#[derive(Debug)]
+pub struct ArchiveEntry {
+    /// Name of the file inside the archive
+    pub name: String,
+    pub source: ArchiveSource,
+}
+
+#[derive(Debug)]
+pub enum ArchiveSource {
+    /// Read from disk while the archive is written
+    File(PathBuf),
+    /// Generated content, e.g. a manifest
+    Bytes(Vec<u8>),
+}
+
+/// Stream a zip archive of the given entries.
+///
+/// The archive is written while the body is read, so only one chunk of it is held in memory at a time.
+/// If writing fails, the body ends with an error instead of a truncated archive.
+pub fn zip_stream(entries: Vec<ArchiveEntry>) -> Body {
+    let (reader, writer) = tokio::io::duplex(PIPE_CAPACITY);
+
+    let writer_task = tokio::spawn(write_zip(entries, writer));
+
+    let result = futures::stream::once(async move {
+        match writer_task.await {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => {
+                tracing::error!(?err, "Failed to write zip archive");
+
+                Some(Err(err))
+            }
+            Err(err) => Some(Err(std::io::Error::other(err))),
+        }
+    })
+    .filter_map(std::future::ready);
+
+    Body::from_stream(ReaderStream::new(reader).chain(result))
+}
+
+async fn write_zip(
+    entries: Vec<ArchiveEntry>,
+    writer: tokio::io::DuplexStream,
+) -> Result<(), std::io::Error> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+
+    for entry in entries {
+        let builder = ZipEntryBuilder::new(entry.name.into(), Compression::Deflate);
+
+        match entry.source {
+            ArchiveSource::File(path) => {
+                let file = tokio::fs::File::open(&path).await?;
+
+                let mut entry_writer = zip
+                    .write_entry_stream(builder)
+                    .await
+                    .map_err(std::io::Error::other)?;
+
+                futures::io::copy(file.compat(), &mut entry_writer).await?;
+
+                entry_writer.close().await.map_err(std::io::Error::other)?;
+            }
+            ArchiveSource::Bytes(bytes) => {
+                zip.write_entry_whole(builder, &bytes)
+                    .await
+                    .map_err(std::io::Error::other)?;
+            }
+        }
+    }
+
+    zip.close().await.map_err(std::io::Error::other)?;
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use super::task::Status;
+use serde::Serialize;
+use std::{collections::HashSet, time::Duration};
+
+/// How often a failed callback is retried.
+const CALLBACK_RETRIES: u32 = 2;
+/// Wait before the first retry. Doubled for every further retry
+const CALLBACK_BACKOFF: Duration = Duration::from_secs(1);
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posted to a task's `callback_url` once the task is done.
+#[derive(Debug, Serialize)]
+pub struct CallbackPayload {
+    pub id: String,
+    pub status: Status,
+    /// Set if the task ran an OS process that exited
+    pub exit_code: Option<i32>,
+}
+
+/// Callbacks are only sent to `http` or `https` urls on an allowed host,
+/// so the server can not be used to send requests into the network it runs in.
+pub fn callback_url_allowed(url: &url::Url, allowed_hosts: &HashSet<String>) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && url
+            .host_str()
+            .is_some_and(|host| allowed_hosts.contains(host))
+}
+
+/// Post the payload to the url, retrying on network errors and non success statuses.
+#[tracing::instrument(skip_all, fields(id = payload.id, %url))]
+pub async fn send_callback(url: url::Url, payload: CallbackPayload) {
+    let body = match serde_json::to_vec(&payload) {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!(?err, "Failed to serialize callback payload");
+
+            return;
+        }
+    };
+
+    // A redirect could point anywhere, e.g. into the network the server runs in, so it is not followed.
+    let client = match reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!(?err, "Failed to build callback client");
+
+            return;
+        }
+    };
+    let mut backoff = CALLBACK_BACKOFF;
+
+    for attempt in 0..=CALLBACK_RETRIES {
+        if attempt > 0 {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        let result = client
+            .post(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .timeout(CALLBACK_TIMEOUT)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(response) if response.status().is_redirection() => {
+                tracing::warn!(status = %response.status(), "Callback url redirected. Not following it");
+
+                return;
+            }
+            Ok(_) => {
+                tracing::debug!("Callback sent");
+
+                return;
+            }
+            Err(err) => tracing::warn!(?err, attempt, "Failed to send callback"),
+        }
+    }
+
+    tracing::error!("Giving up on callback");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::task::FailureKind;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    async fn serve(app: axum::Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn redirects_of_an_allowed_host_are_not_followed() {
+        let internal_requests = Arc::new(AtomicUsize::new(0));
+        let internal = serve(axum::Router::new().fallback({
+            let internal_requests = internal_requests.clone();
+            move || async move {
+                internal_requests.fetch_add(1, Ordering::SeqCst);
+            }
+        }))
+        .await;
+
+        let allowed_requests = Arc::new(AtomicUsize::new(0));
+        let allowed = serve(axum::Router::new().fallback({
+            let allowed_requests = allowed_requests.clone();
+            move || async move {
+                allowed_requests.fetch_add(1, Ordering::SeqCst);
+
+                axum::response::Redirect::temporary(&format!("http://{internal}/internal"))
+            }
+        }))
+        .await;
+
+        send_callback(
+            format!("http://{allowed}/done").parse().unwrap(),
+            CallbackPayload {
+                id: String::from("0"),
+                status: Status::Failed {
+                    reason: String::from("reason"),
+                    kind: FailureKind::Process,
+                },
+                exit_code: None,
+            },
+        )
+        .await;
+
+        assert_eq!(allowed_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(internal_requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn only_http_urls_on_allowed_hosts_are_allowed() {
+        let allowed_hosts = HashSet::from([String::from("hooks.example.com")]);
+
+        let allowed = |url: &str| callback_url_allowed(&url.parse().unwrap(), &allowed_hosts);
+
+        assert!(allowed("https://hooks.example.com/done"));
+        assert!(allowed("http://hooks.example.com:8080/done"));
+        assert!(!allowed("https://other.example.com/done"));
+        assert!(!allowed("ftp://hooks.example.com/done"));
+        assert!(!callback_url_allowed(
+            &"https://hooks.example.com/done".parse().unwrap(),
+            &HashSet::new()
+        ));
+    }
+}
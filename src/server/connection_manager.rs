@@ -0,0 +1,580 @@
+use super::{
+    prometheus,
+    ws::{IoType, ServerMessage},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    sync::{
+        mpsc::error::{TryRecvError, TrySendError},
+        Notify, RwLock,
+    },
+    task::AbortHandle,
+};
+
+/// Number of messages a connection may lag behind before the [`SlowClientPolicy`] applies.
+const CONNECTION_CHANNEL_CAPACITY: usize = 100;
+
+/// What to do with a connection whose channel is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SlowClientPolicy {
+    /// Drop the oldest queued message to make room and keep the connection
+    #[default]
+    Drop,
+    /// Close the connection
+    Close,
+}
+
+struct ChannelState {
+    messages: VecDeque<ServerMessage>,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+/// A bounded queue of messages. Unlike a [`tokio::sync::mpsc`] channel, the sender can drop the oldest message.
+struct Channel {
+    state: Mutex<ChannelState>,
+    notify: Notify,
+}
+
+fn channel() -> (ConnectionSender, ConnectionReceiver) {
+    let channel = Arc::new(Channel {
+        state: Mutex::new(ChannelState {
+            messages: VecDeque::with_capacity(CONNECTION_CHANNEL_CAPACITY),
+            sender_alive: true,
+            receiver_alive: true,
+        }),
+        notify: Notify::new(),
+    });
+
+    (
+        ConnectionSender {
+            channel: channel.clone(),
+        },
+        ConnectionReceiver { channel },
+    )
+}
+
+/// Dropping it closes the channel. The receiver still gets the queued messages.
+struct ConnectionSender {
+    channel: Arc<Channel>,
+}
+
+impl ConnectionSender {
+    /// Queue the message.
+    ///
+    /// On a full channel the [`SlowClientPolicy`] decides which message is dropped and returned:
+    /// the oldest one, making room for the new one, or the new one.
+    fn try_send(
+        &self,
+        message: ServerMessage,
+        policy: SlowClientPolicy,
+    ) -> Result<(), TrySendError<ServerMessage>> {
+        let mut state = self.channel.state.lock().expect("Channel lock poisoned");
+
+        if !state.receiver_alive {
+            return Err(TrySendError::Closed(message));
+        }
+
+        let dropped = if state.messages.len() < CONNECTION_CHANNEL_CAPACITY {
+            None
+        } else {
+            match policy {
+                SlowClientPolicy::Drop => state.messages.pop_front(),
+                SlowClientPolicy::Close => return Err(TrySendError::Full(message)),
+            }
+        };
+
+        state.messages.push_back(message);
+        drop(state);
+
+        self.channel.notify.notify_one();
+
+        match dropped {
+            Some(dropped) => Err(TrySendError::Full(dropped)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ConnectionSender {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.channel.state.lock() {
+            state.sender_alive = false;
+        }
+
+        self.channel.notify.notify_one();
+    }
+}
+
+/// The receiving half of a connection's channel, returned by [`ConnectionManager::add_connection`].
+pub struct ConnectionReceiver {
+    channel: Arc<Channel>,
+}
+
+impl ConnectionReceiver {
+    /// Wait for the next message. `None` once the connection was removed and the queue is empty.
+    ///
+    /// Cancel safe, a message is only taken from the queue when it is returned.
+    pub async fn recv(&mut self) -> Option<ServerMessage> {
+        loop {
+            match self.try_recv() {
+                Ok(message) => return Some(message),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => self.channel.notify.notified().await,
+            }
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<ServerMessage, TryRecvError> {
+        let mut state = self.channel.state.lock().expect("Channel lock poisoned");
+
+        match state.messages.pop_front() {
+            Some(message) => Ok(message),
+            None if state.sender_alive => Err(TryRecvError::Empty),
+            None => Err(TryRecvError::Disconnected),
+        }
+    }
+}
+
+impl Drop for ConnectionReceiver {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.channel.state.lock() {
+            state.receiver_alive = false;
+        }
+    }
+}
+
+struct Connection {
+    chat_id: String,
+    tx: ConnectionSender,
+    /// Task id to the kind of IO the connection wants. `None` means both.
+    /// Empty means every task of the chat.
+    subscriptions: HashMap<String, Option<IoType>>,
+    /// Tokio tasks that serve the connection, e.g. tailing a file. Aborted with the connection
+    tasks: Vec<AbortHandle>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl Connection {
+    fn wants(&self, message: &ServerMessage) -> bool {
+        if self.subscriptions.is_empty() {
+            return true;
+        }
+
+        match message {
+            ServerMessage::TaskIoChunk(chunk) => match self.subscriptions.get(&chunk.id) {
+                Some(Some(io_type)) => *io_type == chunk.io_type,
+                Some(None) => true,
+                None => false,
+            },
+            ServerMessage::ReplayTruncated { id, .. } => self.subscriptions.contains_key(id),
+            ServerMessage::FileAppend { .. } | ServerMessage::StdinRejected { .. } => true,
+        }
+    }
+}
+
+/// Keeps track of the connected WebSocket clients.
+///
+/// Every connection owns the receiving half of a bounded channel.
+/// The manager never waits for a slow connection, it applies the [`SlowClientPolicy`] instead.
+/// Either way a message is dropped and counted.
+pub struct ConnectionManager {
+    /// The key is the connection id.
+    connections: RwLock<HashMap<u32, Connection>>,
+    current_id: AtomicU32,
+    dropped_messages: AtomicU64,
+    slow_client_policy: SlowClientPolicy,
+}
+
+impl ConnectionManager {
+    pub fn new(slow_client_policy: SlowClientPolicy) -> Self {
+        Self {
+            connections: RwLock::new(HashMap::new()),
+            current_id: AtomicU32::new(0),
+            dropped_messages: AtomicU64::new(0),
+            slow_client_policy,
+        }
+    }
+
+    /// Register a connection for the given chat id.
+    ///
+    /// Returns the id of the connection and the receiver for the messages sent to it.
+    pub async fn add_connection(&self, chat_id: String) -> (u32, ConnectionReceiver) {
+        let (tx, rx) = channel();
+
+        let id = self.current_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut connections = self.connections.write().await;
+        connections.insert(
+            id,
+            Connection {
+                chat_id,
+                tx,
+                subscriptions: HashMap::new(),
+                tasks: Vec::new(),
+            },
+        );
+        prometheus::set_ws_connections(connections.len());
+
+        tracing::debug!(%id, "Connection added");
+
+        (id, rx)
+    }
+
+    pub async fn remove_connection(&self, id: u32) {
+        let mut connections = self.connections.write().await;
+        if connections.remove(&id).is_some() {
+            tracing::debug!(%id, "Connection removed");
+        }
+        prometheus::set_ws_connections(connections.len());
+    }
+
+    /// Restrict the connection to the given task's IO, optionally of one [`IoType`] only.
+    pub async fn subscribe(&self, id: u32, task_id: String, io_type: Option<IoType>) {
+        let mut connections = self.connections.write().await;
+        if let Some(connection) = connections.get_mut(&id) {
+            tracing::debug!(%id, %task_id, ?io_type, "Connection subscribed");
+
+            connection.subscriptions.insert(task_id, io_type);
+        }
+    }
+
+    /// Abort the tokio task once the connection is removed, or right away if it is already gone.
+    pub async fn attach_task(&self, id: u32, task: AbortHandle) {
+        let mut connections = self.connections.write().await;
+        match connections.get_mut(&id) {
+            Some(connection) => connection.tasks.push(task),
+            None => task.abort(),
+        }
+    }
+
+    pub async fn connection_count(&self) -> usize {
+        self.connections.read().await.len()
+    }
+
+    /// Number of messages that were dropped because a connection's channel was full.
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
+
+    /// Send a message to a single connection.
+    ///
+    /// Applies the [`SlowClientPolicy`] like [`ConnectionManager::broadcast`].
+    pub async fn send(&self, id: u32, message: ServerMessage) {
+        let result = {
+            let connections = self.connections.read().await;
+
+            let Some(connection) = connections.get(&id) else {
+                return;
+            };
+
+            connection.tx.try_send(message, self.slow_client_policy)
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+
+                tracing::warn!(%id, policy=?self.slow_client_policy, "Connection channel full. Message dropped");
+
+                if self.slow_client_policy == SlowClientPolicy::Close {
+                    self.remove_connection(id).await;
+
+                    tracing::warn!(%id, "Closed slow connection");
+                }
+            }
+            Err(TrySendError::Closed(_)) => {
+                tracing::debug!(%id, "Pruning closed connection");
+
+                self.remove_connection(id).await;
+            }
+        }
+    }
+
+    /// Send a message to every connection of the given chat id.
+    ///
+    /// Connections whose receiver is gone are removed.
+    pub async fn broadcast(&self, chat_id: &str, message: ServerMessage) {
+        let mut slow_connections = Vec::new();
+        let mut dead_connections = Vec::new();
+
+        {
+            let connections = self.connections.read().await;
+
+            for (id, connection) in connections.iter() {
+                if connection.chat_id != chat_id || !connection.wants(&message) {
+                    continue;
+                }
+
+                match connection
+                    .tx
+                    .try_send(message.clone(), self.slow_client_policy)
+                {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+
+                        tracing::warn!(%id, policy=?self.slow_client_policy, "Connection channel full. Message dropped");
+
+                        slow_connections.push(*id);
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        dead_connections.push(*id);
+                    }
+                }
+            }
+        }
+
+        if self.slow_client_policy == SlowClientPolicy::Drop {
+            slow_connections.clear();
+        }
+
+        if slow_connections.is_empty() && dead_connections.is_empty() {
+            return;
+        }
+
+        // Dropping the sender closes the channel, which ends the connection's receive loop.
+        let mut connections = self.connections.write().await;
+        for id in &slow_connections {
+            connections.remove(id);
+
+            tracing::warn!(%id, "Closed slow connection");
+        }
+
+        for id in &dead_connections {
+            connections.remove(id);
+        }
+        prometheus::set_ws_connections(connections.len());
+
+        if !dead_connections.is_empty() {
+            tracing::debug!(ids=?dead_connections, "Pruned closed connections");
+        }
+    }
+}
+
+/// Removes a connection from its [`ConnectionManager`] when dropped,
+/// so a connection is removed however its handler ends.
+pub struct ConnectionGuard {
+    pub id: u32,
+    manager: Arc<ConnectionManager>,
+}
+
+impl ConnectionGuard {
+    pub fn new(id: u32, manager: Arc<ConnectionManager>) -> Self {
+        Self { id, manager }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let id = self.id;
+
+        // Fast path. The lock is only contended while a message is sent.
+        if let Ok(mut connections) = self.manager.connections.try_write() {
+            if connections.remove(&id).is_some() {
+                tracing::debug!(%id, "Connection removed");
+            }
+            prometheus::set_ws_connections(connections.len());
+
+            return;
+        }
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let manager = self.manager.clone();
+                handle.spawn(async move { manager.remove_connection(id).await });
+            }
+            Err(_) => tracing::warn!(%id, "No runtime to remove connection"),
+        }
+    }
+}
+
+impl Default for ConnectionManager {
+    fn default() -> Self {
+        Self::new(SlowClientPolicy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ws::TaskIoChunk;
+
+    fn chunk(chunk: &str) -> ServerMessage {
+        io_chunk(chunk, IoType::Stdout)
+    }
+
+    fn io_chunk(chunk: &str, io_type: IoType) -> ServerMessage {
+        ServerMessage::TaskIoChunk(TaskIoChunk {
+            id: String::from("0"),
+            chunk: chunk.to_string(),
+            io_type,
+        })
+    }
+
+    #[tokio::test]
+    async fn connection_count_reflects_added_connections() {
+        let manager = ConnectionManager::default();
+
+        let mut receivers = Vec::new();
+        for _ in 0..5 {
+            receivers.push(manager.add_connection(String::from("chat_id")).await);
+        }
+
+        assert_eq!(manager.connection_count().await, 5);
+
+        let (id, _) = &receivers[0];
+        manager.remove_connection(*id).await;
+
+        assert_eq!(manager.connection_count().await, 4);
+    }
+
+    #[tokio::test]
+    async fn broadcast_counts_dropped_messages_of_full_channels() {
+        let manager = ConnectionManager::new(SlowClientPolicy::Drop);
+
+        let (_, mut rx) = manager.add_connection(String::from("chat_id")).await;
+        let (_, _other_chat_rx) = manager.add_connection(String::from("other_chat_id")).await;
+
+        for line in 0..CONNECTION_CHANNEL_CAPACITY + 3 {
+            manager.broadcast("chat_id", chunk(&line.to_string())).await;
+        }
+
+        assert_eq!(manager.dropped_messages(), 3);
+
+        let received: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|message| match message {
+                ServerMessage::TaskIoChunk(chunk) => chunk.chunk,
+                other => panic!("Unexpected message: {other:?}"),
+            })
+            .collect();
+
+        // The oldest messages made room for the newest ones.
+        let expected: Vec<String> = (3..CONNECTION_CHANNEL_CAPACITY + 3)
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(received, expected);
+        assert_eq!(manager.connection_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn close_policy_closes_stalled_connection() {
+        let manager = ConnectionManager::new(SlowClientPolicy::Close);
+
+        // Never read from, so the channel fills up.
+        let (_, mut stalled_rx) = manager.add_connection(String::from("chat_id")).await;
+
+        for _ in 0..CONNECTION_CHANNEL_CAPACITY + 1 {
+            manager.broadcast("chat_id", chunk("line")).await;
+        }
+
+        assert_eq!(manager.dropped_messages(), 1);
+        assert_eq!(manager.connection_count().await, 0);
+
+        // The buffered messages are still readable, then the channel reports closed.
+        let mut received = 0;
+        while stalled_rx.recv().await.is_some() {
+            received += 1;
+        }
+
+        assert_eq!(received, CONNECTION_CHANNEL_CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn broadcast_prunes_dropped_connections() {
+        let manager = ConnectionManager::default();
+
+        let (_, mut first_rx) = manager.add_connection(String::from("chat_id")).await;
+        let (_, dropped_rx) = manager.add_connection(String::from("chat_id")).await;
+        let (_, mut last_rx) = manager.add_connection(String::from("chat_id")).await;
+
+        manager.broadcast("chat_id", chunk("before")).await;
+
+        drop(dropped_rx);
+
+        manager.broadcast("chat_id", chunk("after")).await;
+
+        assert_eq!(manager.connection_count().await, 2);
+        assert_eq!(manager.dropped_messages(), 0);
+
+        for rx in [&mut first_rx, &mut last_rx] {
+            let chunks: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+                .map(|message| match message {
+                    ServerMessage::TaskIoChunk(chunk) => chunk.chunk,
+                    other => panic!("Unexpected message: {other:?}"),
+                })
+                .collect();
+
+            assert_eq!(chunks, ["before", "after"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn stderr_subscription_filters_stdout() {
+        let manager = ConnectionManager::default();
+
+        let (id, mut rx) = manager.add_connection(String::from("chat_id")).await;
+        let (_, mut unfiltered_rx) = manager.add_connection(String::from("chat_id")).await;
+
+        manager
+            .subscribe(id, String::from("0"), Some(IoType::Stderr))
+            .await;
+
+        manager
+            .broadcast("chat_id", io_chunk("out", IoType::Stdout))
+            .await;
+        manager
+            .broadcast("chat_id", io_chunk("err", IoType::Stderr))
+            .await;
+
+        let Ok(ServerMessage::TaskIoChunk(chunk)) = rx.try_recv() else {
+            panic!("No stderr chunk");
+        };
+        assert_eq!(chunk.chunk, "err");
+        assert!(rx.try_recv().is_err());
+
+        // Connections without subscriptions still receive everything.
+        let mut received = 0;
+        while unfiltered_rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, 2);
+    }
+
+    #[test]
+    fn connection_gauge_follows_connects_and_disconnects() {
+        let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            let manager = Arc::new(ConnectionManager::default());
+
+            let (first, _first_rx) =
+                futures::executor::block_on(manager.add_connection(String::from("chat_id")));
+            let (second, _second_rx) =
+                futures::executor::block_on(manager.add_connection(String::from("chat_id")));
+            assert!(handle.render().contains("job_hub_ws_connections 2"));
+
+            futures::executor::block_on(manager.remove_connection(first));
+            assert!(handle.render().contains("job_hub_ws_connections 1"));
+
+            // The way the handlers of the connections remove them.
+            drop(ConnectionGuard::new(second, manager.clone()));
+            assert!(handle.render().contains("job_hub_ws_connections 0"));
+        });
+    }
+}
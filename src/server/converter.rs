@@ -0,0 +1,99 @@
+use std::path::Path;
+
+/// Target used when the client does not ask for one.
+pub const DEFAULT_TARGET: &str = "locust";
+
+/// A conversion of the log files of a project, run as an OS process over the project directory.
+pub trait LogConverter: Send + Sync {
+    /// Value of the `target` query parameter selecting this converter.
+    fn target(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Command and arguments to convert the log files in `project_dir`.
+    fn command(&self, project_dir: &Path) -> (String, Vec<String>);
+}
+
+/// Converts GS log files to the Locust log format using the ML_ETL script.
+pub struct LocustConverter;
+
+impl LogConverter for LocustConverter {
+    fn target(&self) -> &'static str {
+        "locust"
+    }
+
+    fn description(&self) -> &'static str {
+        "Converts GS log files to the Locust log format"
+    }
+
+    fn command(&self, project_dir: &Path) -> (String, Vec<String>) {
+        let command = cfg!(target_os = "windows")
+            .then(|| "python")
+            .unwrap_or("python3")
+            .to_string();
+
+        let path_to_gs_log_to_locst_converter_script = Path::new("ML_ETL")
+            .join("GS")
+            .join("Logfiles")
+            .join("GSLogToLocustConverter.py")
+            .to_string_lossy()
+            .to_string();
+
+        let project_dir = project_dir.to_string_lossy().to_string();
+
+        let args = vec![
+            path_to_gs_log_to_locst_converter_script,
+            String::from("--directory"),
+            project_dir,
+            String::from("--force"),
+        ];
+
+        (command, args)
+    }
+}
+
+/// Only Locust for now. The ML_ETL scripts have no csv or json writers a converter could run,
+/// so those targets are rejected like any other unknown one.
+static CONVERTERS: [&dyn LogConverter; 1] = [&LocustConverter];
+
+/// Every available converter.
+pub fn converters() -> &'static [&'static dyn LogConverter] {
+    &CONVERTERS
+}
+
+/// The converter for the given target, [`DEFAULT_TARGET`] if `None`.
+pub fn converter(target: Option<&str>) -> Option<&'static dyn LogConverter> {
+    let target = target.unwrap_or(DEFAULT_TARGET);
+
+    converters()
+        .iter()
+        .find(|converter| converter.target() == target)
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locust_is_the_default_target() {
+        let converter = converter(None).expect("No default converter");
+
+        assert_eq!(converter.target(), "locust");
+
+        let (_, args) = converter.command(Path::new("projects/project"));
+        assert!(args[0].ends_with("GSLogToLocustConverter.py"));
+        assert_eq!(
+            args[1..],
+            ["--directory", "projects/project", "--force"].map(String::from)
+        );
+    }
+
+    #[test]
+    fn unknown_target_has_no_converter() {
+        assert!(converter(Some("locust")).is_some());
+        assert!(converter(Some("xml")).is_none());
+        assert!(converter(Some("csv")).is_none());
+        assert!(converter(Some("json")).is_none());
+    }
+}
@@ -0,0 +1,104 @@
+use axum::http::{
+    header::{self, InvalidHeaderValue},
+    HeaderName, HeaderValue, Method,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use super::middleware::X_REQUEST_ID;
+
+static API_KEY: HeaderName = HeaderName::from_static("api_key");
+
+/// CORS for browser clients of the given origins.
+///
+/// Only the methods and headers the api uses are allowed. Credentials are allowed, so the origins must be trusted.
+/// Without origins every origin is allowed, but without credentials.
+pub fn cors_layer(allowed_origins: &[String]) -> Result<CorsLayer, InvalidHeaderValue> {
+    if allowed_origins.is_empty() {
+        tracing::warn!("No CORS origins configured. Allowing every origin");
+
+        return Ok(CorsLayer::permissive());
+    }
+
+    let origins = allowed_origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let layer = CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+        ])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            API_KEY.clone(),
+            X_REQUEST_ID.clone(),
+        ])
+        .expose_headers([X_REQUEST_ID.clone(), header::RETRY_AFTER])
+        .allow_credentials(true);
+
+    Ok(layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn preflight(origin: &str) -> axum::response::Response {
+        let app = Router::new()
+            .route("/api/status", get(|| async { "ok" }))
+            .layer(cors_layer(&[String::from("https://allowed.example")]).unwrap());
+
+        app.oneshot(
+            Request::options("/api/status")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "api_key")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn allowed_origin_gets_cors_headers() {
+        let response = preflight("https://allowed.example").await;
+        let headers = response.headers();
+
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://allowed.example"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap()
+            .contains("GET"));
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap()
+            .contains("api_key"));
+    }
+
+    #[tokio::test]
+    async fn disallowed_origin_gets_no_cors_headers() {
+        let response = preflight("https://evil.example").await;
+
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn invalid_origin_is_an_error() {
+        assert!(cors_layer(&[String::from("https://bad\norigin")]).is_err());
+    }
+}
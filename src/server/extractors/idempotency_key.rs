@@ -0,0 +1,31 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderName},
+};
+use std::convert::Infallible;
+
+pub static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// The `Idempotency-Key` header, if present and valid UTF-8.
+///
+/// Retrying a request with the same key returns the task the first request started.
+pub struct IdempotencyKey(pub Option<String>);
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for IdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let key = parts
+            .headers
+            .get(&IDEMPOTENCY_KEY)
+            .and_then(|value| value.to_str().ok())
+            .filter(|key| !key.is_empty())
+            .map(String::from);
+
+        Ok(Self(key))
+    }
+}
@@ -1,2 +1,3 @@
 pub mod chat_id;
+pub mod idempotency_key;
 pub mod query;
@@ -0,0 +1,125 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{future::Future, path::PathBuf};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileTailError {
+    #[error("Failed to watch the file: {0}")]
+    Watch(#[from] notify::Error),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// Follows a file like `tail -f`.
+///
+/// Only complete lines are reported. An incomplete last line is held back until its newline is appended.
+pub struct FileTail {
+    path: PathBuf,
+    /// Position up to which the file was read
+    offset: u64,
+    /// Bytes after the last newline
+    partial: Vec<u8>,
+    events: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    /// Stops watching once dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl FileTail {
+    /// Start watching the file. Only what is appended from now on is followed.
+    pub fn open(path: PathBuf) -> Result<Self, FileTailError> {
+        let (tx, events) = mpsc::unbounded_channel();
+
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        watcher.watch(&path, RecursiveMode::NonRecursive)?;
+
+        // Read the length after the watcher is registered, so no append is missed.
+        let offset = std::fs::metadata(&path)?.len();
+
+        Ok(Self {
+            path,
+            offset,
+            partial: Vec::new(),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// The length of the file when it was opened. Everything before is not followed.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Report the lines appended to the file with `on_lines`, until the file can not be read anymore.
+    pub async fn follow<F, Fut>(mut self, mut on_lines: F) -> Result<(), FileTailError>
+    where
+        F: FnMut(Vec<String>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        while let Some(event) = self.events.recv().await {
+            event?;
+
+            let lines = self.read_appended().await?;
+
+            if !lines.is_empty() {
+                on_lines(lines).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn read_appended(&mut self) -> Result<Vec<String>, std::io::Error> {
+        let mut file = tokio::fs::File::open(&self.path).await?;
+        let len = file.metadata().await?.len();
+
+        // The file was truncated. Start over.
+        if len < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+
+        file.seek(std::io::SeekFrom::Start(self.offset)).await?;
+
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended).await?;
+        self.offset += appended.len() as u64;
+
+        self.partial.extend_from_slice(&appended);
+
+        Ok(Self::take_lines(&mut self.partial))
+    }
+
+    /// Remove the complete lines from the start of `buffer`.
+    fn take_lines(buffer: &mut Vec<u8>) -> Vec<String> {
+        let Some(end) = buffer.iter().rposition(|byte| *byte == b'\n') else {
+            return Vec::new();
+        };
+
+        let complete: Vec<u8> = buffer.drain(..=end).collect();
+
+        String::from_utf8_lossy(&complete)
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_lines_are_held_back() {
+        let mut buffer = b"line 1\r\nline 2\nline".to_vec();
+
+        assert_eq!(FileTail::take_lines(&mut buffer), ["line 1", "line 2"]);
+        assert_eq!(buffer, b"line");
+
+        assert!(FileTail::take_lines(&mut buffer).is_empty());
+    }
+}
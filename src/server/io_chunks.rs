@@ -0,0 +1,162 @@
+/// Line mode emits a partial line once this many bytes are pending without a newline.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// How the IO of a task is split into [`super::ws::TaskIoChunk`]s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IoChunkMode {
+    /// One chunk per read
+    #[default]
+    Raw,
+    /// One chunk per line, including the newline
+    Line,
+}
+
+/// How the IO of a task is read and split.
+#[derive(Debug, Clone, Copy)]
+pub struct IoOptions {
+    pub chunk_mode: IoChunkMode,
+    /// Size of a single read
+    pub buffer_bytes: usize,
+}
+
+impl Default for IoOptions {
+    fn default() -> Self {
+        Self {
+            chunk_mode: IoChunkMode::default(),
+            buffer_bytes: 8192,
+        }
+    }
+}
+
+/// Turns the bytes read from a task's IO into chunks according to an [`IoChunkMode`].
+pub struct IoChunker {
+    mode: IoChunkMode,
+    pending: Vec<u8>,
+}
+
+impl IoChunker {
+    pub fn new(mode: IoChunkMode) -> Self {
+        Self {
+            mode,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Feed the bytes of one read. Returns the chunks that are complete.
+    ///
+    /// A UTF-8 character cut off at the end of a read is kept until the next read completes it.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut chunks = Vec::new();
+
+        match self.mode {
+            IoChunkMode::Raw => {
+                chunks.extend(self.take_complete());
+            }
+            IoChunkMode::Line => {
+                while let Some(newline) = self.pending.iter().position(|byte| *byte == b'\n') {
+                    let line: Vec<u8> = self.pending.drain(..=newline).collect();
+                    chunks.push(String::from_utf8_lossy(&line).to_string());
+                }
+
+                if self.pending.len() >= MAX_LINE_BYTES {
+                    chunks.extend(self.take_complete());
+                }
+            }
+        }
+
+        chunks
+    }
+
+    /// Everything pending, except a trailing incomplete character.
+    fn take_complete(&mut self) -> Option<String> {
+        let complete = self.pending.len() - incomplete_tail_len(&self.pending);
+
+        if complete == 0 {
+            return None;
+        }
+
+        let complete: Vec<u8> = self.pending.drain(..complete).collect();
+
+        Some(String::from_utf8_lossy(&complete).to_string())
+    }
+
+    /// The rest after the IO has ended, e.g. a last line without a newline.
+    pub fn finish(self) -> Option<String> {
+        (!self.pending.is_empty()).then(|| String::from_utf8_lossy(&self.pending).to_string())
+    }
+}
+
+/// Length of a UTF-8 character at the end of `bytes` that is missing its continuation bytes.
+fn incomplete_tail_len(bytes: &[u8]) -> usize {
+    // A character is at most 4 bytes long, so its lead byte is within the last 3 bytes if it is incomplete.
+    for (back, byte) in bytes.iter().rev().take(3).enumerate() {
+        let char_len = match byte {
+            0b1000_0000..=0b1011_1111 => continue,
+            0b1100_0000..=0b1101_1111 => 2,
+            0b1110_0000..=0b1110_1111 => 3,
+            0b1111_0000..=0b1111_0111 => 4,
+            _ => return 0,
+        };
+
+        let available = back + 1;
+
+        return if available < char_len { available } else { 0 };
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_mode_keeps_multibyte_characters_across_reads() {
+        let input = "grüße\nこんにちは\nend";
+        let bytes = input.as_bytes();
+
+        let mut chunker = IoChunker::new(IoChunkMode::Line);
+
+        // Reads of 3 bytes split most of the multibyte characters.
+        let mut chunks: Vec<String> = bytes
+            .chunks(3)
+            .flat_map(|read| chunker.push(read))
+            .collect();
+        chunks.extend(chunker.finish());
+
+        assert_eq!(chunks, ["grüße\n", "こんにちは\n", "end"]);
+        assert!(chunks.iter().all(|chunk| !chunk.contains('\u{FFFD}')));
+    }
+
+    #[test]
+    fn raw_mode_carries_split_characters_to_the_next_chunk() {
+        let input = "grüße こんにちは 🦀";
+        let bytes = input.as_bytes();
+
+        for read_size in 1..=5 {
+            let mut chunker = IoChunker::new(IoChunkMode::Raw);
+
+            let mut output: String = bytes
+                .chunks(read_size)
+                .flat_map(|read| chunker.push(read))
+                .collect();
+            output.extend(chunker.finish());
+
+            assert_eq!(output, input, "read size {read_size}");
+        }
+    }
+
+    #[test]
+    fn invalid_bytes_are_still_replaced() {
+        let mut chunker = IoChunker::new(IoChunkMode::Raw);
+
+        let mut output: String = chunker.push(b"a\xffb").into_iter().collect();
+        // A lead byte without its continuation at the very end is flushed when the IO ends.
+        output.extend(chunker.push(b"\xe3"));
+        output.extend(chunker.finish());
+
+        assert_eq!(output, "a\u{FFFD}b\u{FFFD}");
+    }
+}
@@ -0,0 +1,332 @@
+use super::{
+    connection_manager::ConnectionManager,
+    io_chunks::{IoChunker, IoOptions},
+    task_output::TaskOutput,
+    ws::{IoType, ServerMessage, TaskIoChunk},
+};
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
+use std::{path::Path, sync::Arc};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+
+/// Name of the directory, inside the projects directory, that holds the persisted IO of a task.
+///
+/// Task ids restart at `0` on every start, so `boot_id` keeps a run from overwriting the logs of the previous one.
+pub fn task_log_dir_name(boot_id: &str, task_id: &str) -> String {
+    format!("task_{boot_id}_{task_id}")
+}
+
+/// Name of a persisted log file, e.g. `stdout` is written to `stdout.log`, or `stdout.log.gz` if compressed.
+pub fn task_log_file_name(stream: &str, compress: bool) -> String {
+    if compress {
+        format!("{stream}.log.gz")
+    } else {
+        format!("{stream}.log")
+    }
+}
+
+/// Read a persisted log file, decompressing it if it was written compressed.
+pub async fn read_task_log(path: &Path, compressed: bool) -> Result<Vec<u8>, std::io::Error> {
+    let bytes = tokio::fs::read(path).await?;
+
+    if !compressed {
+        return Ok(bytes);
+    }
+
+    let mut decompressed = Vec::new();
+    GzipDecoder::new(&bytes[..])
+        .read_to_end(&mut decompressed)
+        .await?;
+
+    Ok(decompressed)
+}
+
+type LogFile = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Files the IO of a task is written to, if persisting is enabled.
+pub struct TaskLogFiles {
+    stdout: LogFile,
+    stderr: LogFile,
+    /// Both streams in the order they were read, every line prefixed with `[out]` or `[err]`
+    combined: LogFile,
+}
+
+impl TaskLogFiles {
+    /// Create `stdout.log`, `stderr.log` and `combined.log` in `dir`.
+    ///
+    /// With `compress`, they are gzip compressed and named with a `.gz` extension. They are complete only once the IO ended.
+    pub async fn create(dir: &Path, compress: bool) -> Result<Self, std::io::Error> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        let create = |stream: &str| {
+            let path = dir.join(task_log_file_name(stream, compress));
+
+            async move {
+                let file = File::create(path).await?;
+
+                let file: LogFile = if compress {
+                    Box::new(GzipEncoder::new(file))
+                } else {
+                    Box::new(file)
+                };
+
+                Ok::<_, std::io::Error>(file)
+            }
+        };
+
+        Ok(Self {
+            stdout: create("stdout").await?,
+            stderr: create("stderr").await?,
+            combined: create("combined").await?,
+        })
+    }
+
+    async fn write(&mut self, stream: &mut Stream, bytes: &[u8]) -> Result<(), std::io::Error> {
+        let file = match stream.io_type {
+            IoType::Stdout => &mut self.stdout,
+            IoType::Stderr => &mut self.stderr,
+        };
+        file.write_all(bytes).await?;
+
+        stream.pending_line.extend_from_slice(bytes);
+        while let Some(newline) = stream.pending_line.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = stream.pending_line.drain(..=newline).collect();
+
+            self.combined.write_all(stream.prefix()).await?;
+            self.combined.write_all(&line).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the last lines that did not end with a newline and close the files.
+    async fn finish(&mut self, streams: [&mut Stream; 2]) -> Result<(), std::io::Error> {
+        for stream in streams {
+            if !stream.pending_line.is_empty() {
+                self.combined.write_all(stream.prefix()).await?;
+                self.combined.write_all(&stream.pending_line).await?;
+                self.combined.write_all(b"\n").await?;
+            }
+        }
+
+        // Also writes the trailer of compressed files.
+        self.stdout.shutdown().await?;
+        self.stderr.shutdown().await?;
+        self.combined.shutdown().await
+    }
+}
+
+/// Read state of stdout or stderr.
+struct Stream {
+    io_type: IoType,
+    buf: Vec<u8>,
+    chunker: IoChunker,
+    /// Bytes of the combined log waiting for the end of their line
+    pending_line: Vec<u8>,
+    done: bool,
+}
+
+impl Stream {
+    fn new(io_type: IoType, options: IoOptions) -> Self {
+        Self {
+            io_type,
+            buf: vec![0; options.buffer_bytes.max(1)],
+            chunker: IoChunker::new(options.chunk_mode),
+            pending_line: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn prefix(&self) -> &'static [u8] {
+        match self.io_type {
+            IoType::Stdout => b"[out] ",
+            IoType::Stderr => b"[err] ",
+        }
+    }
+}
+
+/// Sends the IO of a task to the connections of its chat and keeps it in the task's [`TaskOutput`].
+#[derive(Clone)]
+pub struct IoForwarder {
+    pub task_id: String,
+    pub chat_id: String,
+    pub connection_manager: Arc<ConnectionManager>,
+    pub output: Arc<TaskOutput>,
+    pub options: IoOptions,
+}
+
+impl IoForwarder {
+    /// Reads stdout and stderr in chunks until both end.
+    ///
+    /// Both are read in a single loop, so the chunks and the combined log keep the order the output was read in.
+    /// The bytes are written to `log_files` as they are read, if given.
+    #[tracing::instrument(skip_all, fields(id=self.task_id))]
+    pub async fn forward<O, E>(
+        self,
+        mut stdout: O,
+        mut stderr: E,
+        mut log_files: Option<TaskLogFiles>,
+    ) where
+        O: AsyncRead + Unpin,
+        E: AsyncRead + Unpin,
+    {
+        let mut out = Stream::new(IoType::Stdout, self.options);
+        let mut err = Stream::new(IoType::Stderr, self.options);
+
+        while !(out.done && err.done) {
+            let (stream, read) = tokio::select! {
+                read = stdout.read(&mut out.buf), if !out.done => (&mut out, read),
+                read = stderr.read(&mut err.buf), if !err.done => (&mut err, read),
+            };
+
+            let n = match read {
+                Ok(0) => {
+                    stream.done = true;
+                    continue;
+                }
+                Ok(n) => n,
+                Err(error) => {
+                    tracing::error!(io_type=?stream.io_type, ?error, "Failed to read IO");
+
+                    stream.done = true;
+                    continue;
+                }
+            };
+
+            let bytes = stream.buf[..n].to_vec();
+
+            if let Some(files) = log_files.as_mut() {
+                if let Err(err) = files.write(stream, &bytes).await {
+                    tracing::error!(?err, "Failed to persist IO. Not persisting the rest");
+
+                    log_files = None;
+                }
+            }
+
+            for chunk in stream.chunker.push(&bytes) {
+                self.emit(stream.io_type, chunk).await;
+            }
+        }
+
+        for stream in [&mut out, &mut err] {
+            let chunker =
+                std::mem::replace(&mut stream.chunker, IoChunker::new(self.options.chunk_mode));
+            if let Some(chunk) = chunker.finish() {
+                self.emit(stream.io_type, chunk).await;
+            }
+        }
+
+        if let Some(mut files) = log_files {
+            if let Err(err) = files.finish([&mut out, &mut err]).await {
+                tracing::error!(?err, "Failed to flush persisted IO");
+            }
+        }
+
+        tracing::debug!("Finished reading IO");
+    }
+
+    async fn emit(&self, io_type: IoType, chunk: String) {
+        match io_type {
+            IoType::Stdout => tracing::trace!("{chunk}"),
+            IoType::Stderr => tracing::error!("{chunk}"),
+        }
+
+        let chunk = TaskIoChunk {
+            id: self.task_id.clone(),
+            chunk,
+            io_type,
+        };
+
+        self.output.push(chunk.clone());
+
+        self.connection_manager
+            .broadcast(&self.chat_id, ServerMessage::TaskIoChunk(chunk))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn large_output_is_fully_captured_for_any_buffer_size() {
+        let input: String = (0..10_000).map(|i| format!("line {i} ü\n")).collect();
+
+        for buffer_bytes in [1, 7, 256, 8192] {
+            let output = Arc::new(TaskOutput::new(input.len()));
+
+            IoForwarder {
+                task_id: String::from("0"),
+                chat_id: String::from("chat_id"),
+                connection_manager: Arc::new(ConnectionManager::default()),
+                output: output.clone(),
+                options: IoOptions {
+                    buffer_bytes,
+                    ..Default::default()
+                },
+            }
+            .forward(input.as_bytes(), tokio::io::empty(), None)
+            .await;
+
+            let captured: String = output
+                .last(usize::MAX)
+                .into_iter()
+                .map(|chunk| chunk.chunk)
+                .collect();
+
+            assert_eq!(captured, input, "buffer size {buffer_bytes}");
+        }
+    }
+
+    #[tokio::test]
+    async fn combined_log_keeps_the_order_of_stdout_and_stderr() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let files = TaskLogFiles::create(dir.path(), false)
+            .await
+            .expect("Failed to create task log files");
+
+        let (mut stdout_tx, stdout_rx) = tokio::io::duplex(64);
+        let (mut stderr_tx, stderr_rx) = tokio::io::duplex(64);
+
+        let forwarder = IoForwarder {
+            task_id: String::from("0"),
+            chat_id: String::from("chat_id"),
+            connection_manager: Arc::new(ConnectionManager::default()),
+            output: Arc::new(TaskOutput::new(0)),
+            options: IoOptions::default(),
+        };
+        let forwarding = tokio::spawn(forwarder.forward(stdout_rx, stderr_rx, Some(files)));
+
+        let writes: [(IoType, &[u8]); 4] = [
+            (IoType::Stdout, b"out 1\n"),
+            (IoType::Stderr, b"err 1\n"),
+            (IoType::Stdout, b"out 2\n"),
+            (IoType::Stderr, b"err 2 without newline"),
+        ];
+        for (io_type, bytes) in writes {
+            let tx = match io_type {
+                IoType::Stdout => &mut stdout_tx,
+                IoType::Stderr => &mut stderr_tx,
+            };
+            tx.write_all(bytes).await.unwrap();
+            // Give the forwarder time to read, so the order is well defined.
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        drop(stdout_tx);
+        drop(stderr_tx);
+        forwarding.await.unwrap();
+
+        let combined = std::fs::read_to_string(dir.path().join("combined.log")).unwrap();
+        assert_eq!(
+            combined,
+            "[out] out 1\n[err] err 1\n[out] out 2\n[err] err 2 without newline\n"
+        );
+
+        let stderr = std::fs::read_to_string(dir.path().join("stderr.log")).unwrap();
+        assert_eq!(stderr, "err 1\nerr 2 without newline");
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+/// Most labels a task may have.
+pub const MAX_LABELS: usize = 16;
+pub const MAX_LABEL_KEY_BYTES: usize = 64;
+pub const MAX_LABEL_VALUE_BYTES: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LabelError {
+    #[error("Label {0:?} is not of the form `key:value`")]
+    Malformed(String),
+    #[error("More than {MAX_LABELS} labels")]
+    TooMany,
+    #[error("Label key {0:?} is empty or longer than {MAX_LABEL_KEY_BYTES} bytes")]
+    InvalidKey(String),
+    #[error("Value of label {0:?} is longer than {MAX_LABEL_VALUE_BYTES} bytes")]
+    ValueTooLong(String),
+}
+
+/// Parse a single `key:value` label.
+pub fn parse_label(label: &str) -> Result<(String, String), LabelError> {
+    let (key, value) = label
+        .split_once(':')
+        .ok_or_else(|| LabelError::Malformed(label.to_string()))?;
+
+    if key.is_empty() || key.len() > MAX_LABEL_KEY_BYTES {
+        return Err(LabelError::InvalidKey(key.to_string()));
+    }
+
+    if value.len() > MAX_LABEL_VALUE_BYTES {
+        return Err(LabelError::ValueTooLong(key.to_string()));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse comma separated `key:value` labels, e.g. `env:prod,team:load`. A later label overrides an earlier one with the same key.
+pub fn parse_labels(labels: &str) -> Result<HashMap<String, String>, LabelError> {
+    let labels = labels
+        .split(',')
+        .filter(|label| !label.is_empty())
+        .map(parse_label)
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    if labels.len() > MAX_LABELS {
+        return Err(LabelError::TooMany);
+    }
+
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_parsed_and_validated() {
+        let labels = parse_labels("env:prod,team:load,url:http://host").expect("Valid labels");
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels["env"], "prod");
+        // Only the first colon separates key and value.
+        assert_eq!(labels["url"], "http://host");
+
+        assert!(parse_labels("").expect("No labels").is_empty());
+
+        assert!(matches!(parse_labels("env"), Err(LabelError::Malformed(_))));
+        assert!(matches!(
+            parse_labels(":prod"),
+            Err(LabelError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            parse_label(&format!("{}:v", "k".repeat(MAX_LABEL_KEY_BYTES + 1))),
+            Err(LabelError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            parse_label(&format!("k:{}", "v".repeat(MAX_LABEL_VALUE_BYTES + 1))),
+            Err(LabelError::ValueTooLong(_))
+        ));
+
+        let too_many: Vec<String> = (0..=MAX_LABELS).map(|i| format!("k{i}:v")).collect();
+        assert!(matches!(
+            parse_labels(&too_many.join(",")),
+            Err(LabelError::TooMany)
+        ));
+    }
+}
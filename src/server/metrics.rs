@@ -0,0 +1,80 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+/// The kind of a task. Used as a metrics dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, ToSchema)]
+pub enum TaskKind {
+    DownloadZipFile,
+    GsLogToLocustConverter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TaskMetricKey {
+    kind: TaskKind,
+    label: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TaskCounters {
+    started: u64,
+    finished: u64,
+}
+
+/// Task counters sliced by [`TaskKind`] and the optional user supplied `metric_label`.
+///
+/// Labels are validated against an allowlist before reaching this struct,
+/// so the number of keys stays low.
+#[derive(Default)]
+pub struct TaskMetrics {
+    counters: RwLock<HashMap<TaskMetricKey, TaskCounters>>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TaskMetric {
+    pub kind: TaskKind,
+    /// `metric_label` given on task start
+    #[schema(example = "nightly")]
+    pub label: Option<String>,
+    /// Number of tasks started
+    pub started: u64,
+    /// Number of tasks that reached a terminal state
+    pub finished: u64,
+}
+
+impl TaskMetrics {
+    pub async fn task_started(&self, kind: TaskKind, label: Option<String>) {
+        let mut counters = self.counters.write().await;
+        counters
+            .entry(TaskMetricKey { kind, label })
+            .or_default()
+            .started += 1;
+    }
+
+    pub async fn task_finished(&self, kind: TaskKind, label: Option<String>) {
+        let mut counters = self.counters.write().await;
+        counters
+            .entry(TaskMetricKey { kind, label })
+            .or_default()
+            .finished += 1;
+    }
+
+    pub async fn snapshot(&self) -> Vec<TaskMetric> {
+        let counters = self.counters.read().await;
+
+        let mut metrics: Vec<TaskMetric> = counters
+            .iter()
+            .map(|(key, counters)| TaskMetric {
+                kind: key.kind,
+                label: key.label.clone(),
+                started: counters.started,
+                finished: counters.finished,
+            })
+            .collect();
+
+        metrics.sort_by(|a, b| (a.kind as u8, &a.label).cmp(&(b.kind as u8, &b.label)));
+
+        metrics
+    }
+}
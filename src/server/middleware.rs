@@ -0,0 +1,314 @@
+use crate::server::{prometheus, response::ApiError, state::ApiState};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::OwnedSemaphorePermit;
+use tower_http::trace::{DefaultOnResponse, OnResponse};
+use tracing::{Instrument, Span};
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longer client supplied request ids are replaced with a generated one.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, if called within [`request_id`].
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Takes the request id from the `x-request-id` header or generates one.
+///
+/// The id is written back to the request headers, so the [`tower_http::trace::TraceLayer`] logs it,
+/// recorded in a span around the request, and echoed in the response headers.
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let header_value = HeaderValue::from_str(&id).expect("Request id is a valid header value");
+    request
+        .headers_mut()
+        .insert(X_REQUEST_ID.clone(), header_value.clone());
+
+    let span = tracing::info_span!("request", request_id = %id);
+
+    let mut response = REQUEST_ID
+        .scope(id, next.run(request).instrument(span))
+        .await;
+
+    response
+        .headers_mut()
+        .insert(X_REQUEST_ID.clone(), header_value);
+
+    response
+}
+
+/// The token of `Authorization: Bearer <token>` or of the `api_key` header.
+///
+/// If both are present, `Authorization` wins.
+fn request_token(headers: &HeaderMap) -> Result<&str, ApiError> {
+    match headers.get(header::AUTHORIZATION) {
+        Some(authorization) => authorization
+            .to_str()
+            .ok()
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| {
+                tracing::warn!("Malformed Authorization header");
+                ApiError::ApiKeyMissing
+            }),
+        None => headers
+            .get("api_key")
+            .ok_or_else(|| {
+                tracing::warn!("api_key header not present");
+                ApiError::ApiKeyMissing
+            })?
+            .to_str()
+            .map_err(|_| {
+                tracing::warn!("Failed to convert api_key header into str");
+                ApiError::ApiKeyMissing
+            }),
+    }
+}
+
+/// Accepts the token as `Authorization: Bearer <token>` or in the `api_key` header.
+///
+/// If both are present, `Authorization` wins. The admin token is accepted too.
+pub async fn validate_bearer_token(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Result<impl IntoResponse, ApiError> {
+    let api_key = request_token(&headers)?;
+
+    // Both are compared, so the response time does not tell which one matched.
+    if !(state.api_token_valid(api_key) | state.admin_token_valid(api_key)) {
+        tracing::warn!(%api_key, "Invalid api_key");
+        return Err(ApiError::ApiKeyInvalid);
+    }
+
+    let res = next.run(request).await;
+
+    Ok(res)
+}
+
+/// Guards the admin routes. Accepts the token like [`validate_bearer_token`], but only the admin token.
+///
+/// Without a configured admin token, the api tokens are accepted.
+/// A valid api token that is not the admin token is answered with `403 Forbidden`.
+pub async fn validate_admin_token(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Result<impl IntoResponse, ApiError> {
+    let token = request_token(&headers)?;
+
+    if !state.admin_token_valid(token) {
+        if state.api_token_valid(token) {
+            tracing::warn!("Api key used for an admin route");
+            return Err(ApiError::AdminTokenRequired);
+        }
+
+        tracing::warn!(api_key = %token, "Invalid admin token");
+        return Err(ApiError::ApiKeyInvalid);
+    }
+
+    let res = next.run(request).await;
+
+    Ok(res)
+}
+
+/// A slot of the connection limit, held while a request is handled.
+///
+/// Inserted into the request extensions, so a WebSocket upgrade can keep it for the life of the connection.
+#[derive(Clone)]
+pub struct ConnectionPermit {
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+/// Answers `503 Service Unavailable` if the connection limit of the state is reached, instead of queueing the request.
+pub async fn limit_connections(
+    State(state): State<ApiState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(limit) = state.connection_limit() else {
+        return next.run(request).await;
+    };
+
+    let Ok(permit) = limit.clone().try_acquire_owned() else {
+        tracing::warn!("Connection limit reached");
+
+        return ApiError::TooManyConnections.into_response();
+    };
+
+    request.extensions_mut().insert(ConnectionPermit {
+        _permit: Arc::new(permit),
+    });
+
+    next.run(request).await
+}
+
+/// Answers `504 Gateway Timeout` if the handler does not respond within the request timeout of the state.
+///
+/// Only the handler is timed. A body that is streamed after the response started is not cut off.
+pub async fn request_timeout(
+    State(state): State<ApiState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(timeout) = state.request_timeout() else {
+        return next.run(request).await;
+    };
+
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!(?timeout, "Request timed out");
+
+            ApiError::Timeout.into_response()
+        }
+    }
+}
+
+/// Copies the [`MatchedPath`] of the request into the response extensions, for [`RecordLatency`].
+///
+/// Must be added with `route_layer`, as the path is only known once the request was routed.
+pub async fn expose_matched_path(request: Request, next: Next) -> Response {
+    let matched_path = request.extensions().get::<MatchedPath>().cloned();
+
+    let mut response = next.run(request).await;
+
+    if let Some(matched_path) = matched_path {
+        response.extensions_mut().insert(matched_path);
+    }
+
+    response
+}
+
+/// Logs the response like `inner` and records its latency by route in the prometheus metrics.
+///
+/// Responses without a [`MatchedPath`], e.g. of unknown paths, are only logged.
+#[derive(Clone, Debug, Default)]
+pub struct RecordLatency {
+    inner: DefaultOnResponse,
+}
+
+impl RecordLatency {
+    pub fn new(inner: DefaultOnResponse) -> Self {
+        Self { inner }
+    }
+}
+
+impl<B> OnResponse<B> for RecordLatency {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        if let Some(matched_path) = response.extensions().get::<MatchedPath>() {
+            prometheus::record_request_duration(matched_path.as_str(), latency);
+        }
+
+        self.inner.on_response(response, latency, span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::state::ApiStateConfig;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|| async { ApiError::NotFound }))
+            .layer(middleware::from_fn(request_id))
+    }
+
+    #[tokio::test]
+    async fn provided_request_id_is_echoed() {
+        let response = app()
+            .oneshot(
+                Request::get("/")
+                    .header("x-request-id", "my-request")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["x-request-id"], "my-request");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["request_id"], "my-request");
+    }
+
+    #[tokio::test]
+    async fn request_id_is_generated() {
+        let response = app()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let id = response.headers()["x-request-id"].to_str().unwrap();
+
+        assert!(uuid::Uuid::parse_str(id).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_handler_times_out_with_504() {
+        let state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig {
+                request_timeout: std::time::Duration::from_secs(5),
+                ..Default::default()
+            },
+        );
+
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                    "done"
+                }),
+            )
+            .route("/fast", get(|| async { "done" }))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                request_timeout,
+            ))
+            .with_state(state);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let response = app
+            .oneshot(Request::get("/fast").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
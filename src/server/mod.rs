@@ -1,6 +1,25 @@
+pub mod archive;
+pub mod callback;
+pub mod connection_manager;
+pub mod converter;
+pub mod cors;
 pub mod extractors;
+pub mod file_tail;
+pub mod io_chunks;
+pub mod io_forward;
+pub mod labels;
+pub mod metrics;
+pub mod middleware;
+pub mod prometheus;
+pub mod rate_limit;
 pub mod response;
+pub mod scheduler;
+pub mod serve;
+pub mod sharded_map;
+pub mod shutdown;
 pub mod state;
+pub mod stats;
 pub mod task;
+pub mod task_output;
 pub mod utils;
 pub mod ws;
@@ -0,0 +1,107 @@
+use super::{
+    metrics::TaskKind,
+    task::{DownloadZipFileStatus, ExitedStatus, ProcessStatus, Status},
+};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::{sync::OnceLock, time::Duration};
+
+pub const TASKS_STARTED: &str = "job_hub_tasks_started_total";
+pub const TASKS_FINISHED: &str = "job_hub_tasks_finished_total";
+pub const TASKS_FAILED: &str = "job_hub_tasks_failed_total";
+pub const TASKS_CANCELED: &str = "job_hub_tasks_canceled_total";
+pub const TASKS_ACTIVE: &str = "job_hub_tasks_active";
+pub const WS_CONNECTIONS: &str = "job_hub_ws_connections";
+pub const DOWNLOADED_BYTES: &str = "job_hub_downloaded_bytes_total";
+pub const HTTP_REQUEST_DURATION: &str = "job_hub_http_request_duration_seconds";
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Renders the metrics recorded by this process.
+///
+/// The recorder is installed globally on the first call. Metrics recorded before are lost.
+pub fn handle() -> &'static PrometheusHandle {
+    HANDLE.get_or_init(|| {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        if metrics::set_global_recorder(recorder).is_err() {
+            tracing::warn!(
+                "A metrics recorder is already installed. Prometheus metrics stay empty"
+            );
+        }
+
+        metrics::describe_counter!(TASKS_STARTED, "Tasks started");
+        metrics::describe_counter!(TASKS_FINISHED, "Tasks that reached a terminal state");
+        metrics::describe_counter!(
+            TASKS_FAILED,
+            "Tasks that failed, timed out or whose process exited with a failure"
+        );
+        metrics::describe_counter!(TASKS_CANCELED, "Tasks that were canceled");
+        metrics::describe_gauge!(TASKS_ACTIVE, "Tasks that are not done yet");
+        metrics::describe_gauge!(WS_CONNECTIONS, "Connected WebSocket clients");
+        metrics::describe_counter!(DOWNLOADED_BYTES, "Bytes received by all downloads");
+        metrics::describe_histogram!(
+            HTTP_REQUEST_DURATION,
+            metrics::Unit::Seconds,
+            "Time until the response of a route started, rendered as quantiles"
+        );
+
+        handle
+    })
+}
+
+/// Installs the recorder, so the first metrics are not lost.
+pub fn init() {
+    handle();
+}
+
+fn kind_label(kind: TaskKind) -> &'static str {
+    match kind {
+        TaskKind::DownloadZipFile => "download_zip_file",
+        TaskKind::GsLogToLocustConverter => "gs_log_to_locust_converter",
+    }
+}
+
+pub fn task_started(kind: TaskKind) {
+    metrics::counter!(TASKS_STARTED, "kind" => kind_label(kind)).increment(1);
+    metrics::gauge!(TASKS_ACTIVE).increment(1.0);
+}
+
+pub fn task_finished(kind: TaskKind, status: &Status) {
+    let kind = kind_label(kind);
+
+    metrics::counter!(TASKS_FINISHED, "kind" => kind).increment(1);
+    metrics::gauge!(TASKS_ACTIVE).decrement(1.0);
+
+    match status {
+        Status::Failed { .. }
+        | Status::Download(DownloadZipFileStatus::Timeout)
+        | Status::Process(
+            ProcessStatus::Timeout
+            | ProcessStatus::Exited {
+                exit_status: ExitedStatus::Failure { .. },
+            },
+        ) => {
+            metrics::counter!(TASKS_FAILED, "kind" => kind).increment(1);
+        }
+        Status::Download(DownloadZipFileStatus::Canceled)
+        | Status::Process(ProcessStatus::Canceled | ProcessStatus::Killed) => {
+            metrics::counter!(TASKS_CANCELED, "kind" => kind).increment(1);
+        }
+        _ => {}
+    }
+}
+
+pub fn add_downloaded_bytes(bytes: u64) {
+    metrics::counter!(DOWNLOADED_BYTES).increment(bytes);
+}
+
+pub fn set_ws_connections(connections: usize) {
+    metrics::gauge!(WS_CONNECTIONS).set(connections as f64);
+}
+
+/// `route` is the matched path, e.g. `/api/status/:id`, so ids do not end up in the labels.
+pub fn record_request_duration(route: &str, duration: Duration) {
+    metrics::histogram!(HTTP_REQUEST_DURATION, "route" => route.to_string())
+        .record(duration.as_secs_f64());
+}
@@ -0,0 +1,150 @@
+use std::{collections::HashMap, time::Duration};
+use tokio::{sync::RwLock, time::Instant};
+
+/// How often buckets that refilled are dropped. A bucket refills within a minute at most.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: Instant, capacity: f64, tokens_per_sec: f64) -> f64 {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        (self.tokens + elapsed * tokens_per_sec).min(capacity)
+    }
+}
+
+struct Buckets {
+    /// The key is the chat id.
+    by_chat_id: HashMap<String, Bucket>,
+    swept_at: Instant,
+}
+
+/// Token bucket per chat id, limiting how many tasks a chat can start.
+///
+/// A bucket holds up to `rate_per_min` tokens and refills continuously at the same rate,
+/// so a chat can burst up to the full rate and then has to wait for tokens to come back.
+pub struct TaskRateLimiter {
+    /// `0` disables the limit.
+    rate_per_min: u32,
+    /// A full bucket is the same as none, so the ones that refilled are dropped, every [`SWEEP_INTERVAL`].
+    buckets: RwLock<Buckets>,
+}
+
+impl TaskRateLimiter {
+    pub fn new(rate_per_min: u32) -> Self {
+        Self {
+            rate_per_min,
+            buckets: RwLock::new(Buckets {
+                by_chat_id: HashMap::new(),
+                swept_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token for the given chat id.
+    ///
+    /// Returns how long to wait for the next token, if there is none left.
+    pub async fn acquire(&self, chat_id: &str) -> Result<(), Duration> {
+        if self.rate_per_min == 0 {
+            return Ok(());
+        }
+
+        let capacity = f64::from(self.rate_per_min);
+        let tokens_per_sec = capacity / 60.0;
+
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.write().await;
+
+        if now.duration_since(buckets.swept_at) >= SWEEP_INTERVAL {
+            buckets
+                .by_chat_id
+                .retain(|_, bucket| bucket.tokens_at(now, capacity, tokens_per_sec) < capacity);
+            buckets.swept_at = now;
+        }
+
+        let bucket = buckets
+            .by_chat_id
+            .entry(chat_id.to_string())
+            .or_insert(Bucket {
+                tokens: capacity,
+                refilled_at: now,
+            });
+
+        bucket.tokens = bucket.tokens_at(now, capacity, tokens_per_sec);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            return Ok(());
+        }
+
+        // Rounded up, so the token is there when the caller comes back.
+        let retry_after_ms = ((1.0 - bucket.tokens) / tokens_per_sec * 1000.0).ceil();
+
+        Err(Duration::from_millis(retry_after_ms as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_past_the_limit_is_rejected_until_refilled() {
+        let limiter = TaskRateLimiter::new(2);
+
+        assert!(limiter.acquire("chat_id").await.is_ok());
+        assert!(limiter.acquire("chat_id").await.is_ok());
+
+        let retry_after = limiter
+            .acquire("chat_id")
+            .await
+            .expect_err("Burst must be limited");
+        assert_eq!(retry_after, Duration::from_secs(30));
+
+        // Other chats have their own bucket.
+        assert!(limiter.acquire("other_chat_id").await.is_ok());
+
+        tokio::time::advance(retry_after).await;
+
+        assert!(limiter.acquire("chat_id").await.is_ok());
+        assert!(limiter.acquire("chat_id").await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refilled_buckets_are_dropped() {
+        let limiter = TaskRateLimiter::new(60);
+
+        assert!(limiter.acquire("idle_chat_id").await.is_ok());
+        for _ in 0..60 {
+            assert!(limiter.acquire("busy_chat_id").await.is_ok());
+        }
+
+        // Refills the idle bucket, but not the emptied one.
+        tokio::time::advance(SWEEP_INTERVAL - Duration::from_secs(1)).await;
+        assert!(limiter.acquire("busy_chat_id").await.is_ok());
+        tokio::time::advance(Duration::from_secs(1)).await;
+
+        assert!(limiter.acquire("new_chat_id").await.is_ok());
+
+        let buckets = limiter.buckets.read().await;
+        let mut chat_ids: Vec<&String> = buckets.by_chat_id.keys().collect();
+        chat_ids.sort();
+        assert_eq!(chat_ids, [&"busy_chat_id", &"new_chat_id"]);
+    }
+
+    #[tokio::test]
+    async fn zero_rate_disables_the_limit() {
+        let limiter = TaskRateLimiter::new(0);
+
+        for _ in 0..100 {
+            assert!(limiter.acquire("chat_id").await.is_ok());
+        }
+    }
+}
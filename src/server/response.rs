@@ -11,6 +11,9 @@ struct ApiErrorResponse {
     status_code: StatusCode,
     err: ApiError,
     msg: &'static str,
+    /// To correlate a reported error with the server logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 impl From<ApiError> for ApiErrorResponse {
@@ -19,8 +22,14 @@ impl From<ApiError> for ApiErrorResponse {
             ApiError::ChatIdMissing => (StatusCode::BAD_REQUEST, "Chat id missing"),
             ApiError::ApiKeyMissing => (StatusCode::BAD_REQUEST, "Api key missing"),
             ApiError::ApiKeyInvalid => (StatusCode::UNAUTHORIZED, "Api key invalid"),
+            ApiError::AdminTokenRequired => (StatusCode::FORBIDDEN, "Admin token required"),
             ApiError::QueryInvalid => (StatusCode::BAD_REQUEST, "Query invalid"),
             ApiError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
+            ApiError::Timeout => (StatusCode::GATEWAY_TIMEOUT, "Request timed out"),
+            ApiError::TooManyConnections => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many connections. Try again later",
+            ),
             ApiError::InternalServerError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal server error. See server logs",
@@ -31,6 +40,7 @@ impl From<ApiError> for ApiErrorResponse {
             status_code,
             err: value,
             msg,
+            request_id: super::middleware::current_request_id(),
         }
     }
 }
@@ -52,8 +62,12 @@ pub enum ApiError {
     ChatIdMissing,
     ApiKeyMissing,
     ApiKeyInvalid,
+    /// A valid api key, but not the admin token
+    AdminTokenRequired,
     QueryInvalid,
     NotFound,
+    Timeout,
+    TooManyConnections,
     InternalServerError,
 }
 
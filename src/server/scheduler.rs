@@ -0,0 +1,205 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::oneshot;
+
+/// Limits how many tasks run at once. Queued tasks are started by priority, then in the order they were queued.
+#[derive(Clone)]
+pub struct TaskScheduler {
+    inner: Arc<SchedulerInner>,
+}
+
+struct SchedulerInner {
+    /// `0` means no limit
+    max_running: usize,
+    state: Mutex<SchedulerState>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: usize,
+    /// Every queued task gets the next number, so equal priorities keep their order
+    next_seq: u64,
+    queue: BinaryHeap<Queued>,
+}
+
+struct Queued {
+    priority: i32,
+    seq: u64,
+    /// The freed slot is handed over as a permit. If the waiting task is gone by then, the permit comes back
+    tx: oneshot::Sender<SchedulerPermit>,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    /// The greatest is the next to run: the highest priority, then the one queued first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Holds a slot of the [`TaskScheduler`] until it is dropped.
+pub struct SchedulerPermit {
+    /// `None` if the scheduler has no limit or the slot was already released
+    scheduler: Option<Arc<SchedulerInner>>,
+}
+
+impl Drop for SchedulerPermit {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            SchedulerInner::release(&scheduler);
+        }
+    }
+}
+
+impl TaskScheduler {
+    /// `max_running` of `0` runs every task right away.
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            inner: Arc::new(SchedulerInner {
+                max_running,
+                state: Mutex::new(SchedulerState::default()),
+            }),
+        }
+    }
+
+    /// Wait for a free slot. Tasks with a higher `priority` get one first.
+    pub async fn acquire(&self, priority: i32) -> SchedulerPermit {
+        if self.inner.max_running == 0 {
+            return SchedulerPermit { scheduler: None };
+        }
+
+        let rx = {
+            let mut state = self.inner.state.lock().expect("Scheduler lock poisoned");
+
+            if state.running < self.inner.max_running && state.queue.is_empty() {
+                state.running += 1;
+
+                return SchedulerPermit {
+                    scheduler: Some(self.inner.clone()),
+                };
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.queue.push(Queued { priority, seq, tx });
+
+            rx
+        };
+
+        rx.await
+            .expect("The scheduler lives as long as its queued tasks")
+    }
+
+    /// Tasks waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.inner
+            .state
+            .lock()
+            .expect("Scheduler lock poisoned")
+            .queue
+            .len()
+    }
+}
+
+impl SchedulerInner {
+    /// Hand the slot to the next queued task, or free it if none is waiting.
+    fn release(this: &Arc<Self>) {
+        let mut state = this.state.lock().expect("Scheduler lock poisoned");
+
+        while let Some(queued) = state.queue.pop() {
+            let permit = SchedulerPermit {
+                scheduler: Some(this.clone()),
+            };
+
+            match queued.tx.send(permit) {
+                Ok(()) => return,
+                Err(mut permit) => {
+                    // The task stopped waiting. Dropping the permit must not release the slot again.
+                    permit.scheduler = None;
+                }
+            }
+        }
+
+        state.running -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn higher_priority_runs_first_under_a_limit_of_one() {
+        let scheduler = TaskScheduler::new(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let running = scheduler.acquire(0).await;
+
+        let mut waiting = Vec::new();
+        for (name, priority) in [("low", 0), ("high", 10), ("also low", 0)] {
+            let queued_scheduler = scheduler.clone();
+            let order = order.clone();
+
+            waiting.push(tokio::spawn(async move {
+                let _permit = queued_scheduler.acquire(priority).await;
+                order.lock().unwrap().push(name);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }));
+
+            // Queue them one after the other.
+            while scheduler.queued() < waiting.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        drop(running);
+        for task in waiting {
+            task.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), ["high", "low", "also low"]);
+    }
+
+    #[tokio::test]
+    async fn slot_of_a_task_that_stopped_waiting_is_not_lost() {
+        let scheduler = TaskScheduler::new(1);
+
+        let running = scheduler.acquire(0).await;
+
+        let gave_up = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire(5).await }
+        });
+        while scheduler.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        gave_up.abort();
+        let _ = gave_up.await;
+
+        drop(running);
+
+        tokio::time::timeout(Duration::from_secs(1), scheduler.acquire(0))
+            .await
+            .expect("The slot was lost");
+    }
+}
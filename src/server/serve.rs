@@ -0,0 +1,376 @@
+use axum::{
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{oneshot, watch},
+};
+use tower::Service;
+
+/// Load a TLS certificate chain and its private key from PEM files.
+pub async fn rustls_config(cert: &Path, key: &Path) -> Result<RustlsConfig, std::io::Error> {
+    RustlsConfig::from_pem_file(cert, key).await
+}
+
+/// Requests that are being handled, by a running id, for logging the ones abandoned on shutdown.
+#[derive(Default)]
+struct InFlightRequests {
+    next_id: AtomicU64,
+    requests: Mutex<HashMap<u64, String>>,
+}
+
+impl InFlightRequests {
+    fn requests(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .expect("In flight requests lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Removes the request from [`InFlightRequests`] once it is answered or dropped.
+struct InFlightGuard {
+    in_flight: Arc<InFlightRequests>,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight
+            .requests
+            .lock()
+            .expect("In flight requests lock poisoned")
+            .remove(&self.id);
+    }
+}
+
+async fn track_in_flight(
+    State(in_flight): State<Arc<InFlightRequests>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let id = in_flight.next_id.fetch_add(1, Ordering::Relaxed);
+
+    in_flight
+        .requests
+        .lock()
+        .expect("In flight requests lock poisoned")
+        .insert(id, format!("{} {}", request.method(), request.uri()));
+
+    let _guard = InFlightGuard { in_flight, id };
+
+    next.run(request).await
+}
+
+/// Serve `app` on `listener` until `shutdown` completes, over HTTPS if `tls` is given.
+///
+/// Connections in progress are finished before this returns, unless they take longer than `shutdown_timeout`.
+/// Requests still in progress then are logged and abandoned.
+///
+/// A connection that does not send the headers of a request within `header_read_timeout` after it started sending them is closed.
+/// This includes the upgrade request of a WebSocket.
+pub async fn serve<F>(
+    listener: TcpListener,
+    app: Router,
+    tls: Option<RustlsConfig>,
+    shutdown: F,
+    shutdown_timeout: Duration,
+    header_read_timeout: Duration,
+) -> Result<(), std::io::Error>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let in_flight = Arc::new(InFlightRequests::default());
+    let app = app.layer(middleware::from_fn_with_state(
+        in_flight.clone(),
+        track_in_flight,
+    ));
+
+    let (shutdown_started_tx, shutdown_started_rx) = oneshot::channel();
+    let shutdown = async move {
+        shutdown.await;
+        let _ = shutdown_started_tx.send(());
+    };
+
+    let deadline = async move {
+        if shutdown_started_rx.await.is_err() {
+            // The server stopped without a shutdown.
+            return std::future::pending().await;
+        }
+
+        tokio::time::sleep(shutdown_timeout).await;
+    };
+
+    tokio::select! {
+        result = serve_until(listener, app, tls, shutdown, header_read_timeout) => result,
+        _ = deadline => {
+            tracing::warn!(requests = ?in_flight.requests(), "Shutdown timeout elapsed. Abandoning requests in progress");
+
+            Ok(())
+        }
+    }
+}
+
+fn http_builder(header_read_timeout: Duration) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_read_timeout);
+
+    builder
+}
+
+/// Like `axum::serve` with a graceful shutdown, with the `header_read_timeout` `axum::serve` does not offer.
+async fn serve_http<F>(
+    listener: TcpListener,
+    app: Router,
+    shutdown: F,
+    header_read_timeout: Duration,
+) -> Result<(), std::io::Error>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let builder = Arc::new(http_builder(header_read_timeout));
+
+    // Every connection holds a receiver. Sending starts their graceful shutdown, and once all are dropped they are done.
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::ConnectionRefused
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::ConnectionReset
+                ) => continue,
+                Err(err) => {
+                    tracing::warn!(?err, "Failed to accept connection");
+
+                    // E.g. too many open files. Waiting gives the other connections the chance to close some.
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let service = make_service
+            .call(remote_addr)
+            .await
+            .unwrap_or_else(|err| match err {});
+
+        let builder = builder.clone();
+        let mut shutdown_rx = shutdown_rx.clone();
+
+        tokio::spawn(async move {
+            let connection = builder.serve_connection_with_upgrades(
+                TokioIo::new(stream),
+                TowerToHyperService::new(service),
+            );
+            tokio::pin!(connection);
+
+            tokio::select! {
+                result = connection.as_mut() => {
+                    if let Err(err) = result {
+                        tracing::debug!(?err, %remote_addr, "Failed to serve connection");
+                    }
+
+                    return;
+                }
+                _ = shutdown_rx.changed() => connection.as_mut().graceful_shutdown(),
+            }
+
+            if let Err(err) = connection.await {
+                tracing::debug!(?err, %remote_addr, "Failed to serve connection");
+            }
+        });
+    }
+
+    drop(listener);
+    drop(shutdown_rx);
+
+    let _ = shutdown_tx.send(());
+    shutdown_tx.closed().await;
+
+    Ok(())
+}
+
+async fn serve_until<F>(
+    listener: TcpListener,
+    app: Router,
+    tls: Option<RustlsConfig>,
+    shutdown: F,
+    header_read_timeout: Duration,
+) -> Result<(), std::io::Error>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let Some(tls) = tls else {
+        return serve_http(listener, app, shutdown, header_read_timeout).await;
+    };
+
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    let handle = axum_server::Handle::new();
+
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.await;
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    let mut server = axum_server::from_tcp_rustls(listener.into_std()?, tls);
+    *server.http_builder() = http_builder(header_read_timeout);
+
+    server.handle(handle).serve(make_service).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls;
+
+    #[tokio::test]
+    async fn serves_https_with_a_self_signed_certificate() {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")])
+            .expect("Failed to generate certificate");
+
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let tls = rustls_config(&cert_path, &key_path)
+            .await
+            .expect("Failed to load certificate");
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            Some(tls),
+            async {
+                let _ = shutdown_rx.await;
+            },
+            Duration::from_secs(10),
+            Duration::from_secs(10),
+        ));
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(&rustls::Certificate(cert.serialize_der().unwrap()))
+            .unwrap();
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut tls_stream = connector
+            .connect("localhost".try_into().unwrap(), tcp)
+            .await
+            .expect("TLS handshake failed");
+
+        tls_stream
+            .write_all(b"GET /health HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        tls_stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("ok"), "{response}");
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().expect("Server failed");
+    }
+
+    #[tokio::test]
+    async fn hung_request_does_not_block_shutdown_past_the_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        let (request_received_tx, request_received_rx) = oneshot::channel::<()>();
+        let request_received_tx = Arc::new(Mutex::new(Some(request_received_tx)));
+        let app = Router::new().route(
+            "/hang",
+            get(move || async move {
+                if let Some(tx) = request_received_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+
+                std::future::pending::<()>().await
+            }),
+        );
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            None,
+            async {
+                let _ = shutdown_rx.await;
+            },
+            Duration::from_millis(200),
+            Duration::from_secs(10),
+        ));
+
+        let mut tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tcp.write_all(b"GET /hang HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        request_received_rx.await.unwrap();
+
+        shutdown_tx.send(()).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("Shutdown was blocked by the hung request")
+            .unwrap()
+            .expect("Server failed");
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Shards of [`ShardedMap`] used by [`ShardedMap::default`].
+const DEFAULT_SHARDS: usize = 16;
+
+/// A map split into shards that are locked on their own, so operations on different keys rarely wait for each other.
+///
+/// Operations on every key lock all shards, always in the same order.
+pub struct ShardedMap<V> {
+    shards: Box<[RwLock<HashMap<String, V>>]>,
+    hasher: RandomState,
+}
+
+impl<V> Default for ShardedMap<V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARDS)
+    }
+}
+
+impl<V> ShardedMap<V> {
+    /// At least one shard is used.
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<HashMap<String, V>> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+
+        &self.shards[index]
+    }
+
+    /// Read guard of the shard holding `key`.
+    pub async fn read(&self, key: &str) -> RwLockReadGuard<'_, HashMap<String, V>> {
+        self.shard(key).read().await
+    }
+
+    pub async fn insert(&self, key: String, value: V) -> Option<V> {
+        self.shard(&key).write().await.insert(key, value)
+    }
+
+    pub async fn remove(&self, key: &str) -> Option<V> {
+        self.shard(key).write().await.remove(key)
+    }
+
+    pub async fn contains_key(&self, key: &str) -> bool {
+        self.read(key).await.contains_key(key)
+    }
+
+    /// Read guards of every shard.
+    pub async fn read_all(&self) -> Vec<RwLockReadGuard<'_, HashMap<String, V>>> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            guards.push(shard.read().await);
+        }
+
+        guards
+    }
+
+    /// Write guards of every shard. Nothing is inserted or removed while they are held.
+    pub async fn write_all(&self) -> Vec<RwLockWriteGuard<'_, HashMap<String, V>>> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            guards.push(shard.write().await);
+        }
+
+        guards
+    }
+
+    pub async fn len(&self) -> usize {
+        self.read_all().await.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.read_all().await.iter().all(|shard| shard.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_inserts_and_reads_are_not_lost() {
+        const WRITERS: usize = 8;
+        const KEYS_PER_WRITER: usize = 1000;
+
+        let map = Arc::new(ShardedMap::default());
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let map = map.clone();
+                tokio::spawn(async move {
+                    for key in 0..KEYS_PER_WRITER {
+                        let key = format!("{writer}-{key}");
+                        map.insert(key.clone(), writer).await;
+
+                        assert_eq!(map.read(&key).await.get(&key), Some(&writer));
+                    }
+                })
+            })
+            .collect();
+
+        let started = std::time::Instant::now();
+        for writer in writers {
+            writer.await.expect("Writer panicked");
+        }
+        tracing::debug!(elapsed = ?started.elapsed(), "Inserted and read every key");
+
+        assert_eq!(map.len().await, WRITERS * KEYS_PER_WRITER);
+
+        for writer in 0..WRITERS {
+            let key = format!("{writer}-{}", KEYS_PER_WRITER - 1);
+            assert!(map.contains_key(&key).await);
+            assert_eq!(map.remove(&key).await, Some(writer));
+        }
+        assert!(!map.is_empty().await);
+    }
+}
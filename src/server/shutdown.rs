@@ -0,0 +1,137 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{watch, Notify};
+
+/// What a running download does when the server shuts down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DownloadShutdownPolicy {
+    /// Abort the download and remove the files it already extracted
+    #[default]
+    Abort,
+    /// Give the download a grace period to finish, then abort it
+    Finish,
+}
+
+/// Signals running tasks that the server is shutting down and waits for them to settle.
+pub struct ShutdownCoordinator {
+    shutdown_tx: watch::Sender<bool>,
+    active_tasks: AtomicUsize,
+    drained: Notify,
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        let (shutdown_tx, _) = watch::channel(false);
+
+        Self {
+            shutdown_tx,
+            active_tasks: AtomicUsize::new(0),
+            drained: Notify::new(),
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.shutdown_tx.subscribe()
+    }
+
+    pub fn shutdown(&self) {
+        tracing::info!("Signaling shutdown to running tasks");
+
+        self.shutdown_tx.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown_tx.borrow()
+    }
+
+    /// Resolves once [`ShutdownCoordinator::shutdown`] was called.
+    pub async fn shutdown_started(&self) {
+        let mut signal = self.subscribe();
+
+        // The sender lives in `self`, so the channel can not close while waiting.
+        let _ = signal.wait_for(|shutdown| *shutdown).await;
+    }
+
+    /// The returned guard keeps [`ShutdownCoordinator::drained`] pending until it is dropped.
+    pub fn track(self: &Arc<Self>) -> TaskGuard {
+        self.active_tasks.fetch_add(1, Ordering::SeqCst);
+
+        TaskGuard {
+            coordinator: self.clone(),
+        }
+    }
+
+    /// Wait until every tracked task has dropped its [`TaskGuard`].
+    pub async fn drained(&self) {
+        let notified = self.drained.notified();
+        tokio::pin!(notified);
+
+        loop {
+            notified.as_mut().enable();
+
+            let active_tasks = self.active_tasks.load(Ordering::SeqCst);
+            if active_tasks == 0 {
+                return;
+            }
+
+            tracing::info!(%active_tasks, "Waiting for tasks to settle");
+
+            notified.as_mut().await;
+            notified.set(self.drained.notified());
+        }
+    }
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct TaskGuard {
+    coordinator: Arc<ShutdownCoordinator>,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if self.coordinator.active_tasks.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.coordinator.drained.notify_waiters();
+        }
+    }
+}
+
+/// Lets a download task observe the server shutdown according to a [`DownloadShutdownPolicy`].
+pub struct DownloadShutdown {
+    pub signal: watch::Receiver<bool>,
+    pub policy: DownloadShutdownPolicy,
+    /// How long [`DownloadShutdownPolicy::Finish`] waits before aborting
+    pub grace: Duration,
+}
+
+impl DownloadShutdown {
+    /// Resolves once the server started shutting down, regardless of the policy.
+    pub async fn started(&mut self) {
+        if self.signal.wait_for(|shutdown| *shutdown).await.is_err() {
+            // The coordinator is gone, so there will be no shutdown signal.
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Resolves when the download has to be aborted because of a shutdown.
+    pub async fn wait(&mut self) {
+        self.started().await;
+
+        if self.policy == DownloadShutdownPolicy::Finish {
+            tracing::info!(grace=?self.grace, "Shutdown. Giving download a grace period to finish");
+
+            tokio::time::sleep(self.grace).await;
+        }
+
+        tracing::info!("Shutdown. Aborting download");
+    }
+}
@@ -1,17 +1,70 @@
-use super::task::{Handle, Status, Task};
+use super::{
+    archive::{ArchiveEntry, ArchiveSource},
+    callback::{self, CallbackPayload},
+    connection_manager::{
+        ConnectionGuard, ConnectionManager, ConnectionReceiver, SlowClientPolicy,
+    },
+    converter,
+    file_tail::{FileTail, FileTailError},
+    io_chunks::IoOptions,
+    io_forward::{read_task_log, task_log_dir_name, task_log_file_name, IoForwarder, TaskLogFiles},
+    metrics::{TaskKind, TaskMetric, TaskMetrics},
+    prometheus,
+    rate_limit::TaskRateLimiter,
+    scheduler::TaskScheduler,
+    sharded_map::ShardedMap,
+    shutdown::{DownloadShutdown, DownloadShutdownPolicy, ShutdownCoordinator},
+    stats::{ServerStats, Stats},
+    task::{DownloadRetryPolicy, Handle, ResourceLimits, Status, StdinError, Task, TaskStdin},
+    task_output::TaskOutput,
+    ws::{ClientMessage, IoType, ServerMessage},
+};
+use axum::extract::ws::{Message, WebSocket};
+use futures::{SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::SocketAddr,
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicU32, Ordering},
-        Arc,
+        Arc, Mutex, MutexGuard,
     },
+    time::Duration,
 };
 use tokio::{
-    io::{AsyncBufReadExt, AsyncRead, BufReader},
-    sync::RwLock,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{OnceCell, Semaphore},
 };
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+/// Whether `token` is one of `expected`, compared in constant time.
+fn token_matches<'a>(token: &str, expected: impl Iterator<Item = &'a String>) -> bool {
+    use subtle::ConstantTimeEq;
+
+    let token = token.as_bytes();
+
+    let mut valid = subtle::Choice::from(0);
+    for expected in expected {
+        let expected = expected.as_bytes();
+
+        // The length is not secret, only the content is.
+        if token.len() == expected.len() {
+            valid |= token.ct_eq(expected);
+        }
+    }
+
+    bool::from(valid)
+}
+
+/// Lines returned by [`ApiStateInner::tail_newest_file`] at most, however many are asked for.
+pub const MAX_TAIL_LINES: usize = 10_000;
+
+/// Added to the cancel grace for killing an OS process that did not exit in time.
+const CANCEL_KILL_WAIT: Duration = Duration::from_secs(2);
 
 /// I want my [`ApiState`] to be [`Clone`] and [`Send`] and [`Sync`] as is.
 /// So I'm wrapping [`ApiState::inner`] in an [`Arc`].
@@ -21,66 +74,675 @@ pub struct ApiState {
 }
 
 impl ApiState {
-    pub fn new(api_token: String, projects_dir: String) -> Self {
+    pub fn new(api_tokens: HashSet<String>, projects_dir: String, config: ApiStateConfig) -> Self {
         Self {
-            inner: Arc::new(ApiStateInner::new(api_token, projects_dir)),
+            inner: Arc::new(ApiStateInner::new(api_tokens, projects_dir, config)),
         }
     }
 
+    /// Compares in constant time, so a token can not be guessed from response times.
+    ///
+    /// Every configured token is compared, even after a match.
     pub fn api_token_valid(&self, api_token: &str) -> bool {
-        api_token == self.api_token
+        token_matches(api_token, self.api_tokens.iter())
+    }
+
+    /// Whether `token` may use the admin routes. Compared in constant time, like [`ApiState::api_token_valid`].
+    ///
+    /// Falls back to the api tokens if no [`ApiStateConfig::admin_token`] is configured.
+    pub fn admin_token_valid(&self, token: &str) -> bool {
+        match &self.config.admin_token {
+            Some(admin_token) => token_matches(token, std::iter::once(admin_token)),
+            None => self.api_token_valid(token),
+        }
+    }
+
+    /// Subscribe to the [`ServerMessage`]s of a single task, ending with its terminal [`Status`].
+    ///
+    /// Registers a connection with the [`ConnectionManager`] for as long as the stream lives.
+    /// The status is polled, so the terminal event follows the end of the task within [`TASK_EVENTS_STATUS_INTERVAL`].
+    pub async fn task_events(
+        &self,
+        id: &str,
+        chat_id: &str,
+    ) -> Option<impl Stream<Item = TaskEvent>> {
+        self.task_status(id, chat_id).await?;
+
+        let (connection_id, rx) = self
+            .connection_manager
+            .add_connection(chat_id.to_string())
+            .await;
+        let guard = ConnectionGuard::new(connection_id, self.connection_manager.clone());
+
+        self.connection_manager
+            .subscribe(connection_id, id.to_string(), None)
+            .await;
+
+        let subscription = TaskSubscription {
+            state: self.clone(),
+            id: id.to_string(),
+            chat_id: chat_id.to_string(),
+            rx,
+            _guard: guard,
+            interval: tokio::time::interval(TASK_EVENTS_STATUS_INTERVAL),
+            terminated: false,
+        };
+
+        Some(futures::stream::unfold(
+            subscription,
+            TaskSubscription::next,
+        ))
+    }
+}
+
+/// Create the projects directory if it is missing and make sure files can be written to it.
+///
+/// Called once at startup, so a bad directory fails there instead of on the first task.
+pub fn prepare_projects_dir(projects_dir: &Path) -> Result<(), ProjectsDirError> {
+    std::fs::create_dir_all(projects_dir).map_err(|source| ProjectsDirError::Create {
+        path: projects_dir.to_path_buf(),
+        source,
+    })?;
+
+    if !projects_dir.is_dir() {
+        return Err(ProjectsDirError::NotADirectory(projects_dir.to_path_buf()));
+    }
+
+    let probe = projects_dir.join(".write_check");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|source| ProjectsDirError::NotWritable {
+            path: projects_dir.to_path_buf(),
+            source,
+        })
+}
+
+/// How often [`ApiState::task_events`] checks whether the task terminated.
+pub const TASK_EVENTS_STATUS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// An item of [`ApiState::task_events`].
+#[derive(Debug)]
+pub enum TaskEvent {
+    Message(ServerMessage),
+    /// The last event of the stream
+    Terminated(Status),
+}
+
+struct TaskSubscription {
+    state: ApiState,
+    id: String,
+    chat_id: String,
+    rx: ConnectionReceiver,
+    _guard: ConnectionGuard,
+    interval: tokio::time::Interval,
+    terminated: bool,
+}
+
+impl TaskSubscription {
+    async fn next(mut self) -> Option<(TaskEvent, Self)> {
+        if self.terminated {
+            return None;
+        }
+
+        loop {
+            tokio::select! {
+                message = self.rx.recv() => {
+                    // The manager closed the channel, e.g. because the subscriber was too slow.
+                    let message = message?;
+
+                    return Some((TaskEvent::Message(message), self));
+                }
+                _ = self.interval.tick() => {
+                    // `None` if the task was removed from memory in the meantime.
+                    let status = self.state.task_status(&self.id, &self.chat_id).await?;
+
+                    if !status.is_terminal() {
+                        continue;
+                    }
+
+                    // Chunks sent before the task terminated come first.
+                    if let Ok(message) = self.rx.try_recv() {
+                        return Some((TaskEvent::Message(message), self));
+                    }
+
+                    self.terminated = true;
+
+                    return Some((TaskEvent::Terminated(status), self));
+                }
+            }
+        }
+    }
+}
+
+type IdempotencyKey = (String, String);
+
+/// (chat id, idempotency key) to the id of the task started with them, see [`ApiStateInner::start_task_idempotent`].
+///
+/// A key is forgotten when its task is removed from memory.
+#[derive(Clone, Default)]
+struct IdempotencyKeys {
+    inner: Arc<Mutex<IdempotencyKeysInner>>,
+}
+
+#[derive(Default)]
+struct IdempotencyKeysInner {
+    /// Empty while the task is being started
+    keys: HashMap<IdempotencyKey, Arc<OnceCell<String>>>,
+    /// Task id to its key
+    tasks: HashMap<String, IdempotencyKey>,
+}
+
+impl IdempotencyKeys {
+    fn lock(&self) -> MutexGuard<'_, IdempotencyKeysInner> {
+        self.inner.lock().expect("Idempotency keys lock poisoned")
+    }
+
+    /// The cell of the key, holding the id of its task once it is started.
+    fn reserve(&self, key: &IdempotencyKey) -> Arc<OnceCell<String>> {
+        self.lock().keys.entry(key.clone()).or_default().clone()
+    }
+
+    fn started(&self, key: &IdempotencyKey, id: &str) {
+        self.lock().tasks.insert(id.to_string(), key.clone());
+    }
+
+    /// Forget the key after its task failed to start, unless another retry is waiting for it.
+    fn release(&self, key: &IdempotencyKey, cell: &Arc<OnceCell<String>>) {
+        let mut inner = self.lock();
+
+        // Held by the map and the caller only.
+        let unused = inner.keys.get(key).is_some_and(|reserved| {
+            Arc::ptr_eq(reserved, cell) && Arc::strong_count(cell) == 2 && !cell.initialized()
+        });
+
+        if unused {
+            inner.keys.remove(key);
+        }
+    }
+
+    fn forget(&self, id: &str) {
+        let mut inner = self.lock();
+
+        if let Some(key) = inner.tasks.remove(id) {
+            inner.keys.remove(&key);
+        }
+    }
+}
+
+/// Optional settings for [`ApiState`].
+#[derive(Debug, Clone)]
+pub struct ApiStateConfig {
+    /// Values accepted as a task's `metric_label`.
+    /// Anything else is rejected to keep the metrics cardinality low.
+    pub metric_label_allowlist: HashSet<String>,
+    /// Hosts a task's `callback_url` may point to. Empty rejects every callback url.
+    pub callback_host_allowlist: HashSet<String>,
+    /// What to do with WebSocket clients that can not keep up with the broadcast.
+    pub ws_slow_client_policy: SlowClientPolicy,
+    /// How long a WebSocket client has to answer the ping sent after the upgrade, or to send a message, before it is disconnected.
+    pub ws_handshake_timeout: Duration,
+    /// What running downloads do when the server shuts down.
+    pub download_shutdown_policy: DownloadShutdownPolicy,
+    /// Grace period of [`DownloadShutdownPolicy::Finish`].
+    pub download_shutdown_grace: Duration,
+    /// How long a canceled OS process may take to exit before it is killed
+    pub cancel_grace: Duration,
+    /// Limits of the OS processes of tasks
+    pub task_resource_limits: ResourceLimits,
+    pub download_retry_policy: DownloadRetryPolicy,
+    /// Requests to the api with a larger body are rejected with `413 Payload Too Large`.
+    pub max_request_body_bytes: usize,
+    /// Api requests and WebSocket connections handled at once. Further requests are rejected with `503 Service Unavailable`.
+    /// `0` disables the limit.
+    pub max_connections: usize,
+    /// Api requests whose handler takes longer are answered with `504 Gateway Timeout`. Zero disables the timeout.
+    /// Long-lived endpoints, like the WebSocket, task streams, project downloads and long-polling the status, are not timed.
+    pub request_timeout: Duration,
+    /// Tasks a chat may start per minute. `0` disables the limit.
+    pub task_rate_per_min: u32,
+    /// Tasks running at once. Further tasks are queued by priority. `0` disables the limit.
+    pub max_concurrent_tasks: usize,
+    /// IO chunks kept per task for [`ClientMessage::Replay`].
+    pub task_output_buffer_chunks: usize,
+    /// Bytes of IO kept per task for [`ClientMessage::Replay`]. The oldest chunks are dropped first.
+    pub max_task_output_bytes: usize,
+    pub io_options: IoOptions,
+    /// Write the stdout and stderr of tasks to `stdout.log`, `stderr.log` and interleaved to `combined.log` in the `task_<boot id>_<id>` directory
+    /// of the projects directory, where they can be read like the files of a project.
+    pub persist_task_output: bool,
+    /// Gzip the persisted task output. The files get a `.gz` extension
+    pub compress_task_output: bool,
+    /// Token of the admin routes. Without it, the api tokens are accepted there too.
+    pub admin_token: Option<String>,
+}
+
+impl Default for ApiStateConfig {
+    fn default() -> Self {
+        Self {
+            metric_label_allowlist: HashSet::new(),
+            callback_host_allowlist: HashSet::new(),
+            ws_slow_client_policy: SlowClientPolicy::default(),
+            ws_handshake_timeout: Duration::from_secs(10),
+            download_shutdown_policy: DownloadShutdownPolicy::default(),
+            download_shutdown_grace: Duration::from_secs(30),
+            cancel_grace: Duration::from_secs(10),
+            task_resource_limits: ResourceLimits::default(),
+            download_retry_policy: DownloadRetryPolicy {
+                max_retries: 3,
+                backoff: Duration::from_millis(500),
+            },
+            max_request_body_bytes: 2 * 1024 * 1024,
+            request_timeout: Duration::from_secs(30),
+            max_connections: 1024,
+            task_rate_per_min: 0,
+            max_concurrent_tasks: 0,
+            task_output_buffer_chunks: 256,
+            max_task_output_bytes: 1024 * 1024,
+            io_options: IoOptions::default(),
+            persist_task_output: false,
+            compress_task_output: false,
+            admin_token: None,
+        }
     }
 }
 
+/// Options every kind of task accepts when it is started.
+#[derive(Debug, Clone, Default)]
+pub struct TaskOptions {
+    /// Label for the task metrics. Must be in [`ApiStateConfig::metric_label_allowlist`]
+    pub metric_label: Option<String>,
+    /// Set by the client to organize its tasks
+    pub labels: HashMap<String, String>,
+    /// Posted the final status of the task once it is done
+    pub callback_url: Option<url::Url>,
+    /// Queued tasks with a higher priority run first, if [`ApiStateConfig::max_concurrent_tasks`] is reached
+    pub priority: i32,
+    /// Niceness of the OS process of the task, added to the one of the server and clamped to [`super::task::NICE_RANGE`]. Tasks without one, like downloads, ignore it
+    pub priority_nice: Option<i32>,
+    /// Keep the stdin of the OS process of the task open for [`ClientMessage::Stdin`]. Otherwise it reads EOF right away
+    pub interactive: bool,
+}
+
+/// What a task was started with, so [`ApiStateInner::resubmit_task`] can start it again.
+#[derive(Debug, Clone)]
+struct TaskParams {
+    command: TaskCommand,
+    options: TaskOptions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TaskCommand {
+    Download {
+        download_url: url::Url,
+        project_name: String,
+        expected_sha256: Option<String>,
+    },
+    Convert {
+        project_name: String,
+        target: Option<String>,
+    },
+}
+
 /// Collecting relevant data for a task.
 struct TaskData {
     chat_id: String,
+    /// The project the task works on
+    project_name: String,
     handle: Handle,
+    /// Recent IO chunks of the task, for [`ClientMessage::Replay`]
+    output: Arc<TaskOutput>,
+    /// Directory the task writes its results to
+    work_dir: PathBuf,
+    /// Files in [`TaskData::work_dir`], listed once the task is done
+    artifacts: Arc<OnceCell<Vec<String>>>,
+    /// Set by the client to organize its tasks
+    labels: HashMap<String, String>,
+    params: TaskParams,
 }
 
 pub struct ApiStateInner {
-    api_token: String,
+    /// Every token in here is accepted, so tokens can be rotated without downtime.
+    api_tokens: HashSet<String>,
     /// Contains all the tasks that are currently running.
     /// The key is the task id.
-    tasks: Arc<RwLock<HashMap<String, TaskData>>>,
+    tasks: Arc<ShardedMap<TaskData>>,
     /// I'm not wrapping [`ApiStateInner`] in a lock.
     /// So it's a good old [`AtomicU32`].
     current_id: AtomicU32,
+    /// Unique per start of the server, see [`task_log_dir_name`].
+    boot_id: String,
     projects_dir: String,
+    config: ApiStateConfig,
+    task_metrics: Arc<TaskMetrics>,
+    connection_manager: Arc<ConnectionManager>,
+    task_rate_limiter: TaskRateLimiter,
+    scheduler: TaskScheduler,
+    /// Permits for [`ApiStateConfig::max_connections`]
+    connection_limit: Option<Arc<Semaphore>>,
+    shutdown_coordinator: Arc<ShutdownCoordinator>,
+    idempotency_keys: IdempotencyKeys,
+    stats: Arc<ServerStats>,
 }
 
 impl ApiStateInner {
-    pub fn new(api_token: String, projects_dir: String) -> Self {
+    pub fn new(api_tokens: HashSet<String>, projects_dir: String, config: ApiStateConfig) -> Self {
+        let connection_manager = Arc::new(ConnectionManager::new(config.ws_slow_client_policy));
+        let task_rate_limiter = TaskRateLimiter::new(config.task_rate_per_min);
+        let scheduler = TaskScheduler::new(config.max_concurrent_tasks);
+        let connection_limit =
+            (config.max_connections > 0).then(|| Arc::new(Semaphore::new(config.max_connections)));
+
         Self {
-            api_token,
-            tasks: Arc::new(RwLock::new(HashMap::new())),
+            api_tokens,
+            tasks: Arc::new(ShardedMap::default()),
             current_id: AtomicU32::new(0),
+            boot_id: uuid::Uuid::new_v4().simple().to_string(),
             projects_dir,
+            config,
+            task_metrics: Arc::new(TaskMetrics::default()),
+            connection_manager,
+            shutdown_coordinator: Arc::new(ShutdownCoordinator::new()),
+            task_rate_limiter,
+            scheduler,
+            connection_limit,
+            idempotency_keys: IdempotencyKeys::default(),
+            stats: Arc::new(ServerStats::default()),
         }
     }
 
+    /// Tell running tasks that the server is shutting down.
+    pub fn shutdown(&self) {
+        self.shutdown_coordinator.shutdown();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_coordinator.is_shutting_down()
+    }
+
+    /// Resolves once [`ApiStateInner::shutdown`] was called.
+    pub async fn shutdown_started(&self) {
+        self.shutdown_coordinator.shutdown_started().await;
+    }
+
+    /// Wait for the running downloads to finish or abort, according to the [`DownloadShutdownPolicy`].
+    pub async fn drain_tasks(&self) {
+        self.shutdown_coordinator.drained().await;
+    }
+
     pub fn generate_random_chat_id(&self) -> String {
         uuid::Uuid::new_v4().to_string()
     }
 
+    /// Returns the id before the increment, so every call gets a different one, even concurrently.
     fn increment_current_task_id(&self) -> u32 {
-        let id = self.current_id.load(Ordering::Relaxed);
-
-        self.current_id.store(id + 1, Ordering::Relaxed);
-
-        id
+        self.current_id.fetch_add(1, Ordering::Relaxed)
     }
 
     fn project_dir(&self, project_name: &str) -> PathBuf {
         PathBuf::from(&self.projects_dir).join(project_name)
     }
 
+    /// A project name must be a single directory in [`ApiStateInner::projects_dir`].
+    ///
+    /// Only alphanumerics, `-` and `_` are allowed, so a name can not leave the projects directory.
+    fn project_name_valid(project_name: &str) -> bool {
+        !project_name.is_empty()
+            && project_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// Name of the directory, inside the projects directory, that holds the persisted IO of the given task.
+    pub fn task_log_dir_name(&self, task_id: &str) -> String {
+        task_log_dir_name(&self.boot_id, task_id)
+    }
+
+    /// How long a canceled task takes to terminate at most. The cancel grace, then the kill.
+    pub fn cancel_wait(&self) -> Duration {
+        self.config.cancel_grace + CANCEL_KILL_WAIT
+    }
+
+    pub fn max_request_body_bytes(&self) -> usize {
+        self.config.max_request_body_bytes
+    }
+
+    /// `None` if the connections are not limited.
+    pub fn connection_limit(&self) -> Option<&Arc<Semaphore>> {
+        self.connection_limit.as_ref()
+    }
+
+    /// `None` if requests are not timed.
+    pub fn request_timeout(&self) -> Option<Duration> {
+        Some(self.config.request_timeout).filter(|timeout| !timeout.is_zero())
+    }
+
+    fn callback_url_allowed(&self, callback_url: Option<&url::Url>) -> bool {
+        callback_url.is_none_or(|url| {
+            callback::callback_url_allowed(url, &self.config.callback_host_allowlist)
+        })
+    }
+
+    fn metric_label_allowed(&self, metric_label: Option<&str>) -> bool {
+        match metric_label {
+            Some(label) => self.config.metric_label_allowlist.contains(label),
+            None => true,
+        }
+    }
+
+    pub async fn task_metrics(&self) -> Vec<TaskMetric> {
+        self.task_metrics.snapshot().await
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    /// Check the subsystems the tasks depend on.
+    pub async fn health(&self) -> Health {
+        let stats = self.stats();
+        let active_tasks = stats.tasks_queued + stats.tasks_running;
+
+        Health {
+            projects_dir_writable: self.projects_dir_writable().await,
+            task_runner_available: !self.is_shutting_down(),
+            // Tasks only queue up once the limit of running tasks is reached.
+            tasks_within_limit: self.config.max_concurrent_tasks == 0 || stats.tasks_queued == 0,
+            active_tasks,
+        }
+    }
+
+    /// Write and remove a probe file, like [`prepare_projects_dir`] at startup.
+    async fn projects_dir_writable(&self) -> bool {
+        let probe = PathBuf::from(&self.projects_dir)
+            .join(format!(".write_check_{}", uuid::Uuid::new_v4()));
+
+        match tokio::fs::write(&probe, b"").await {
+            Ok(()) => tokio::fs::remove_file(&probe).await.is_ok(),
+            Err(err) => {
+                tracing::warn!(?err, projects_dir = %self.projects_dir, "Projects directory is not writable");
+
+                false
+            }
+        }
+    }
+
+    pub fn connection_manager(&self) -> &ConnectionManager {
+        &self.connection_manager
+    }
+
+    pub async fn connection_count(&self) -> usize {
+        self.connection_manager.connection_count().await
+    }
+
+    pub fn dropped_messages(&self) -> u64 {
+        self.connection_manager.dropped_messages()
+    }
+
+    /// Check a download url without downloading it.
+    ///
+    /// Asks with `HEAD` first. Servers that do not support it are asked for the first byte only.
+    pub async fn probe_download(&self, download_url: url::Url) -> DownloadProbe {
+        let client = reqwest::Client::new();
+
+        let response = match client.head(download_url.clone()).send().await {
+            Ok(response)
+                if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED
+                    || response.status() == reqwest::StatusCode::NOT_IMPLEMENTED =>
+            {
+                client
+                    .get(download_url)
+                    .header(reqwest::header::RANGE, "bytes=0-0")
+                    .send()
+                    .await
+            }
+            response => response,
+        };
+
+        let response = match response {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                tracing::debug!(status=%response.status(), "Download probe failed");
+
+                return DownloadProbe::unreachable();
+            }
+            Err(err) => {
+                tracing::debug!(%err, "Download probe failed");
+
+                return DownloadProbe::unreachable();
+            }
+        };
+
+        let headers = response.headers();
+
+        let content_type = headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+
+        // A ranged response carries the full length after the slash: `bytes 0-0/1234`.
+        let content_length = match headers
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(content_range) => content_range
+                .rsplit_once('/')
+                .and_then(|(_, total)| total.parse().ok()),
+            None => headers
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok()),
+        };
+
+        DownloadProbe {
+            reachable: true,
+            content_length,
+            content_type,
+        }
+    }
+
+    /// Call `start` unless the chat already started a task with the same `idempotency_key`.
+    /// Then the id of that task is returned instead.
+    ///
+    /// A key expires with its task, when the task is removed from memory.
+    pub async fn start_task_idempotent<F, Fut, E>(
+        &self,
+        chat_id: &str,
+        idempotency_key: Option<String>,
+        start: F,
+    ) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+    {
+        let Some(idempotency_key) = idempotency_key else {
+            return start().await;
+        };
+
+        let key = (chat_id.to_string(), idempotency_key);
+
+        // Concurrent retries wait for the same cell, without holding up other keys.
+        let cell = self.idempotency_keys.reserve(&key);
+
+        let mut started = false;
+        let result = cell
+            .get_or_try_init(|| async {
+                started = true;
+
+                let id = start().await?;
+                self.idempotency_keys.started(&key, &id);
+
+                Ok(id)
+            })
+            .await;
+
+        match result {
+            Ok(id) => {
+                if !started {
+                    tracing::debug!(%id, "Task already started with this idempotency key");
+                }
+
+                Ok(id.clone())
+            }
+            Err(err) => {
+                self.idempotency_keys.release(&key, &cell);
+
+                Err(err)
+            }
+        }
+    }
+
     pub async fn run_download_task(
         &self,
         chat_id: String,
         download_url: url::Url,
         project_name: String,
-    ) -> Result<String, std::io::Error> {
+        expected_sha256: Option<String>,
+        options: TaskOptions,
+    ) -> Result<String, RunDownloadTaskError> {
+        let params = TaskParams {
+            command: TaskCommand::Download {
+                download_url: download_url.clone(),
+                project_name: project_name.clone(),
+                expected_sha256: expected_sha256.clone(),
+            },
+            options: options.clone(),
+        };
+
+        let TaskOptions {
+            metric_label,
+            labels,
+            callback_url,
+            priority,
+            priority_nice: _,
+            interactive: _,
+        } = options;
+
+        if !self.metric_label_allowed(metric_label.as_deref()) {
+            return Err(RunDownloadTaskError::MetricLabelNotAllowed);
+        }
+
+        if !Self::project_name_valid(&project_name) {
+            return Err(RunDownloadTaskError::InvalidProjectName);
+        }
+
+        if !self.callback_url_allowed(callback_url.as_ref()) {
+            return Err(RunDownloadTaskError::CallbackUrlNotAllowed);
+        }
+
+        let expected_sha256 = expected_sha256
+            .map(|sha256| {
+                let valid = sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit());
+                valid
+                    .then(|| sha256.to_ascii_lowercase())
+                    .ok_or(RunDownloadTaskError::InvalidSha256)
+            })
+            .transpose()?;
+
+        self.task_rate_limiter
+            .acquire(&chat_id)
+            .await
+            .map_err(|retry_after| RunDownloadTaskError::RateLimited { retry_after })?;
+
         // Let's create a directory for the project
         let project_dir = self.project_dir(&project_name);
         tokio::fs::create_dir_all(&project_dir).await?;
@@ -91,75 +753,176 @@ impl ApiStateInner {
         let timeout = std::time::Duration::from_secs(600);
 
         let (task, task_handle) = Task::new(id.clone());
+        let artifacts = Arc::new(OnceCell::new());
         let task_data = TaskData {
-            chat_id,
+            chat_id: chat_id.clone(),
+            project_name,
             handle: task_handle,
+            output: Arc::new(TaskOutput::new(0)),
+            work_dir: project_dir.clone(),
+            artifacts: artifacts.clone(),
+            labels,
+            params,
         };
 
-        let mut tasks = self.tasks.write().await;
-        tasks.insert(id.clone(), task_data);
+        self.tasks.insert(id.clone(), task_data).await;
 
         let tasks = self.tasks.clone();
+        let idempotency_keys = self.idempotency_keys.clone();
 
-        tokio::spawn(async move {
-            task.run_download_and_unzip_from_download_url(timeout, download_url, project_dir)
+        let task_metrics = self.task_metrics.clone();
+        task_metrics
+            .task_started(TaskKind::DownloadZipFile, metric_label.clone())
+            .await;
+        prometheus::task_started(TaskKind::DownloadZipFile);
+
+        let stats = self.stats.clone();
+        stats.task_queued();
+
+        let scheduler = self.scheduler.clone();
+
+        let span = tracing::info_span!("task", id = %task_id, chat_id = %chat_id);
+
+        let shutdown_coordinator = self.shutdown_coordinator.clone();
+        let mut shutdown = DownloadShutdown {
+            signal: self.shutdown_coordinator.subscribe(),
+            policy: self.config.download_shutdown_policy,
+            grace: self.config.download_shutdown_grace,
+        };
+        let retry_policy = self.config.download_retry_policy;
+
+        let run = async move {
+            let mut task = task;
+
+            // A queued download is not tracked, so it does not hold up the shutdown.
+            match task
+                .wait_for_download_permit(scheduler.acquire(priority), &project_dir, &mut shutdown)
+                .await
+            {
+                Some(permit) => {
+                    let shutdown_guard = shutdown_coordinator.track();
+
+                    stats.task_running();
+
+                    let bytes_downloaded = task
+                        .run_download_and_unzip_from_download_url(
+                            timeout,
+                            download_url,
+                            project_dir.clone(),
+                            expected_sha256,
+                            retry_policy,
+                            shutdown,
+                        )
+                        .await;
+
+                    drop(permit);
+
+                    stats.add_bytes_downloaded(bytes_downloaded);
+                    prometheus::add_downloaded_bytes(bytes_downloaded);
+                    stats.task_finished();
+
+                    record_artifacts(&artifacts, &project_dir).await;
+
+                    drop(shutdown_guard);
+                }
+                None => stats.task_dequeued(),
+            }
+
+            task_metrics
+                .task_finished(TaskKind::DownloadZipFile, metric_label)
                 .await;
+            if let Some(status) = final_status(&tasks, &task_id).await {
+                prometheus::task_finished(TaskKind::DownloadZipFile, &status);
+            }
+
+            if let Some(callback_url) = callback_url {
+                send_task_callback(&tasks, &task_id, callback_url).await;
+            }
 
             // TODO: remove after adding a database.
             // Keeping task in memory for 15 minutes after it's done.
             // simulating an in-memory database.
 
-            tracing::debug!(id=%task_id, "Task finished. Waiting 15 minutes before removing it from memory");
+            tracing::debug!("Task finished. Waiting 15 minutes before removing it from memory");
             tokio::time::sleep(std::time::Duration::from_secs(900)).await;
-            tracing::debug!(id=%task_id, "Removing task from memory");
-            let mut tasks = tasks.write().await;
-            tasks.remove(&task_id);
-        });
+            tracing::debug!("Removing task from memory");
+            tasks.remove(&task_id).await;
+            idempotency_keys.forget(&task_id);
+        };
+
+        // Every log of the task carries its id and chat id.
+        tokio::spawn(run.instrument(span));
 
         Ok(id)
     }
 
-    #[tracing::instrument(skip_all, fields(id=task_id))]
-    async fn trace_stdout<R: AsyncRead + Unpin>(task_id: String, stdout_rx: R) {
-        let buf_reader = BufReader::new(stdout_rx);
-        let mut lines = buf_reader.lines();
+    pub async fn run_gs_log_to_locust_converter_task(
+        &self,
+        chat_id: String,
+        project_name: String,
+        target: Option<String>,
+        options: TaskOptions,
+    ) -> Result<String, ConverterError> {
+        let params = TaskParams {
+            command: TaskCommand::Convert {
+                project_name: project_name.clone(),
+                target: target.clone(),
+            },
+            options: options.clone(),
+        };
 
-        while let Ok(Some(line)) = lines.next_line().await {
-            tracing::trace!("{line}");
-        }
+        let TaskOptions {
+            metric_label,
+            labels,
+            callback_url,
+            priority,
+            priority_nice,
+            interactive,
+        } = options;
 
-        tracing::debug!("Finished reading stdout");
-    }
+        let converter =
+            converter::converter(target.as_deref()).ok_or(ConverterError::UnsupportedTarget)?;
 
-    #[tracing::instrument(skip_all, fields(id=task_id))]
-    async fn trace_stderr<R: AsyncRead + Unpin>(task_id: String, stderr_rx: R) {
-        let buf_reader = BufReader::new(stderr_rx);
-        let mut lines = buf_reader.lines();
+        if !self.metric_label_allowed(metric_label.as_deref()) {
+            return Err(ConverterError::MetricLabelNotAllowed);
+        }
 
-        while let Ok(Some(line)) = lines.next_line().await {
-            tracing::error!("{line}");
+        if !self.callback_url_allowed(callback_url.as_ref()) {
+            return Err(ConverterError::CallbackUrlNotAllowed);
         }
 
-        tracing::debug!("Finished reading stderr");
-    }
+        if !Self::project_name_valid(&project_name) {
+            return Err(ConverterError::InvalidProjectName);
+        }
 
-    pub async fn run_gs_log_to_locust_converter_task(
-        &self,
-        chat_id: String,
-        project_name: String,
-    ) -> Result<String, GsLogToLocstConverterError> {
         let project_dir = self.project_dir(&project_name);
 
         if !project_dir.exists() {
-            return Err(GsLogToLocstConverterError::NotFound);
+            return Err(ConverterError::NotFound);
         }
 
+        if files_in_dir(project_dir.clone()).await?.is_empty() {
+            return Err(ConverterError::Empty);
+        }
+
+        self.task_rate_limiter
+            .acquire(&chat_id)
+            .await
+            .map_err(|retry_after| ConverterError::RateLimited { retry_after })?;
+
         let id = self.increment_current_task_id().to_string();
         let task_id = id.clone();
 
         let timeout = std::time::Duration::from_secs(600);
 
         let (task, task_handle) = Task::new(id.clone());
+        let mut task = task.with_resource_limits(self.config.task_resource_limits);
+        if let Some(nice) = priority_nice {
+            task = task.with_nice(nice);
+        }
+        if interactive {
+            task = task.with_stdin(TaskStdin::Piped);
+        }
 
         // TODO: Move to tests
         // {
@@ -170,218 +933,4344 @@ impl ApiStateInner {
         // drop(task_handle);
         // }
 
+        let output = Arc::new(TaskOutput::with_max_bytes(
+            self.config.task_output_buffer_chunks,
+            self.config.max_task_output_bytes,
+        ));
+
+        let artifacts = Arc::new(OnceCell::new());
         let task_data = TaskData {
-            chat_id,
+            chat_id: chat_id.clone(),
+            project_name,
             handle: task_handle,
+            output: output.clone(),
+            work_dir: project_dir.clone(),
+            artifacts: artifacts.clone(),
+            labels,
+            params,
         };
 
-        let mut tasks = self.tasks.write().await;
-        tasks.insert(id.clone(), task_data);
+        self.tasks.insert(id.clone(), task_data).await;
 
         let tasks = self.tasks.clone();
-        tokio::spawn(async move {
-            let (stdout_tx, stdout_rx) = tokio::io::duplex(100);
-            let (stderr_tx, stderr_rx) = tokio::io::duplex(100);
+        let idempotency_keys = self.idempotency_keys.clone();
 
-            let stdout_task_id = task_id.clone();
-            let stderr_task_id = task_id.clone();
+        let span = tracing::info_span!("task", id = %task_id, chat_id = %chat_id);
 
-            tokio::spawn(async move {
-                Self::trace_stdout(stdout_task_id, stdout_rx).await;
-            });
+        let io_options = self.config.io_options;
+        let cancel_grace = self.config.cancel_grace;
+        let forwarder = IoForwarder {
+            task_id: task_id.clone(),
+            chat_id,
+            connection_manager: self.connection_manager.clone(),
+            output,
+            options: io_options,
+        };
 
-            tokio::spawn(async move {
-                Self::trace_stderr(stderr_task_id, stderr_rx).await;
-            });
+        let task_log_dir = self
+            .config
+            .persist_task_output
+            .then(|| PathBuf::from(&self.projects_dir).join(self.task_log_dir_name(&task_id)));
+        let compress_task_output = self.config.compress_task_output;
+
+        let task_metrics = self.task_metrics.clone();
+        task_metrics
+            .task_started(TaskKind::GsLogToLocustConverter, metric_label.clone())
+            .await;
+        prometheus::task_started(TaskKind::GsLogToLocustConverter);
+
+        let stats = self.stats.clone();
+        stats.task_queued();
+
+        let scheduler = self.scheduler.clone();
+
+        let run = async move {
+            let mut task = task;
+
+            match task
+                .wait_for_process_permit(scheduler.acquire(priority))
+                .await
+            {
+                Some(permit) => {
+                    stats.task_running();
+
+                    // The pipes are as large as a read, so a read is not capped by them.
+                    let pipe_size = io_options.buffer_bytes.max(1);
+                    let (stdout_tx, stdout_rx) = tokio::io::duplex(pipe_size);
+                    let (stderr_tx, stderr_rx) = tokio::io::duplex(pipe_size);
+
+                    let log_files = match task_log_dir {
+                        Some(dir) => match TaskLogFiles::create(&dir, compress_task_output).await {
+                            Ok(files) => Some(files),
+                            Err(err) => {
+                                tracing::error!(
+                                    ?err,
+                                    ?dir,
+                                    "Failed to create task log files. Not persisting IO"
+                                );
 
-            let command = cfg!(target_os = "windows")
-                .then(|| "python")
-                .unwrap_or("python3")
-                .to_string();
+                                None
+                            }
+                        },
+                        None => None,
+                    };
 
-            let path_to_gs_log_to_locst_converter_script = PathBuf::from("ML_ETL")
-                .join("GS")
-                .join("Logfiles")
-                .join("GSLogToLocustConverter.py")
-                .to_string_lossy()
-                .to_string();
+                    tokio::spawn(
+                        forwarder
+                            .forward(stdout_rx, stderr_rx, log_files)
+                            .in_current_span(),
+                    );
 
-            let project_dir = project_dir.to_string_lossy().to_string();
+                    let (command, args) = converter.command(&project_dir);
 
-            let args = vec![
-                path_to_gs_log_to_locst_converter_script,
-                String::from("--directory"),
-                project_dir,
-                String::from("--force"),
-            ];
+                    task.run_os_process(
+                        command,
+                        args,
+                        timeout,
+                        cancel_grace,
+                        Some(stdout_tx),
+                        Some(stderr_tx),
+                    )
+                    .await;
 
-            task.run_os_process(command, args, timeout, Some(stdout_tx), Some(stderr_tx))
+                    drop(permit);
+
+                    stats.task_finished();
+
+                    record_artifacts(&artifacts, &project_dir).await;
+                }
+                None => stats.task_dequeued(),
+            }
+
+            task_metrics
+                .task_finished(TaskKind::GsLogToLocustConverter, metric_label)
                 .await;
+            if let Some(status) = final_status(&tasks, &task_id).await {
+                prometheus::task_finished(TaskKind::GsLogToLocustConverter, &status);
+            }
+
+            if let Some(callback_url) = callback_url {
+                send_task_callback(&tasks, &task_id, callback_url).await;
+            }
 
             // TODO: remove after adding a database.
             // Keeping task in memory for 15 minutes after it's done.
             // simulating an in-memory database.
 
-            tracing::debug!(id=%task_id, "Task finished. Waiting 15 minutes before removing it from memory");
+            tracing::debug!("Task finished. Waiting 15 minutes before removing it from memory");
             tokio::time::sleep(std::time::Duration::from_secs(900)).await;
-            tracing::debug!(id=%task_id, "Removing task from memory");
-            let mut tasks = tasks.write().await;
-            tasks.remove(&task_id);
-        });
+            tracing::debug!("Removing task from memory");
+            tasks.remove(&task_id).await;
+            idempotency_keys.forget(&task_id);
+        };
+
+        // Every log of the task carries its id and chat id.
+        tokio::spawn(run.instrument(span));
 
         Ok(id)
     }
 
-    /// Send a cancel signal to the task with the given id and return immediately.
-    /// The Terminated task will be removed fom memory in a different tokio task which is spawned by [`ApiStateInner::run_task`].
-    pub async fn cancel_task<'a>(&self, id: &'a str, chat_id: &str) -> Option<&'a str> {
-        let tasks = self.tasks.read().await;
-        match tasks.get(id) {
-            Some(task_data) if task_data.chat_id == chat_id => {
-                task_data.handle.send_cancel_signal().await;
+    /// Start a new task with the parameters and options of the task `id` of the chat, e.g. to run a finished job again.
+    ///
+    /// The parameters are checked again, like for a new task, so the server may reject them by now.
+    pub async fn resubmit_task(
+        &self,
+        id: &str,
+        chat_id: &str,
+    ) -> Result<String, ResubmitTaskError> {
+        let TaskParams { command, options } = {
+            let tasks = self.tasks.read(id).await;
 
-                Some(id)
-            }
-            _ => None,
+            tasks
+                .get(id)
+                .filter(|task_data| task_data.chat_id == chat_id)
+                .map(|task_data| task_data.params.clone())
+                .ok_or(ResubmitTaskError::NotFound)?
+        };
+
+        let new_id = match command {
+            TaskCommand::Download {
+                download_url,
+                project_name,
+                expected_sha256,
+            } => {
+                self.run_download_task(
+                    chat_id.to_string(),
+                    download_url,
+                    project_name,
+                    expected_sha256,
+                    options,
+                )
+                .await?
+            }
+            TaskCommand::Convert {
+                project_name,
+                target,
+            } => {
+                self.run_gs_log_to_locust_converter_task(
+                    chat_id.to_string(),
+                    project_name,
+                    target,
+                    options,
+                )
+                .await?
+            }
+        };
+
+        tracing::info!(%id, %new_id, "Task resubmitted");
+
+        Ok(new_id)
+    }
+
+    /// Serve a WebSocket connection of the given chat until it closes.
+    ///
+    /// Forwards the [`ServerMessage`]s of the connection to the socket and acts on the [`ClientMessage`]s it sends.
+    #[tracing::instrument(name = "ws", skip_all, fields(%addr, %chat_id, ?user_agent))]
+    pub async fn accept_connection(
+        &self,
+        socket: WebSocket,
+        chat_id: String,
+        addr: SocketAddr,
+        user_agent: Option<String>,
+    ) {
+        tracing::debug!("Accepted connection");
+
+        let (connection_id, mut rx) = self
+            .connection_manager
+            .add_connection(chat_id.clone())
+            .await;
+        // Removes the connection once the socket closed, or if this future is dropped before.
+        let _guard = ConnectionGuard::new(connection_id, self.connection_manager.clone());
+
+        let (mut sender, mut receiver) = socket.split();
+
+        // A client that stalled after the upgrade would hold the connection forever. A live one answers the ping.
+        // Sending is timed too, a client that does not read can block it.
+        let timeout = self.config.ws_handshake_timeout;
+        let first = tokio::time::timeout(timeout, async {
+            if let Err(err) = sender.send(Message::Ping(Vec::new())).await {
+                return Some(Err(err));
+            }
+
+            receiver.next().await
+        })
+        .await;
+
+        let Ok(first) = first else {
+            tracing::debug!(?timeout, "Handshake timed out");
+
+            let _ = tokio::time::timeout(timeout, sender.send(Message::Close(None))).await;
+
+            return;
+        };
+
+        // The first message is handled like the ones after it.
+        let mut receiver = futures::stream::iter(first).chain(receiver);
+
+        loop {
+            tokio::select! {
+                message = rx.recv() => {
+                    // The manager closed the channel, e.g. because the connection was too slow.
+                    let Some(message) = message else {
+                        break;
+                    };
+
+                    let text = match serde_json::to_string(&message) {
+                        Ok(text) => text,
+                        Err(err) => {
+                            tracing::error!(?err, "Failed to serialize server message");
+
+                            continue;
+                        }
+                    };
+
+                    if let Err(err) = sender.send(Message::Text(text)).await {
+                        tracing::debug!(?err, "Failed to send message");
+
+                        break;
+                    }
+                }
+                message = receiver.next() => {
+                    match message {
+                        Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                            Ok(message) => {
+                                self.handle_client_message(connection_id, &chat_id, message).await;
+                            }
+                            Err(err) => {
+                                tracing::debug!(?err, "Invalid client message");
+                            }
+                        },
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => {
+                            tracing::debug!(?err, "Failed to receive message");
+
+                            break;
+                        }
+                    }
+                }
+            }
         }
+
+        self.connection_manager
+            .remove_connection(connection_id)
+            .await;
+
+        tracing::debug!("Connection closed");
     }
 
-    pub async fn task_status(&self, id: &str, chat_id: &str) -> Option<Status> {
-        let tasks = self.tasks.read().await;
-        match tasks.get(id) {
-            Some(task_data) if task_data.chat_id == chat_id => {
-                let status = task_data.handle.status().await;
+    /// Act on a message a WebSocket connection of the given chat sent.
+    pub async fn handle_client_message(
+        &self,
+        connection_id: u32,
+        chat_id: &str,
+        message: ClientMessage,
+    ) {
+        match message {
+            ClientMessage::Replay { task_id, last_n } => {
+                let (chunks, dropped_bytes) = {
+                    let tasks = self.tasks.read(&task_id).await;
+                    match tasks.get(&task_id) {
+                        Some(task_data) if task_data.chat_id == chat_id => (
+                            task_data.output.last(last_n.unwrap_or(usize::MAX)),
+                            task_data.output.dropped_bytes(),
+                        ),
+                        _ => {
+                            tracing::debug!(%connection_id, %task_id, "Replay of unknown task");
+
+                            return;
+                        }
+                    }
+                };
+
+                if dropped_bytes > 0 {
+                    self.connection_manager
+                        .send(
+                            connection_id,
+                            ServerMessage::ReplayTruncated {
+                                id: task_id,
+                                dropped_bytes,
+                            },
+                        )
+                        .await;
+                }
+
+                for chunk in chunks {
+                    self.connection_manager
+                        .send(connection_id, ServerMessage::TaskIoChunk(chunk))
+                        .await;
+                }
+            }
+            ClientMessage::Subscribe { task_id, io_type } => {
+                self.connection_manager
+                    .subscribe(connection_id, task_id, io_type)
+                    .await;
+            }
+            ClientMessage::TailFile { project, file } => {
+                if let Err(err) = self.tail_file(connection_id, chat_id, project, file).await {
+                    tracing::debug!(%connection_id, ?err, "Failed to tail file");
+                }
+            }
+            ClientMessage::Stdin { task_id, data } => {
+                if let Err(err) = self.write_stdin(&task_id, chat_id, data.into_bytes()).await {
+                    tracing::debug!(%connection_id, %task_id, ?err, "Failed to write to stdin");
 
-                Some(status)
+                    self.connection_manager
+                        .send(
+                            connection_id,
+                            ServerMessage::StdinRejected {
+                                id: task_id,
+                                reason: err.to_string(),
+                            },
+                        )
+                        .await;
+                }
+            }
+            ClientMessage::CloseStdin { task_id } => {
+                if let Err(err) = self.close_stdin(&task_id, chat_id).await {
+                    tracing::debug!(%connection_id, %task_id, ?err, "Failed to close stdin");
+                }
             }
-            _ => None,
         }
     }
 
-    pub async fn list_files(&self, project_name: String) -> Result<Vec<String>, ListFilesError> {
-        let project_dir = PathBuf::from(&self.projects_dir).join(project_name);
+    /// Write `data` to the stdin of a task of the chat.
+    pub async fn write_stdin(
+        &self,
+        id: &str,
+        chat_id: &str,
+        data: Vec<u8>,
+    ) -> Result<(), WriteStdinError> {
+        let handle = self
+            .task_handle(id, chat_id)
+            .await
+            .ok_or(WriteStdinError::NotFound)?;
 
-        if !project_dir.exists() {
-            return Err(ListFilesError::NotFound);
+        handle.write_stdin(data)?;
+
+        Ok(())
+    }
+
+    /// Close the stdin of a task of the chat.
+    pub async fn close_stdin(&self, id: &str, chat_id: &str) -> Result<(), WriteStdinError> {
+        let handle = self
+            .task_handle(id, chat_id)
+            .await
+            .ok_or(WriteStdinError::NotFound)?;
+
+        handle.close_stdin()?;
+
+        Ok(())
+    }
+
+    /// Send the last lines of the file to the connection, then follow it until the connection is removed.
+    async fn tail_file(
+        &self,
+        connection_id: u32,
+        chat_id: &str,
+        project: String,
+        file: String,
+    ) -> Result<(), TailFileError> {
+        const INITIAL_LINES: usize = 100;
+
+        if !Self::project_name_valid(&project) || !Self::file_name_valid(&file) {
+            return Err(TailFileError::InvalidPath);
         }
 
-        let mut read_dir = tokio::fs::read_dir(project_dir).await?;
+        let owned = self.tasks.read_all().await.iter().any(|shard| {
+            shard
+                .values()
+                .any(|task_data| task_data.chat_id == chat_id && task_data.project_name == project)
+        });
+        if !owned {
+            return Err(TailFileError::NotFound);
+        }
 
-        let mut files: Vec<String> = Vec::new();
+        let path = self.project_dir(&project).join(&file);
+        if !tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+        {
+            return Err(TailFileError::NotFound);
+        }
 
-        while let Ok(Some(entry)) = read_dir.next_entry().await {
-            let file_name = entry.file_name();
-            let file_name = file_name.to_string_lossy().to_string();
+        let tail = FileTail::open(path.clone())?;
 
-            files.push(file_name);
+        let lines = Self::tail_lines_before(&path, INITIAL_LINES, tail.offset()).await?;
+        if !lines.is_empty() {
+            self.connection_manager
+                .send(
+                    connection_id,
+                    ServerMessage::FileAppend {
+                        project: project.clone(),
+                        file: file.clone(),
+                        lines,
+                    },
+                )
+                .await;
         }
 
-        Ok(files)
+        let connection_manager = self.connection_manager.clone();
+        let following = tokio::spawn(
+            async move {
+                let result = tail
+                    .follow(|lines| {
+                        connection_manager.send(
+                            connection_id,
+                            ServerMessage::FileAppend {
+                                project: project.clone(),
+                                file: file.clone(),
+                                lines,
+                            },
+                        )
+                    })
+                    .await;
+
+                if let Err(err) = result {
+                    tracing::debug!(%connection_id, ?err, "Stopped tailing file");
+                }
+            }
+            .in_current_span(),
+        );
+
+        self.connection_manager
+            .attach_task(connection_id, following.abort_handle())
+            .await;
+
+        Ok(())
     }
 
-    pub async fn get_file(
+    /// Send a cancel signal to the task with the given id and return immediately.
+    /// The Terminated task will be removed fom memory in a different tokio task which is spawned by [`ApiStateInner::run_task`].
+    pub async fn cancel_task<'a>(&self, id: &'a str, chat_id: &str) -> Option<&'a str> {
+        let handle = self.task_handle(id, chat_id).await?;
+
+        handle.send_cancel_signal().await;
+
+        Some(id)
+    }
+
+    /// Like [`ApiStateInner::cancel_task`], but waits up to `wait` for the task to terminate.
+    ///
+    /// Returns the status of the task by then, which is not terminal if it is still running.
+    pub async fn cancel_task_and_wait(
         &self,
-        project_name: String,
-        file_name: String,
-    ) -> Result<String, GetFileError> {
-        let project_dir = PathBuf::from(&self.projects_dir).join(project_name);
+        id: &str,
+        chat_id: &str,
+        wait: Duration,
+    ) -> Option<Status> {
+        let status_changed = self.task_handle(id, chat_id).await?.status_changed();
 
-        if !project_dir.exists() {
-            return Err(GetFileError::NotFound);
+        self.cancel_task(id, chat_id).await;
+
+        let terminated = async {
+            loop {
+                // Registered before reading the status, so a transition in between is not missed.
+                let changed = status_changed.notified();
+                tokio::pin!(changed);
+                changed.as_mut().enable();
+
+                match self.task_status(id, chat_id).await {
+                    Some(status) if !status.is_terminal() => changed.await,
+                    status => return status,
+                }
+            }
+        };
+
+        match tokio::time::timeout(wait, terminated).await {
+            Ok(status) => status,
+            Err(_) => self.task_status(id, chat_id).await,
         }
+    }
 
-        let file_path = project_dir.join(file_name);
+    /// A clone of the handle of the chat's task, so it is awaited without holding the lock of the tasks.
+    async fn task_handle(&self, id: &str, chat_id: &str) -> Option<Handle> {
+        let tasks = self.tasks.read(id).await;
 
-        if !file_path.exists() {
-            return Err(GetFileError::NotFound);
+        tasks
+            .get(id)
+            .filter(|task_data| task_data.chat_id == chat_id)
+            .map(|task_data| task_data.handle.clone())
+    }
+
+    /// Cancel the task, wait up to `wait` for it to terminate, then remove it from memory and delete its project directory.
+    ///
+    /// The project is kept, and the task is not canceled, if another task that is not done yet works on it.
+    pub async fn delete_task(
+        &self,
+        id: &str,
+        chat_id: &str,
+        wait: Duration,
+    ) -> Result<(), DeleteTaskError> {
+        let (project_name, work_dir) = {
+            let tasks = self.tasks.read(id).await;
+            let task_data = tasks
+                .get(id)
+                .filter(|task_data| task_data.chat_id == chat_id)
+                .ok_or(DeleteTaskError::NotFound)?;
+
+            (task_data.project_name.clone(), task_data.work_dir.clone())
+        };
+
+        let project_in_use = self
+            .tasks
+            .read_all()
+            .await
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .any(|(other_id, other)| {
+                other_id != id && other.project_name == project_name && !other.handle.is_terminal()
+            });
+
+        if project_in_use {
+            return Err(DeleteTaskError::ProjectInUse);
         }
 
-        let file_content = tokio::fs::read_to_string(file_path).await?;
+        let status = self.cancel_task_and_wait(id, chat_id, wait).await;
 
-        Ok(file_content)
+        if status.is_some_and(|status| !status.is_terminal()) {
+            return Err(DeleteTaskError::StillRunning);
+        }
+
+        // Checked again and moved out of the way while holding the lock, like in `delete_project`,
+        // so no task can start on this project in the meantime.
+        let deleted_dir = PathBuf::from(&self.projects_dir)
+            .join(format!(".deleted_{project_name}_{}", uuid::Uuid::new_v4()));
+        let project_deleted = {
+            let mut tasks = self.tasks.write_all().await;
+
+            let project_in_use =
+                tasks
+                    .iter()
+                    .flat_map(|shard| shard.iter())
+                    .any(|(other_id, other)| {
+                        other_id != id
+                            && other.project_name == project_name
+                            && !other.handle.is_terminal()
+                    });
+
+            if project_in_use {
+                return Err(DeleteTaskError::ProjectInUse);
+            }
+
+            for shard in tasks.iter_mut() {
+                shard.remove(id);
+            }
+            self.idempotency_keys.forget(id);
+
+            match tokio::fs::rename(&work_dir, &deleted_dir).await {
+                Ok(()) => true,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => false,
+                Err(err) => return Err(err.into()),
+            }
+        };
+
+        if project_deleted {
+            tokio::fs::remove_dir_all(&deleted_dir).await?;
+
+            tracing::info!(%id, %project_name, "Task and project deleted");
+        } else {
+            tracing::info!(%id, "Task deleted");
+        }
+
+        let task_log_dir = PathBuf::from(&self.projects_dir).join(self.task_log_dir_name(id));
+        match tokio::fs::remove_dir_all(&task_log_dir).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(())
     }
-}
 
-#[derive(Debug, thiserror::Error)]
-pub enum GsLogToLocstConverterError {
-    #[error("Project not found")]
-    NotFound,
-}
+    /// Remove the tasks that finished from memory without waiting for their retention, of every chat.
+    ///
+    /// Returns the number of removed tasks. Their projects and persisted output are kept.
+    pub async fn evict_completed_tasks(&self) -> usize {
+        // No task is added or removed between checking the statuses and removing the terminal ones.
+        let mut shards = self.tasks.write_all().await;
 
-#[derive(Debug, thiserror::Error)]
-pub enum ListFilesError {
-    #[error("Project not found")]
-    NotFound,
-    #[error("IO error: {0}")]
-    IoError(#[from] std::io::Error),
-}
+        let mut evicted = 0;
+        for shard in shards.iter_mut() {
+            shard.retain(|id, task_data| {
+                let terminal = task_data.handle.is_terminal();
+                if terminal {
+                    self.idempotency_keys.forget(id);
+                    evicted += 1;
+                }
 
-#[derive(Debug, thiserror::Error)]
-pub enum GetFileError {
-    #[error("Project/File not found")]
-    NotFound,
-    #[error("IO error: {0}")]
-    IoError(#[from] std::io::Error),
-}
+                !terminal
+            });
+        }
 
-impl Deref for ApiState {
-    type Target = ApiStateInner;
+        tracing::info!(evicted, "Completed tasks evicted");
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+        evicted
     }
-}
 
-impl Drop for ApiStateInner {
-    fn drop(&mut self) {
-        tracing::trace!("Api state inner dropped");
+    pub async fn task_status(&self, id: &str, chat_id: &str) -> Option<Status> {
+        let handle = self.task_handle(id, chat_id).await?;
+
+        Some(handle.status().await)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::server::task::{ProcessStatus, Status::Process};
+    /// Statuses of the given tasks. Ids that are unknown or belong to another chat are left out.
+    pub async fn task_statuses(&self, ids: &[String], chat_id: &str) -> HashMap<String, Status> {
+        let mut statuses = HashMap::new();
+        for id in ids {
+            if let Some(handle) = self.task_handle(id, chat_id).await {
+                statuses.insert(id.clone(), handle.status().await);
+            }
+        }
 
-    fn init_tracing() {
-        if std::env::var_os("RUST_LOG").is_none() {
-            std::env::set_var("RUST_LOG", "job_hub=trace");
+        statuses
+    }
+
+    /// The tasks of a chat, optionally only the ones with the given label, ordered by id.
+    pub async fn list_tasks(
+        &self,
+        chat_id: &str,
+        label: Option<(String, String)>,
+    ) -> Vec<TaskSummary> {
+        // The statuses are read after releasing the shards.
+        let tasks: Vec<_> = self
+            .tasks
+            .read_all()
+            .await
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .filter(|(_, task_data)| task_data.chat_id == chat_id)
+            .filter(|(_, task_data)| {
+                label
+                    .as_ref()
+                    .is_none_or(|(key, value)| task_data.labels.get(key) == Some(value))
+            })
+            .map(|(id, task_data)| {
+                (
+                    id.clone(),
+                    task_data.handle.clone(),
+                    task_data.labels.clone(),
+                )
+            })
+            .collect();
+
+        let mut summaries = Vec::with_capacity(tasks.len());
+        for (id, handle, labels) in tasks {
+            summaries.push(TaskSummary {
+                id,
+                status: handle.status().await,
+                labels,
+            });
         }
 
-        tracing_subscriber::fmt()
-            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-            .init();
+        // Ids are increasing numbers.
+        summaries.sort_by_key(|summary| summary.id.parse::<u32>().unwrap_or(u32::MAX));
+
+        summaries
     }
 
-    // cargo test --package job_hub --lib -- server::state::tests::run_gs_log_to_locst_converter_task --exact --nocapture --ignored
-    // python .\ML_ETL\GS\Logfiles\GSLogToLocustConverter.py --directory .\projects\project\ --force
-    // python3 ML_ETL/GS/Logfiles/GSLogToLocustConverter.py --directory projects/project --force
-    #[tokio::test]
-    #[ignore = "Observation test"]
-    async fn run_gs_log_to_locst_converter_task() {
-        init_tracing();
+    /// Status of a task, with its working directory and artifacts once it is done.
+    pub async fn task_details(&self, id: &str, chat_id: &str) -> Option<TaskDetails> {
+        let (handle, work_dir, artifacts) = {
+            let tasks = self.tasks.read(id).await;
+            let task_data = tasks
+                .get(id)
+                .filter(|task_data| task_data.chat_id == chat_id)?;
 
-        let api_state = ApiState::new("".to_string(), "projects".to_string());
+            (
+                task_data.handle.clone(),
+                task_data.work_dir.clone(),
+                task_data.artifacts.clone(),
+            )
+        };
 
-        let chat_id = "chat_id".to_string();
-        let project_name = "project".to_string();
+        let status = handle.status().await;
 
-        let task_id = api_state
-            .run_gs_log_to_locust_converter_task(chat_id.clone(), project_name)
-            .await
-            .expect("Failed to start task");
+        if !status.is_terminal() {
+            return Some(TaskDetails {
+                status,
+                work_dir: None,
+                artifacts: None,
+            });
+        }
 
-        loop {
-            match api_state.task_status(&task_id, &chat_id).await {
-                Some(Process(ProcessStatus::Created)) => {
-                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                }
-                Some(status) => {
-                    tracing::info!(status = ?status, "Task status");
-                    break;
+        // The status turns terminal right before the task records its artifacts, so they may not be there yet.
+        let artifacts = record_artifacts(&artifacts, &work_dir).await;
+
+        Some(TaskDetails {
+            status,
+            work_dir: Some(work_dir),
+            artifacts: Some(artifacts.clone()),
+        })
+    }
+
+    /// Like [`ApiStateInner::task_details`], but waits up to `wait` for the status of a running task to change.
+    ///
+    /// Returns right away if the task is already done.
+    pub async fn wait_for_task_details(
+        &self,
+        id: &str,
+        chat_id: &str,
+        wait: Duration,
+    ) -> Option<TaskDetails> {
+        let status_changed = {
+            let tasks = self.tasks.read(id).await;
+            let task_data = tasks
+                .get(id)
+                .filter(|task_data| task_data.chat_id == chat_id)?;
+
+            task_data.handle.status_changed()
+        };
+
+        // Registered before reading the status, so a transition in between is not missed.
+        let changed = status_changed.notified();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+
+        let details = self.task_details(id, chat_id).await?;
+        if details.status.is_terminal() {
+            return Some(details);
+        }
+
+        let _ = tokio::time::timeout(wait, changed).await;
+
+        self.task_details(id, chat_id).await
+    }
+
+    /// Everything a task wrote to stdout or stderr so far.
+    ///
+    /// Read from the persisted log, if task output is persisted.
+    /// Otherwise from the buffered chunks of the task, which lack the oldest output once the buffer is full.
+    pub async fn task_output(
+        &self,
+        id: &str,
+        chat_id: &str,
+        io_type: IoType,
+    ) -> Result<String, TaskOutputError> {
+        let output = {
+            let tasks = self.tasks.read(id).await;
+            match tasks.get(id) {
+                Some(task_data) if task_data.chat_id == chat_id => task_data.output.clone(),
+                _ => return Err(TaskOutputError::NotFound),
+            }
+        };
+
+        if self.config.persist_task_output {
+            let stream = match io_type {
+                IoType::Stdout => "stdout",
+                IoType::Stderr => "stderr",
+            };
+            let compressed = self.config.compress_task_output;
+
+            let path = PathBuf::from(&self.projects_dir)
+                .join(self.task_log_dir_name(id))
+                .join(task_log_file_name(stream, compressed));
+
+            match read_task_log(&path, compressed).await {
+                Ok(bytes) => return Ok(String::from_utf8_lossy(&bytes).to_string()),
+                // E.g. the log files could not be created. The buffered chunks are still there.
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                // A compressed file is incomplete while the task runs.
+                Err(err) if compressed && err.kind() == std::io::ErrorKind::UnexpectedEof => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let text = output
+            .last(usize::MAX)
+            .into_iter()
+            .filter(|chunk| chunk.io_type == io_type)
+            .map(|chunk| chunk.chunk)
+            .collect();
+
+        Ok(text)
+    }
+
+    /// List the files of a project whose names match the glob `pattern`,
+    /// sorted by `sort`, skipping `offset` files and returning at most `limit`.
+    pub async fn list_files(
+        &self,
+        project_name: String,
+        pattern: Option<&str>,
+        sort: FileSort,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<FilesPage, ListFilesError> {
+        let pattern = pattern
+            .map(glob::Pattern::new)
+            .transpose()
+            .map_err(|_| ListFilesError::InvalidPattern)?;
+
+        if !Self::project_name_valid(&project_name) {
+            return Err(ListFilesError::InvalidProjectName);
+        }
+
+        let project_dir = PathBuf::from(&self.projects_dir).join(project_name);
+
+        if !project_dir.exists() {
+            return Err(ListFilesError::NotFound);
+        }
+
+        let mut read_dir = tokio::fs::read_dir(project_dir).await?;
+
+        let mut files: Vec<(FileInfo, Option<std::time::SystemTime>)> = Vec::new();
+
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy().to_string();
+
+            if let Some(pattern) = &pattern {
+                if !pattern.matches(&file_name) {
+                    continue;
                 }
-                _ => break,
             }
+
+            let metadata = entry.metadata().await?;
+            let modified = metadata.modified().ok();
+
+            let file_info = FileInfo {
+                name: file_name,
+                size_bytes: metadata.len(),
+                modified: modified
+                    .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|since_epoch| since_epoch.as_secs()),
+            };
+
+            files.push((file_info, modified));
+        }
+
+        match sort {
+            FileSort::Name => files.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name)),
+            // Newest first, the name keeps the order stable for equal times
+            FileSort::Modified => files.sort_by(|(a, a_modified), (b, b_modified)| {
+                b_modified.cmp(a_modified).then_with(|| a.name.cmp(&b.name))
+            }),
         }
+
+        let total = files.len();
+
+        let files = files
+            .into_iter()
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(file_info, _)| file_info)
+            .collect();
+
+        Ok(FilesPage { files, total })
+    }
+
+    /// The last `lines` lines of the most recently modified file of a project, at most [`MAX_TAIL_LINES`].
+    pub async fn tail_newest_file(
+        &self,
+        project_name: String,
+        lines: usize,
+    ) -> Result<TailedFile, TailError> {
+        if !Self::project_name_valid(&project_name) {
+            return Err(TailError::InvalidProjectName);
+        }
+
+        let lines = lines.min(MAX_TAIL_LINES);
+        let project_dir = self.project_dir(&project_name);
+
+        if !project_dir.exists() {
+            return Err(TailError::NotFound);
+        }
+
+        let mut read_dir = tokio::fs::read_dir(&project_dir).await?;
+
+        let mut newest: Option<(std::time::SystemTime, String)> = None;
+
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let modified = metadata.modified()?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+
+            if newest.as_ref().is_none_or(|(newest, _)| modified > *newest) {
+                newest = Some((modified, file_name));
+            }
+        }
+
+        let (_, file_name) = newest.ok_or(TailError::NotFound)?;
+
+        let lines = Self::tail_lines(&project_dir.join(&file_name), lines).await?;
+
+        Ok(TailedFile { file_name, lines })
+    }
+
+    /// Reads the file backwards in chunks until it has seen enough lines, so big files are not read as a whole.
+    async fn tail_lines(
+        path: &std::path::Path,
+        lines: usize,
+    ) -> Result<Vec<String>, std::io::Error> {
+        let len = tokio::fs::metadata(path).await?.len();
+
+        Self::tail_lines_before(path, lines, len).await
+    }
+
+    /// Like [`ApiStateInner::tail_lines`], but ignores everything after the first `end` bytes of the file.
+    async fn tail_lines_before(
+        path: &std::path::Path,
+        lines: usize,
+        end: u64,
+    ) -> Result<Vec<String>, std::io::Error> {
+        const CHUNK_SIZE: u64 = 8 * 1024;
+
+        if lines == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut position = end;
+
+        let mut tail: Vec<u8> = Vec::new();
+        let mut newlines = 0;
+
+        // One more newline than lines, so the first line is complete, even if the file ends with a newline.
+        while position > 0 && newlines <= lines {
+            let chunk_size = CHUNK_SIZE.min(position);
+            position -= chunk_size;
+
+            let mut chunk = vec![0; chunk_size as usize];
+            file.seek(std::io::SeekFrom::Start(position)).await?;
+            file.read_exact(&mut chunk).await?;
+
+            newlines += chunk.iter().filter(|byte| **byte == b'\n').count();
+
+            chunk.extend_from_slice(&tail);
+            tail = chunk;
+        }
+
+        let tail = String::from_utf8_lossy(&tail);
+        let tail: Vec<&str> = tail.lines().collect();
+
+        let start = tail.len().saturating_sub(lines);
+
+        Ok(tail[start..].iter().map(|line| line.to_string()).collect())
+    }
+
+    /// Remove the project directory, unless a task that is not done yet works on it.
+    pub async fn delete_project(&self, project_name: &str) -> Result<(), DeleteProjectError> {
+        if !Self::project_name_valid(project_name) {
+            return Err(DeleteProjectError::InvalidProjectName);
+        }
+
+        let project_dir = self.project_dir(project_name);
+
+        if !project_dir.is_dir() {
+            return Err(DeleteProjectError::NotFound);
+        }
+
+        // Moved out of the way while holding the lock, so no task can start on this project in the meantime.
+        // The name can not be taken by a project, so it is deleted after releasing the lock.
+        let deleted_dir = PathBuf::from(&self.projects_dir)
+            .join(format!(".deleted_{project_name}_{}", uuid::Uuid::new_v4()));
+        {
+            let tasks = self.tasks.write_all().await;
+
+            let in_use = tasks
+                .iter()
+                .flat_map(|shard| shard.values())
+                .any(|task_data| {
+                    task_data.project_name == project_name && !task_data.handle.is_terminal()
+                });
+
+            if in_use {
+                return Err(DeleteProjectError::InUse);
+            }
+
+            tokio::fs::rename(&project_dir, &deleted_dir).await?;
+        }
+
+        tokio::fs::remove_dir_all(&deleted_dir).await?;
+
+        tracing::info!(%project_name, "Project deleted");
+
+        Ok(())
+    }
+
+    /// Every file of a project, including the ones in subdirectories, named relative to the project directory.
+    ///
+    /// Symlinks are skipped, so the archive can not reach outside of the project directory.
+    pub async fn project_archive_entries(
+        &self,
+        project_name: &str,
+    ) -> Result<Vec<ArchiveEntry>, DownloadProjectError> {
+        if !Self::project_name_valid(project_name) {
+            return Err(DownloadProjectError::InvalidProjectName);
+        }
+
+        let project_dir = self.project_dir(project_name);
+
+        if !project_dir.is_dir() {
+            return Err(DownloadProjectError::NotFound);
+        }
+
+        let entries = files_in_dir(project_dir)
+            .await?
+            .into_iter()
+            .map(|(name, path)| ArchiveEntry {
+                name,
+                source: ArchiveSource::File(path),
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// The selected files of a project, split into the ones that exist and the ones that do not.
+    ///
+    /// A file selected more than once is only returned once.
+    ///
+    /// A file name may point into a subdirectory, but must not leave the project directory.
+    pub async fn selected_archive_entries(
+        &self,
+        project_name: &str,
+        files: Vec<String>,
+    ) -> Result<SelectedFiles, DownloadProjectError> {
+        if !Self::project_name_valid(project_name) {
+            return Err(DownloadProjectError::InvalidProjectName);
+        }
+
+        let project_dir = self.project_dir(project_name);
+
+        if !project_dir.is_dir() {
+            return Err(DownloadProjectError::NotFound);
+        }
+
+        let mut selected = SelectedFiles::default();
+        let mut seen = HashSet::new();
+
+        for file_name in files {
+            if !Self::file_name_valid(&file_name) {
+                return Err(DownloadProjectError::InvalidFileName(file_name));
+            }
+
+            // `a//b` and `a/b/` are the same file as `a/b`, which goes into the archive once.
+            let file_name = std::path::Path::new(&file_name)
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            if !seen.insert(file_name.clone()) {
+                continue;
+            }
+
+            let path = project_dir.join(&file_name);
+
+            // Symlinks are treated as missing, like in the whole project archive.
+            match tokio::fs::symlink_metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => selected.entries.push(ArchiveEntry {
+                    name: file_name,
+                    source: ArchiveSource::File(path),
+                }),
+                Ok(_) => selected.missing.push(file_name),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    selected.missing.push(file_name)
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(selected)
+    }
+
+    /// A relative path made of normal components only.
+    fn file_name_valid(file_name: &str) -> bool {
+        let mut components = std::path::Path::new(file_name).components().peekable();
+
+        components.peek().is_some()
+            && components.all(|component| matches!(component, std::path::Component::Normal(_)))
+    }
+
+    fn file_path(&self, project_name: String, file_name: String) -> Result<PathBuf, GetFileError> {
+        if !Self::project_name_valid(&project_name) || !Self::file_name_valid(&file_name) {
+            return Err(GetFileError::InvalidName);
+        }
+
+        let project_dir = PathBuf::from(&self.projects_dir).join(project_name);
+
+        if !project_dir.exists() {
+            return Err(GetFileError::NotFound);
+        }
+
+        let file_path = project_dir.join(file_name);
+
+        if !file_path.exists() {
+            return Err(GetFileError::NotFound);
+        }
+
+        Ok(file_path)
+    }
+
+    /// With `lossy`, invalid UTF-8 is replaced with `U+FFFD` instead of failing with [`GetFileError::InvalidUtf8`].
+    pub async fn get_file(
+        &self,
+        project_name: String,
+        file_name: String,
+        lossy: bool,
+    ) -> Result<String, GetFileError> {
+        let file_path = self.file_path(project_name, file_name)?;
+
+        let file_content = tokio::fs::read(file_path).await?;
+
+        if lossy {
+            return Ok(String::from_utf8_lossy(&file_content).into_owned());
+        }
+
+        String::from_utf8(file_content).map_err(|_| GetFileError::InvalidUtf8)
+    }
+
+    /// Metadata of a file, without reading it.
+    pub async fn file_metadata(
+        &self,
+        project_name: String,
+        file_name: String,
+    ) -> Result<std::fs::Metadata, GetFileError> {
+        let file_path = self.file_path(project_name, file_name)?;
+
+        let metadata = tokio::fs::metadata(file_path).await?;
+
+        if !metadata.is_file() {
+            return Err(GetFileError::NotFound);
+        }
+
+        Ok(metadata)
+    }
+
+    /// Like [`ApiStateInner::get_file`], but does not expect the content to be text.
+    pub async fn get_file_bytes(
+        &self,
+        project_name: String,
+        file_name: String,
+    ) -> Result<Vec<u8>, GetFileError> {
+        let file_path = self.file_path(project_name, file_name)?;
+
+        let file_content = tokio::fs::read(file_path).await?;
+
+        Ok(file_content)
+    }
+}
+
+/// Every file in `dir`, including the ones in subdirectories, named relative to `dir` and sorted by name.
+///
+/// Symlinks are skipped.
+async fn files_in_dir(dir: PathBuf) -> Result<Vec<(String, PathBuf)>, std::io::Error> {
+    let mut files = Vec::new();
+    let mut dirs = vec![(dir, String::new())];
+
+    while let Some((dir, prefix)) = dirs.pop() {
+        let mut read_dir = tokio::fs::read_dir(&dir).await?;
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+
+            if file_type.is_dir() {
+                dirs.push((entry.path(), format!("{name}/")));
+            } else if file_type.is_file() {
+                files.push((name, entry.path()));
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(files)
+}
+
+/// List the files a task left in `work_dir`, if not done yet.
+async fn record_artifacts<'a>(
+    artifacts: &'a OnceCell<Vec<String>>,
+    work_dir: &Path,
+) -> &'a Vec<String> {
+    artifacts
+        .get_or_init(|| async {
+            match files_in_dir(work_dir.to_path_buf()).await {
+                Ok(files) => files.into_iter().map(|(name, _)| name).collect(),
+                Err(err) => {
+                    // E.g. a canceled download removes its directory.
+                    tracing::debug!(?err, ?work_dir, "Failed to list artifacts");
+
+                    Vec::new()
+                }
+            }
+        })
+        .await
+}
+
+/// Status of a task that is done. `None` if it is not in memory anymore.
+async fn final_status(tasks: &ShardedMap<TaskData>, task_id: &str) -> Option<Status> {
+    let handle = tasks.read(task_id).await.get(task_id)?.handle.clone();
+
+    Some(handle.status().await)
+}
+
+/// Post the final status of the task to its callback url.
+async fn send_task_callback(tasks: &ShardedMap<TaskData>, task_id: &str, callback_url: url::Url) {
+    let Some(status) = final_status(tasks, task_id).await else {
+        return;
+    };
+
+    let payload = CallbackPayload {
+        id: task_id.to_string(),
+        exit_code: status.exit_code(),
+        status,
+    };
+
+    callback::send_callback(callback_url, payload).await;
+}
+
+/// A task in [`ApiStateInner::list_tasks`].
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TaskSummary {
+    #[schema(example = "0")]
+    pub id: String,
+    pub status: Status,
+    #[schema(example = json!({"env": "prod"}))]
+    pub labels: HashMap<String, String>,
+}
+
+/// Result of [`ApiStateInner::task_details`].
+pub struct TaskDetails {
+    pub status: Status,
+    /// Set once the task is done
+    pub work_dir: Option<PathBuf>,
+    /// Files in [`TaskDetails::work_dir`], relative to it. Set once the task is done
+    pub artifacts: Option<Vec<String>>,
+}
+
+/// Result of [`ApiStateInner::probe_download`].
+#[derive(Debug)]
+pub struct DownloadProbe {
+    /// The url answered with a success status
+    pub reachable: bool,
+    /// Size of the file in bytes
+    pub content_length: Option<u64>,
+    pub content_type: Option<String>,
+}
+
+impl DownloadProbe {
+    fn unreachable() -> Self {
+        Self {
+            reachable: false,
+            content_length: None,
+            content_type: None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RunDownloadTaskError {
+    #[error("Metric label not allowed")]
+    MetricLabelNotAllowed,
+    #[error("Callback url not allowed")]
+    CallbackUrlNotAllowed,
+    #[error("Invalid project name")]
+    InvalidProjectName,
+    #[error("Sha256 must be 64 hex characters")]
+    InvalidSha256,
+    #[error("Too many tasks started. Retry after {retry_after:?}")]
+    RateLimited { retry_after: Duration },
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConverterError {
+    #[error("Project not found")]
+    NotFound,
+    #[error("Project has no files to convert")]
+    Empty,
+    #[error("Invalid project name")]
+    InvalidProjectName,
+    #[error("Unsupported conversion target")]
+    UnsupportedTarget,
+    #[error("Metric label not allowed")]
+    MetricLabelNotAllowed,
+    #[error("Callback url not allowed")]
+    CallbackUrlNotAllowed,
+    #[error("Too many tasks started. Retry after {retry_after:?}")]
+    RateLimited { retry_after: Duration },
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResubmitTaskError {
+    #[error("Task not found")]
+    NotFound,
+    #[error(transparent)]
+    Download(#[from] RunDownloadTaskError),
+    #[error(transparent)]
+    Converter(#[from] ConverterError),
+}
+
+/// Status of the subsystems, from [`ApiStateInner::health`].
+#[derive(Debug, Clone, Copy)]
+pub struct Health {
+    pub projects_dir_writable: bool,
+    /// The server is not shutting down, so new tasks are started
+    pub task_runner_available: bool,
+    /// No tasks wait for the limit of concurrently running tasks
+    pub tasks_within_limit: bool,
+    /// Queued and running tasks
+    pub active_tasks: u64,
+}
+
+impl Health {
+    pub fn healthy(&self) -> bool {
+        self.projects_dir_writable && self.task_runner_available && self.tasks_within_limit
+    }
+}
+
+/// Order of the files returned by [`ApiStateInner::list_files`].
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSort {
+    /// By name, ascending
+    #[default]
+    Name,
+    /// By modification time, newest first
+    Modified,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FileInfo {
+    /// Name of the file
+    #[schema(example = "file_1.log")]
+    pub name: String,
+    /// Size of the file in bytes
+    #[schema(example = 1024)]
+    pub size_bytes: u64,
+    /// Last modification time in seconds since the Unix epoch
+    #[schema(example = 1707000000)]
+    pub modified: Option<u64>,
+}
+
+/// A page of the files of a project.
+pub struct FilesPage {
+    pub files: Vec<FileInfo>,
+    /// Number of files in the project, regardless of the page
+    pub total: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListFilesError {
+    #[error("Project not found")]
+    NotFound,
+    #[error("Invalid glob pattern")]
+    InvalidPattern,
+    #[error("Invalid project name")]
+    InvalidProjectName,
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+pub struct TailedFile {
+    pub file_name: String,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProjectsDirError {
+    #[error("Failed to create projects directory {path:?}: {source}")]
+    Create {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Projects directory {0:?} is not a directory")]
+    NotADirectory(PathBuf),
+    #[error("Projects directory {path:?} is not writable: {source}")]
+    NotWritable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TaskOutputError {
+    #[error("Task not found")]
+    NotFound,
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TailError {
+    #[error("Project not found or empty")]
+    NotFound,
+    #[error("Invalid project name")]
+    InvalidProjectName,
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WriteStdinError {
+    #[error("Task not found")]
+    NotFound,
+    #[error("Failed to write to stdin: {0}")]
+    Stdin(#[from] StdinError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TailFileError {
+    #[error("Invalid project or file name")]
+    InvalidPath,
+    #[error("Project/File not found")]
+    NotFound,
+    #[error("Failed to follow the file: {0}")]
+    Follow(#[from] FileTailError),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Default)]
+pub struct SelectedFiles {
+    pub entries: Vec<ArchiveEntry>,
+    /// Selected file names that do not exist in the project
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadProjectError {
+    #[error("Invalid project name")]
+    InvalidProjectName,
+    #[error("Invalid file name: {0}")]
+    InvalidFileName(String),
+    #[error("Project not found")]
+    NotFound,
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeleteTaskError {
+    #[error("Task not found")]
+    NotFound,
+    #[error("Project is used by another running task")]
+    ProjectInUse,
+    #[error("Task did not terminate in time")]
+    StillRunning,
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeleteProjectError {
+    #[error("Invalid project name")]
+    InvalidProjectName,
+    #[error("Project not found")]
+    NotFound,
+    #[error("Project is used by a running task")]
+    InUse,
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GetFileError {
+    #[error("Project/File not found")]
+    NotFound,
+    #[error("File is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("Invalid project or file name")]
+    InvalidName,
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+impl Deref for ApiState {
+    type Target = ApiStateInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Drop for ApiStateInner {
+    fn drop(&mut self) {
+        tracing::trace!("Api state inner dropped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::task::{DownloadZipFileStatus, FailureKind, ProcessStatus, Status::Process};
+    use crate::server::ws::TaskIoChunk;
+    use futures::StreamExt;
+    use std::io::Write;
+
+    fn init_tracing() {
+        if std::env::var_os("RUST_LOG").is_none() {
+            std::env::set_var("RUST_LOG", "job_hub=trace");
+        }
+
+        tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+            .init();
+    }
+
+    /// Parameters of the tasks that are inserted by hand.
+    fn converter_params() -> TaskParams {
+        TaskParams {
+            command: TaskCommand::Convert {
+                project_name: String::from("project"),
+                target: None,
+            },
+            options: TaskOptions::default(),
+        }
+    }
+
+    // cargo test --package job_hub --lib -- server::state::tests::run_gs_log_to_locst_converter_task --exact --nocapture --ignored
+    // python .\ML_ETL\GS\Logfiles\GSLogToLocustConverter.py --directory .\projects\project\ --force
+    // python3 ML_ETL/GS/Logfiles/GSLogToLocustConverter.py --directory projects/project --force
+    #[tokio::test]
+    #[ignore = "Observation test"]
+    async fn run_gs_log_to_locst_converter_task() {
+        init_tracing();
+
+        let api_state = ApiState::new(
+            Default::default(),
+            "projects".to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let chat_id = "chat_id".to_string();
+        let project_name = "project".to_string();
+
+        let task_id = api_state
+            .run_gs_log_to_locust_converter_task(
+                chat_id.clone(),
+                project_name,
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        loop {
+            match api_state.task_status(&task_id, &chat_id).await {
+                Some(Process(ProcessStatus::Created)) => {
+                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                }
+                Some(status) => {
+                    tracing::info!(status = ?status, "Task status");
+                    break;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn api_state_with_metric_labels(projects_dir: &std::path::Path, labels: &[&str]) -> ApiState {
+        let config = ApiStateConfig {
+            metric_label_allowlist: labels.iter().map(|label| label.to_string()).collect(),
+            download_retry_policy: DownloadRetryPolicy {
+                max_retries: 0,
+                backoff: Duration::ZERO,
+            },
+            ..Default::default()
+        };
+
+        ApiState::new(
+            Default::default(),
+            projects_dir.to_string_lossy().to_string(),
+            config,
+        )
+    }
+
+    #[tokio::test]
+    async fn task_metrics_carry_metric_label() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_metric_labels(projects_dir.path(), &["nightly"]);
+
+        // Nothing is listening on port 1, so the download fails right away.
+        let download_url = url::Url::parse("http://127.0.0.1:1/file.zip").expect("valid url");
+
+        api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                TaskOptions {
+                    metric_label: Some("nightly".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("Failed to start task");
+
+        let metric = loop {
+            let metrics = api_state.task_metrics().await;
+            let metric = metrics
+                .into_iter()
+                .find(|metric| metric.label.as_deref() == Some("nightly"))
+                .expect("No metric with the expected label");
+
+            if metric.finished == 1 {
+                break metric;
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+
+        assert_eq!(metric.kind, TaskKind::DownloadZipFile);
+        assert_eq!(metric.started, 1);
+    }
+
+    #[test]
+    fn missing_projects_dir_is_created() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let projects_dir = dir.path().join("nested").join("projects");
+
+        prepare_projects_dir(&projects_dir).expect("Failed to prepare projects dir");
+
+        assert!(projects_dir.is_dir());
+        assert_eq!(std::fs::read_dir(&projects_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn unusable_projects_dir_is_a_descriptive_error() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let file = dir.path().join("file");
+        std::fs::write(&file, "not a directory").unwrap();
+
+        // A file where the directory should be
+        let err = prepare_projects_dir(&file).expect_err("A file is not a projects dir");
+        assert!(matches!(err, ProjectsDirError::Create { .. }));
+        assert!(err
+            .to_string()
+            .contains("Failed to create projects directory"));
+        assert!(err.to_string().contains(&*file.to_string_lossy()));
+
+        // A directory that can not be created below a file
+        let err = prepare_projects_dir(&file.join("projects"))
+            .expect_err("Can not create a directory in a file");
+        assert!(matches!(err, ProjectsDirError::Create { .. }));
+    }
+
+    #[tokio::test]
+    async fn callback_is_posted_when_the_task_is_done() {
+        let (callback_tx, mut callback_rx) = tokio::sync::mpsc::channel(1);
+        let app = axum::Router::new().route(
+            "/done",
+            axum::routing::post(move |body: String| async move {
+                callback_tx.send(body).await.unwrap();
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let callback_url = url::Url::parse(&format!(
+            "http://{}/done",
+            listener.local_addr().expect("Failed to get local addr")
+        ))
+        .expect("valid url");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig {
+                callback_host_allowlist: HashSet::from([String::from("127.0.0.1")]),
+                ..Default::default()
+            },
+        );
+
+        let download_url = serve_zip(zip_bytes(&[("file.log", "content")]), Duration::ZERO).await;
+
+        let not_allowed = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url.clone(),
+                "project".to_string(),
+                None,
+                TaskOptions {
+                    callback_url: Some(url::Url::parse("http://localhost/done").unwrap()),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(
+            not_allowed,
+            Err(RunDownloadTaskError::CallbackUrlNotAllowed)
+        ));
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                TaskOptions {
+                    callback_url: Some(callback_url),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("Failed to start task");
+
+        let body = tokio::time::timeout(Duration::from_secs(10), callback_rx.recv())
+            .await
+            .expect("Callback was not posted")
+            .unwrap();
+        let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(payload["id"], id.as_str());
+        assert_eq!(
+            payload["status"],
+            serde_json::json!({"type": "Download", "content": {"status": "Exited"}})
+        );
+        assert!(payload["exit_code"].is_null());
+    }
+
+    #[tokio::test]
+    async fn same_idempotency_key_starts_a_single_task() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let download_url = serve_zip(zip_bytes(&[("file.log", "content")]), Duration::ZERO).await;
+
+        let start = |chat_id: &'static str, key: &'static str| {
+            let api_state = api_state.clone();
+            let download_url = download_url.clone();
+            async move {
+                api_state
+                    .start_task_idempotent(chat_id, Some(key.to_string()), || {
+                        api_state.run_download_task(
+                            chat_id.to_string(),
+                            download_url,
+                            "project".to_string(),
+                            None,
+                            Default::default(),
+                        )
+                    })
+                    .await
+                    .expect("Failed to start task")
+            }
+        };
+
+        let first = start("chat_id", "key").await;
+        let retried = start("chat_id", "key").await;
+        assert_eq!(first, retried);
+        assert_eq!(api_state.tasks.len().await, 1);
+
+        // Keys are per chat and per key.
+        assert_ne!(start("other_chat_id", "key").await, first);
+        assert_ne!(start("chat_id", "other_key").await, first);
+        assert_eq!(api_state.tasks.len().await, 3);
+
+        // The key expires with its task.
+        wait_for_download_to_terminate(&api_state, &first).await;
+        api_state.evict_completed_tasks().await;
+        assert!(!api_state.idempotency_keys.lock().tasks.contains_key(&first));
+        assert_ne!(start("chat_id", "key").await, first);
+    }
+
+    #[tokio::test]
+    async fn idempotent_start_only_waits_for_the_same_key() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let starts = Arc::new(AtomicU32::new(0));
+
+        let start = |key: &'static str, release: Option<tokio::sync::oneshot::Receiver<()>>| {
+            let api_state = api_state.clone();
+            let starts = starts.clone();
+            async move {
+                api_state
+                    .start_task_idempotent("chat_id", Some(key.to_string()), || async move {
+                        starts.fetch_add(1, Ordering::SeqCst);
+                        if let Some(release) = release {
+                            let _ = release.await;
+                        }
+
+                        Ok::<_, std::convert::Infallible>(format!("task of {key}"))
+                    })
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let pending = tokio::spawn(start("key", Some(release_rx)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let retried = tokio::spawn(start("key", None));
+
+        // Another key does not wait for the pending start.
+        let other = tokio::time::timeout(Duration::from_secs(1), start("other_key", None))
+            .await
+            .expect("Other key waited for the pending start");
+        assert_eq!(other, "task of other_key");
+
+        release_tx.send(()).unwrap();
+        assert_eq!(pending.await.unwrap(), "task of key");
+        assert_eq!(retried.await.unwrap(), "task of key");
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn disallowed_metric_label_is_rejected() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_metric_labels(projects_dir.path(), &["nightly"]);
+
+        let download_url = url::Url::parse("http://127.0.0.1:1/file.zip").expect("valid url");
+
+        let result = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                TaskOptions {
+                    metric_label: Some("user-1234".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(RunDownloadTaskError::MetricLabelNotAllowed)
+        ));
+        assert!(!projects_dir.path().join("project").exists());
+        assert!(api_state.task_metrics().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn project_name_is_validated_before_creating_directories() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let projects_dir = dir.path().join("projects");
+        std::fs::create_dir(&projects_dir).unwrap();
+        let api_state = api_state_with_retries(&projects_dir, 0);
+
+        let download_url = url::Url::parse("http://127.0.0.1:1/file.zip").expect("valid url");
+
+        let run = |project_name: &str| {
+            api_state.run_download_task(
+                "chat_id".to_string(),
+                download_url.clone(),
+                project_name.to_string(),
+                None,
+                Default::default(),
+            )
+        };
+
+        assert!(run("my-project_1").await.is_ok());
+        assert!(projects_dir.join("my-project_1").is_dir());
+
+        for project_name in ["nested/project", "..", "../escaped", ""] {
+            assert!(
+                matches!(
+                    run(project_name).await,
+                    Err(RunDownloadTaskError::InvalidProjectName)
+                ),
+                "{project_name:?}"
+            );
+        }
+        assert!(!projects_dir.join("nested").exists());
+        assert!(!dir.path().join("escaped").exists());
+    }
+
+    fn zip_bytes(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+
+        for (name, content) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .expect("Failed to start zip entry");
+            writer
+                .write_all(content.as_bytes())
+                .expect("Failed to write zip entry");
+        }
+
+        writer.finish().expect("Failed to finish zip").into_inner()
+    }
+
+    /// Serves `body` on `/file.zip` after waiting for `delay`.
+    async fn serve_zip(body: Vec<u8>, delay: Duration) -> url::Url {
+        let app = axum::Router::new().route(
+            "/file.zip",
+            axum::routing::get(move || {
+                let body = body.clone();
+                async move {
+                    tokio::time::sleep(delay).await;
+                    body
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        url::Url::parse(&format!("http://{addr}/file.zip")).expect("valid url")
+    }
+
+    async fn download_status_after_shutdown(
+        policy: DownloadShutdownPolicy,
+        delay: Duration,
+    ) -> (Status, tempfile::TempDir) {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+
+        let config = ApiStateConfig {
+            download_shutdown_policy: policy,
+            download_shutdown_grace: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            config,
+        );
+
+        let download_url = serve_zip(zip_bytes(&[("file.log", "content")]), delay).await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        // Let the download start
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        api_state.shutdown();
+
+        tokio::time::timeout(Duration::from_secs(5), api_state.drain_tasks())
+            .await
+            .expect("Downloads did not settle after shutdown");
+
+        let status = api_state
+            .task_status(&id, "chat_id")
+            .await
+            .expect("Task not found");
+
+        (status, projects_dir)
+    }
+
+    #[tokio::test]
+    async fn shutdown_aborts_download_with_abort_policy() {
+        let (status, projects_dir) =
+            download_status_after_shutdown(DownloadShutdownPolicy::Abort, Duration::from_secs(60))
+                .await;
+
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Aborted)
+        ));
+
+        // The extracted files and the then empty project directory are removed.
+        assert!(!projects_dir.path().join("project").exists());
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_download_finish_with_finish_policy() {
+        let (status, projects_dir) = download_status_after_shutdown(
+            DownloadShutdownPolicy::Finish,
+            Duration::from_millis(300),
+        )
+        .await;
+
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+
+        let file = projects_dir.path().join("project").join("file.log");
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "content");
+    }
+
+    /// Starts two downloads, the second one is queued behind the first one.
+    async fn queued_download(
+        policy: DownloadShutdownPolicy,
+    ) -> (ApiState, String, String, tempfile::TempDir) {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+
+        let config = ApiStateConfig {
+            max_concurrent_tasks: 1,
+            download_shutdown_policy: policy,
+            download_shutdown_grace: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            config,
+        );
+
+        let download_url = serve_zip(
+            zip_bytes(&[("file.log", "content")]),
+            Duration::from_millis(300),
+        )
+        .await;
+
+        let mut ids = Vec::new();
+        for project_name in ["running", "queued"] {
+            let id = api_state
+                .run_download_task(
+                    "chat_id".to_string(),
+                    download_url.clone(),
+                    project_name.to_string(),
+                    None,
+                    Default::default(),
+                )
+                .await
+                .expect("Failed to start task");
+            ids.push(id);
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let queued = ids.pop().unwrap();
+        let running = ids.pop().unwrap();
+
+        (api_state, running, queued, projects_dir)
+    }
+
+    #[tokio::test]
+    async fn queued_download_can_be_canceled() {
+        let (api_state, running, queued, _projects_dir) =
+            queued_download(DownloadShutdownPolicy::Abort).await;
+
+        let status = api_state
+            .cancel_task_and_wait(&queued, "chat_id", Duration::from_secs(1))
+            .await
+            .expect("Task not found");
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Canceled)
+        ));
+
+        // The running download is not affected.
+        let status = api_state.task_status(&running, "chat_id").await.unwrap();
+        assert!(!status.is_terminal());
+    }
+
+    #[tokio::test]
+    async fn queued_download_does_not_start_after_shutdown() {
+        let (api_state, running, queued, _projects_dir) =
+            queued_download(DownloadShutdownPolicy::Finish).await;
+
+        api_state.shutdown();
+
+        tokio::time::timeout(Duration::from_secs(5), api_state.drain_tasks())
+            .await
+            .expect("Downloads did not settle after shutdown");
+
+        let status = api_state.task_status(&running, "chat_id").await.unwrap();
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+
+        let status = api_state.task_status(&queued, "chat_id").await.unwrap();
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Aborted)
+        ));
+    }
+
+    /// Serves `body` on `/file.zip` in `chunks` pieces with a `Content-Length`, pausing between them.
+    async fn serve_zip_in_chunks(body: Vec<u8>, chunks: usize, pause: Duration) -> url::Url {
+        let app = axum::Router::new().route(
+            "/file.zip",
+            axum::routing::get(move || {
+                let body = body.clone();
+                async move {
+                    let content_length = body.len();
+                    let chunk_size = content_length.div_ceil(chunks);
+                    let pieces: Vec<Vec<u8>> = body
+                        .chunks(chunk_size)
+                        .map(|chunk| chunk.to_vec())
+                        .collect();
+
+                    let stream = futures::stream::iter(pieces).then(move |piece| async move {
+                        tokio::time::sleep(pause).await;
+                        Ok::<_, std::io::Error>(piece)
+                    });
+
+                    (
+                        [(axum::http::header::CONTENT_LENGTH, content_length)],
+                        axum::body::Body::from_stream(stream),
+                    )
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        url::Url::parse(&format!("http://{addr}/file.zip")).expect("valid url")
+    }
+
+    #[tokio::test]
+    async fn download_progress_advances() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let content = "line\n".repeat(20_000);
+        let zip = zip_bytes(&[("a.log", &content), ("b.log", &content)]);
+        let zip_len = zip.len() as u64;
+
+        let download_url = serve_zip_in_chunks(zip, 5, Duration::from_millis(100)).await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let mut downloaded_bytes = Vec::new();
+
+        let status = loop {
+            let status = api_state
+                .task_status(&id, "chat_id")
+                .await
+                .expect("Task not found");
+
+            match status {
+                Status::Download(DownloadZipFileStatus::Downloading { bytes, total }) => {
+                    assert_eq!(total, Some(zip_len));
+                    downloaded_bytes.push(bytes);
+                }
+                Status::Download(
+                    DownloadZipFileStatus::Running | DownloadZipFileStatus::Unzipping { .. },
+                )
+                | Process(ProcessStatus::Created) => {}
+                status => break status,
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+
+        downloaded_bytes.dedup();
+        assert!(
+            downloaded_bytes.len() > 1,
+            "Progress did not advance: {downloaded_bytes:?}"
+        );
+        assert!(downloaded_bytes.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    /// Answers `/file.zip` with `fail_status` for the first `fail_times` requests, then with `body`.
+    ///
+    /// Returns the number of requests received so far alongside the url.
+    async fn serve_zip_after_failures(
+        body: Vec<u8>,
+        fail_status: axum::http::StatusCode,
+        fail_times: usize,
+    ) -> (url::Url, Arc<std::sync::atomic::AtomicUsize>) {
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let app = axum::Router::new().route(
+            "/file.zip",
+            axum::routing::get({
+                let requests = requests.clone();
+                move || {
+                    let body = body.clone();
+                    let request = requests.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        use axum::response::IntoResponse;
+
+                        if request < fail_times {
+                            return fail_status.into_response();
+                        }
+
+                        body.into_response()
+                    }
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = url::Url::parse(&format!("http://{addr}/file.zip")).expect("valid url");
+
+        (url, requests)
+    }
+
+    async fn wait_for_download_to_terminate(api_state: &ApiState, id: &str) -> Status {
+        loop {
+            let status = api_state
+                .task_status(id, "chat_id")
+                .await
+                .expect("Task not found");
+
+            match status {
+                Status::Download(
+                    DownloadZipFileStatus::Running
+                    | DownloadZipFileStatus::Downloading { .. }
+                    | DownloadZipFileStatus::Unzipping { .. },
+                )
+                | Process(ProcessStatus::Created) => {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                status => return status,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_count_tasks_and_downloaded_bytes() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let zip = zip_bytes(&[("file.log", "content")]);
+        let zip_len = zip.len() as u64;
+        let download_url = serve_zip(zip, Duration::ZERO).await;
+
+        for project_name in ["a", "b"] {
+            let id = api_state
+                .run_download_task(
+                    "chat_id".to_string(),
+                    download_url.clone(),
+                    project_name.to_string(),
+                    None,
+                    Default::default(),
+                )
+                .await
+                .expect("Failed to start task");
+
+            wait_for_download_to_terminate(&api_state, &id).await;
+        }
+
+        // The counters are updated right after the status turns terminal.
+        let stats = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let stats = api_state.stats();
+                if stats.tasks_running == 0 && stats.bytes_downloaded == 2 * zip_len {
+                    return stats;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Stats did not settle");
+
+        assert_eq!(stats.tasks_total, 2);
+        assert_eq!(stats.tasks_queued, 0);
+    }
+
+    #[tokio::test]
+    async fn canceled_download_terminates_and_removes_project_dir() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let download_url = serve_zip(
+            zip_bytes(&[("file.log", "content")]),
+            Duration::from_secs(30),
+        )
+        .await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start download");
+
+        assert!(projects_dir.path().join("project").exists());
+
+        api_state.cancel_task(&id, "chat_id").await;
+
+        let status = tokio::time::timeout(
+            Duration::from_secs(5),
+            wait_for_download_to_terminate(&api_state, &id),
+        )
+        .await
+        .expect("Canceled download did not terminate");
+
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Canceled)
+        ));
+        assert!(!projects_dir.path().join("project").exists());
+    }
+
+    #[tokio::test]
+    async fn probe_download_reports_metadata_without_side_effects() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let body = zip_bytes(&[("file.log", "content")]);
+        let len = body.len() as u64;
+
+        let app = axum::Router::new().route(
+            "/file.zip",
+            axum::routing::get(move || {
+                let body = body.clone();
+                async move {
+                    (
+                        [(axum::http::header::CONTENT_TYPE, "application/zip")],
+                        body,
+                    )
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let download_url = url::Url::parse(&format!("http://{addr}/file.zip")).unwrap();
+        let probe = api_state.probe_download(download_url).await;
+
+        assert!(probe.reachable);
+        assert_eq!(probe.content_length, Some(len));
+        assert_eq!(probe.content_type.as_deref(), Some("application/zip"));
+
+        let missing_url = url::Url::parse(&format!("http://{addr}/missing.zip")).unwrap();
+        assert!(!api_state.probe_download(missing_url).await.reachable);
+
+        assert_eq!(std::fs::read_dir(projects_dir.path()).unwrap().count(), 0);
+        assert!(api_state.tasks.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn late_connection_replays_task_output() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let (_task, handle) = Task::new(String::from("0"));
+        let output = Arc::new(TaskOutput::new(16));
+        api_state
+            .tasks
+            .insert(
+                String::from("0"),
+                TaskData {
+                    chat_id: String::from("chat_id"),
+                    project_name: String::from("project"),
+                    handle,
+                    output: output.clone(),
+                    work_dir: PathBuf::from("project"),
+                    artifacts: Default::default(),
+                    labels: Default::default(),
+                    params: converter_params(),
+                },
+            )
+            .await;
+
+        IoForwarder {
+            task_id: String::from("0"),
+            chat_id: String::from("chat_id"),
+            connection_manager: api_state.connection_manager.clone(),
+            output,
+            options: IoOptions::default(),
+        }
+        .forward(&b"first output"[..], tokio::io::empty(), None)
+        .await;
+
+        let (connection_id, mut rx) = api_state
+            .connection_manager
+            .add_connection(String::from("chat_id"))
+            .await;
+
+        api_state
+            .handle_client_message(
+                connection_id,
+                "chat_id",
+                ClientMessage::Replay {
+                    task_id: String::from("0"),
+                    last_n: None,
+                },
+            )
+            .await;
+
+        let Ok(ServerMessage::TaskIoChunk(chunk)) = rx.try_recv() else {
+            panic!("No replayed chunk");
+        };
+        assert_eq!(chunk.chunk, "first output");
+
+        // Other chats can not replay the task.
+        api_state
+            .handle_client_message(
+                connection_id,
+                "other_chat_id",
+                ClientMessage::Replay {
+                    task_id: String::from("0"),
+                    last_n: None,
+                },
+            )
+            .await;
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_reports_output_dropped_over_the_byte_limit() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let (_task, handle) = Task::new(String::from("0"));
+        let output = Arc::new(TaskOutput::with_max_bytes(16, 12));
+        api_state
+            .tasks
+            .insert(
+                String::from("0"),
+                TaskData {
+                    chat_id: String::from("chat_id"),
+                    project_name: String::from("project"),
+                    handle,
+                    output: output.clone(),
+                    work_dir: PathBuf::from("project"),
+                    artifacts: Default::default(),
+                    labels: Default::default(),
+                    params: converter_params(),
+                },
+            )
+            .await;
+
+        for chunk in ["first ", "second ", "third"] {
+            output.push(TaskIoChunk {
+                id: String::from("0"),
+                chunk: String::from(chunk),
+                io_type: IoType::Stdout,
+            });
+        }
+
+        let (connection_id, mut rx) = api_state
+            .connection_manager
+            .add_connection(String::from("chat_id"))
+            .await;
+
+        api_state
+            .handle_client_message(
+                connection_id,
+                "chat_id",
+                ClientMessage::Replay {
+                    task_id: String::from("0"),
+                    last_n: None,
+                },
+            )
+            .await;
+
+        let Ok(ServerMessage::ReplayTruncated { id, dropped_bytes }) = rx.try_recv() else {
+            panic!("Truncation was not reported");
+        };
+        assert_eq!(id, "0");
+        assert_eq!(dropped_bytes, 6);
+
+        let replayed: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|message| match message {
+                ServerMessage::TaskIoChunk(chunk) => chunk.chunk,
+                other => panic!("Unexpected message: {other:?}"),
+            })
+            .collect();
+        assert_eq!(replayed, ["second ", "third"]);
+    }
+
+    /// Register a task of `chat_id` with an output buffer, like the converter does.
+    #[cfg(unix)]
+    async fn insert_process_task(api_state: &ApiState) -> (Task, Arc<TaskOutput>) {
+        let (task, handle) = Task::new(String::from("0"));
+        let output = Arc::new(TaskOutput::new(64));
+        api_state
+            .tasks
+            .insert(
+                String::from("0"),
+                TaskData {
+                    chat_id: String::from("chat_id"),
+                    project_name: String::from("project"),
+                    handle,
+                    output: output.clone(),
+                    work_dir: PathBuf::from("project"),
+                    artifacts: Default::default(),
+                    labels: Default::default(),
+                    params: converter_params(),
+                },
+            )
+            .await;
+
+        (task, output)
+    }
+
+    /// Run `script` as the given task and forward its IO until it exits.
+    #[cfg(unix)]
+    async fn run_shell_task(
+        api_state: &ApiState,
+        task: Task,
+        output: Arc<TaskOutput>,
+        script: &'static str,
+    ) {
+        let (stdout_tx, stdout_rx) = tokio::io::duplex(1024);
+        let (stderr_tx, stderr_rx) = tokio::io::duplex(1024);
+
+        let forwarding = tokio::spawn(
+            IoForwarder {
+                task_id: String::from("0"),
+                chat_id: String::from("chat_id"),
+                connection_manager: api_state.connection_manager.clone(),
+                output,
+                options: IoOptions::default(),
+            }
+            .forward(stdout_rx, stderr_rx, None),
+        );
+
+        task.run_os_process(
+            "sh",
+            ["-c", script],
+            Duration::from_secs(10),
+            Duration::ZERO,
+            Some(stdout_tx),
+            Some(stderr_tx),
+        )
+        .await;
+        forwarding.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stdin_sent_over_the_websocket_reaches_the_process() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let (task, output) = insert_process_task(&api_state).await;
+        let task = task.with_stdin(TaskStdin::Piped);
+        let (connection_id, mut rx) = api_state
+            .connection_manager
+            .add_connection(String::from("chat_id"))
+            .await;
+
+        let running = tokio::spawn({
+            let api_state = api_state.clone();
+            async move {
+                run_shell_task(&api_state, task, output, "read line; echo \"got $line\"").await;
+            }
+        });
+
+        while !matches!(
+            api_state.task_status("0", "chat_id").await,
+            Some(Process(ProcessStatus::Running))
+        ) {
+            tokio::task::yield_now().await;
+        }
+
+        // Other chats can not write to the task.
+        assert!(matches!(
+            api_state
+                .write_stdin("0", "other_chat_id", b"intruder\n".to_vec())
+                .await,
+            Err(WriteStdinError::NotFound)
+        ));
+
+        api_state
+            .handle_client_message(
+                connection_id,
+                "chat_id",
+                ClientMessage::Stdin {
+                    task_id: String::from("0"),
+                    data: String::from("hello\n"),
+                },
+            )
+            .await;
+
+        let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("No output");
+        let Some(ServerMessage::TaskIoChunk(chunk)) = message else {
+            panic!("Unexpected message: {message:?}");
+        };
+        assert_eq!(chunk.chunk, "got hello\n");
+
+        running.await.unwrap();
+
+        assert!(matches!(
+            api_state
+                .write_stdin("0", "chat_id", b"too late\n".to_vec())
+                .await,
+            Err(WriteStdinError::Stdin(StdinError::NotRunning))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stdin_of_a_process_not_reading_it_is_rejected_without_waiting() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let (task, output) = insert_process_task(&api_state).await;
+        let task = task.with_stdin(TaskStdin::Piped);
+        let (connection_id, mut rx) = api_state
+            .connection_manager
+            .add_connection(String::from("chat_id"))
+            .await;
+
+        let running = tokio::spawn({
+            let api_state = api_state.clone();
+            async move {
+                run_shell_task(&api_state, task, output, "exec sleep 30").await;
+            }
+        });
+
+        while !matches!(
+            api_state.task_status("0", "chat_id").await,
+            Some(Process(ProcessStatus::Running))
+        ) {
+            tokio::task::yield_now().await;
+        }
+
+        // Fills the pipe to the process, then the buffer in front of it.
+        let rejected = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                api_state
+                    .handle_client_message(
+                        connection_id,
+                        "chat_id",
+                        ClientMessage::Stdin {
+                            task_id: String::from("0"),
+                            data: "x".repeat(64 * 1024),
+                        },
+                    )
+                    .await;
+
+                if let Ok(message) = rx.try_recv() {
+                    break message;
+                }
+            }
+        })
+        .await
+        .expect("Writing to stdin waited for the process");
+
+        let ServerMessage::StdinRejected { id, reason } = rejected else {
+            panic!("Unexpected message: {rejected:?}");
+        };
+        assert_eq!(id, "0");
+        assert!(reason.contains(&StdinError::Full.to_string()), "{reason}");
+
+        api_state.cancel_task("0", "chat_id").await;
+        running.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn process_reading_until_eof_finishes_once_stdin_is_closed() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let (task, output) = insert_process_task(&api_state).await;
+        let task = task.with_stdin(TaskStdin::Piped);
+        let (connection_id, _rx) = api_state
+            .connection_manager
+            .add_connection(String::from("chat_id"))
+            .await;
+
+        let running = tokio::spawn({
+            let api_state = api_state.clone();
+            async move {
+                run_shell_task(&api_state, task, output, "cat; echo done").await;
+            }
+        });
+
+        while !matches!(
+            api_state.task_status("0", "chat_id").await,
+            Some(Process(ProcessStatus::Running))
+        ) {
+            tokio::task::yield_now().await;
+        }
+
+        api_state
+            .handle_client_message(
+                connection_id,
+                "chat_id",
+                ClientMessage::Stdin {
+                    task_id: String::from("0"),
+                    data: String::from("input\n"),
+                },
+            )
+            .await;
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!running.is_finished(), "Finished before stdin was closed");
+
+        api_state
+            .handle_client_message(
+                connection_id,
+                "chat_id",
+                ClientMessage::CloseStdin {
+                    task_id: String::from("0"),
+                },
+            )
+            .await;
+
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .expect("Did not finish after stdin was closed")
+            .unwrap();
+
+        let stdout = api_state
+            .task_output("0", "chat_id", IoType::Stdout)
+            .await
+            .expect("Failed to get stdout");
+        assert_eq!(stdout, "input\ndone\n");
+
+        assert!(matches!(
+            api_state.close_stdin("0", "chat_id").await,
+            Err(WriteStdinError::Stdin(StdinError::NotRunning))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn task_output_returns_what_the_process_printed() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let (task, output) = insert_process_task(&api_state).await;
+        run_shell_task(
+            &api_state,
+            task,
+            output,
+            "echo line 1; echo oops >&2; echo line 2",
+        )
+        .await;
+
+        let stdout = api_state
+            .task_output("0", "chat_id", IoType::Stdout)
+            .await
+            .expect("Failed to get stdout");
+        assert_eq!(stdout, "line 1\nline 2\n");
+
+        let stderr = api_state
+            .task_output("0", "chat_id", IoType::Stderr)
+            .await
+            .expect("Failed to get stderr");
+        assert_eq!(stderr, "oops\n");
+
+        assert!(matches!(
+            api_state
+                .task_output("0", "other_chat_id", IoType::Stdout)
+                .await,
+            Err(TaskOutputError::NotFound)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn task_events_end_with_the_terminal_status() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let (task, output) = insert_process_task(&api_state).await;
+
+        assert!(api_state.task_events("0", "other_chat_id").await.is_none());
+
+        let events = api_state
+            .task_events("0", "chat_id")
+            .await
+            .expect("Task not found");
+        assert_eq!(api_state.connection_count().await, 1);
+
+        run_shell_task(&api_state, task, output, "echo line 1; echo line 2").await;
+
+        let events: Vec<TaskEvent> = tokio::time::timeout(Duration::from_secs(5), events.collect())
+            .await
+            .expect("Stream did not end");
+
+        let (last, chunks) = events.split_last().expect("No events");
+
+        let chunks: String = chunks
+            .iter()
+            .map(|event| match event {
+                TaskEvent::Message(ServerMessage::TaskIoChunk(chunk)) => chunk.chunk.as_str(),
+                TaskEvent::Message(other) => panic!("Unexpected message: {other:?}"),
+                TaskEvent::Terminated(_) => panic!("Terminated before the last event"),
+            })
+            .collect();
+        assert_eq!(chunks, "line 1\nline 2\n");
+
+        assert!(matches!(
+            last,
+            TaskEvent::Terminated(Status::Process(ProcessStatus::Exited { .. }))
+        ));
+
+        // The subscription is gone with the stream.
+        assert_eq!(api_state.connection_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn persisted_task_output_is_readable_as_a_file() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig {
+                persist_task_output: true,
+                ..Default::default()
+            },
+        );
+
+        let task_log_dir = projects_dir.path().join(api_state.task_log_dir_name("0"));
+        let files = TaskLogFiles::create(&task_log_dir, false)
+            .await
+            .expect("Failed to create task log files");
+
+        IoForwarder {
+            task_id: String::from("0"),
+            chat_id: String::from("chat_id"),
+            connection_manager: api_state.connection_manager.clone(),
+            output: Arc::new(TaskOutput::new(0)),
+            options: IoOptions::default(),
+        }
+        .forward(&b"line 1\nline 2\n"[..], &b"error\n"[..], Some(files))
+        .await;
+
+        let stdout = api_state
+            .get_file(
+                api_state.task_log_dir_name("0"),
+                String::from("stdout.log"),
+                false,
+            )
+            .await
+            .expect("Failed to read stdout.log");
+        assert_eq!(stdout, "line 1\nline 2\n");
+
+        let stderr = api_state
+            .get_file(
+                api_state.task_log_dir_name("0"),
+                String::from("stderr.log"),
+                false,
+            )
+            .await
+            .expect("Failed to read stderr.log");
+        assert_eq!(stderr, "error\n");
+    }
+
+    #[test]
+    fn task_logs_of_a_restarted_server_do_not_overwrite_the_previous_ones() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let start = || {
+            ApiState::new(
+                Default::default(),
+                projects_dir.path().to_string_lossy().to_string(),
+                Default::default(),
+            )
+        };
+
+        // Both runs start counting task ids at 0.
+        assert_ne!(
+            start().task_log_dir_name("0"),
+            start().task_log_dir_name("0")
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_task_output_is_read_decompressed() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig {
+                persist_task_output: true,
+                compress_task_output: true,
+                ..Default::default()
+            },
+        );
+
+        let (_task, handle) = Task::new(String::from("0"));
+        api_state
+            .tasks
+            .insert(
+                String::from("0"),
+                TaskData {
+                    chat_id: String::from("chat_id"),
+                    project_name: String::from("project"),
+                    handle,
+                    // Nothing is buffered, so the output can only come from the files.
+                    output: Arc::new(TaskOutput::new(0)),
+                    work_dir: PathBuf::from("project"),
+                    artifacts: Default::default(),
+                    labels: Default::default(),
+                    params: converter_params(),
+                },
+            )
+            .await;
+
+        let task_log_dir = projects_dir.path().join(api_state.task_log_dir_name("0"));
+        let files = TaskLogFiles::create(&task_log_dir, true)
+            .await
+            .expect("Failed to create task log files");
+
+        let stdout: String = (0..1000).map(|i| format!("line {i}\n")).collect();
+        IoForwarder {
+            task_id: String::from("0"),
+            chat_id: String::from("chat_id"),
+            connection_manager: api_state.connection_manager.clone(),
+            output: Arc::new(TaskOutput::new(0)),
+            options: IoOptions::default(),
+        }
+        .forward(stdout.as_bytes(), &b"error\n"[..], Some(files))
+        .await;
+
+        assert!(!task_log_dir.join("stdout.log").exists());
+        let compressed = std::fs::read(task_log_dir.join("stdout.log.gz")).unwrap();
+        assert_eq!(&compressed[..2], [0x1f, 0x8b]);
+        assert!(compressed.len() < stdout.len());
+
+        assert_eq!(
+            api_state
+                .task_output("0", "chat_id", IoType::Stdout)
+                .await
+                .expect("Failed to get stdout"),
+            stdout
+        );
+        assert_eq!(
+            api_state
+                .task_output("0", "chat_id", IoType::Stderr)
+                .await
+                .expect("Failed to get stderr"),
+            "error\n"
+        );
+    }
+
+    fn api_state_with_retries(projects_dir: &std::path::Path, max_retries: u32) -> ApiState {
+        let config = ApiStateConfig {
+            download_retry_policy: DownloadRetryPolicy {
+                max_retries,
+                backoff: Duration::from_millis(10),
+            },
+            ..Default::default()
+        };
+
+        ApiState::new(
+            Default::default(),
+            projects_dir.to_string_lossy().to_string(),
+            config,
+        )
+    }
+
+    #[tokio::test]
+    async fn download_is_retried_after_server_errors() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 3);
+
+        let (download_url, requests) = serve_zip_after_failures(
+            zip_bytes(&[("file.log", "content")]),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            2,
+        )
+        .await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(projects_dir.path().join("project/file.log").exists());
+    }
+
+    /// Serves the body with `Accept-Ranges`, but breaks off the first response after half of it.
+    /// With `range_not_satisfiable`, requests for a range are answered with `416 Range Not Satisfiable`.
+    ///
+    /// Returns the `Range` header of every request.
+    async fn serve_zip_interrupted_once(
+        body: Vec<u8>,
+        range_not_satisfiable: bool,
+    ) -> (url::Url, Arc<std::sync::Mutex<Vec<Option<String>>>>) {
+        let ranges = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let app = axum::Router::new().route(
+            "/file.zip",
+            axum::routing::get({
+                let ranges = ranges.clone();
+                move |headers: axum::http::HeaderMap| {
+                    let body = body.clone();
+                    let range = headers
+                        .get(axum::http::header::RANGE)
+                        .map(|value| value.to_str().unwrap().to_string());
+                    let first_request = {
+                        let mut ranges = ranges.lock().unwrap();
+                        ranges.push(range.clone());
+                        ranges.len() == 1
+                    };
+
+                    async move {
+                        use axum::{http::header, response::IntoResponse};
+
+                        let len = body.len();
+
+                        if first_request {
+                            let half = body[..len / 2].to_vec();
+                            let stream = futures::stream::once(async { Ok(half) }).chain(
+                                futures::stream::once(async {
+                                    // Let the first half reach the client before breaking off.
+                                    tokio::time::sleep(Duration::from_millis(100)).await;
+                                    Err(std::io::Error::other("interrupted"))
+                                }),
+                            );
+
+                            return (
+                                [
+                                    (header::CONTENT_LENGTH, len.to_string()),
+                                    (header::ACCEPT_RANGES, String::from("bytes")),
+                                ],
+                                axum::body::Body::from_stream(stream),
+                            )
+                                .into_response();
+                        }
+
+                        if range_not_satisfiable && range.is_some() {
+                            return axum::http::StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+                        }
+
+                        let Some(range) = range else {
+                            return body.into_response();
+                        };
+
+                        let start: usize = range
+                            .strip_prefix("bytes=")
+                            .and_then(|range| range.strip_suffix('-'))
+                            .map(|start| start.parse().unwrap())
+                            .unwrap_or_default();
+
+                        (
+                            axum::http::StatusCode::PARTIAL_CONTENT,
+                            [(
+                                header::CONTENT_RANGE,
+                                format!("bytes {start}-{}/{len}", len - 1),
+                            )],
+                            body[start..].to_vec(),
+                        )
+                            .into_response()
+                    }
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = url::Url::parse(&format!("http://{addr}/file.zip")).expect("valid url");
+
+        (url, ranges)
+    }
+
+    #[tokio::test]
+    async fn interrupted_download_is_resumed_from_the_offset() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 1);
+
+        let content = "line\n".repeat(1000);
+        let zip = zip_bytes(&[("file.log", &content)]);
+        let zip_len = zip.len();
+        let (download_url, ranges) = serve_zip_interrupted_once(zip, false).await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, Some(format!("bytes={}-", zip_len / 2))]
+        );
+        assert_eq!(
+            std::fs::read_to_string(projects_dir.path().join("project/file.log")).unwrap(),
+            content
+        );
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_range_falls_back_to_the_whole_file() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 1);
+
+        let content = "line\n".repeat(1000);
+        let zip = zip_bytes(&[("file.log", &content)]);
+        let zip_len = zip.len();
+        let (download_url, ranges) = serve_zip_interrupted_once(zip, true).await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, Some(format!("bytes={}-", zip_len / 2)), None]
+        );
+        assert_eq!(
+            std::fs::read_to_string(projects_dir.path().join("project/file.log")).unwrap(),
+            content
+        );
+        // The partial download is gone once it is extracted.
+        let files: Vec<_> = std::fs::read_dir(projects_dir.path().join("project"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["file.log"]);
+    }
+
+    #[tokio::test]
+    async fn download_is_verified_against_the_expected_sha256() {
+        use sha2::{Digest, Sha256};
+
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let zip = zip_bytes(&[("file.log", "content")]);
+        let sha256 = format!("{:x}", Sha256::digest(&zip));
+        let download_url = serve_zip(zip, Duration::ZERO).await;
+
+        let download = |project_name: &str, sha256: String| {
+            api_state.run_download_task(
+                "chat_id".to_string(),
+                download_url.clone(),
+                project_name.to_string(),
+                Some(sha256),
+                Default::default(),
+            )
+        };
+
+        let id = download("matching", sha256.to_uppercase())
+            .await
+            .expect("Failed to start task");
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+        assert!(projects_dir.path().join("matching/file.log").exists());
+
+        let id = download("mismatching", "0".repeat(64))
+            .await
+            .expect("Failed to start task");
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+        assert!(
+            matches!(
+                &status,
+                Status::Failed { reason, kind: FailureKind::Download } if reason.contains(&sha256)
+            ),
+            "{status:?}"
+        );
+        assert!(!projects_dir.path().join("mismatching").exists());
+
+        assert!(matches!(
+            download("invalid", String::from("abc")).await,
+            Err(RunDownloadTaskError::InvalidSha256)
+        ));
+    }
+
+    async fn tar_gz_bytes(files: &[(&str, &str)]) -> Vec<u8> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+        for (name, content) in files {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .await
+                .expect("Failed to append tar entry");
+        }
+        let tar = builder.into_inner().await.expect("Failed to finish tar");
+
+        let mut encoder = async_compression::tokio::write::GzipEncoder::new(Vec::new());
+        encoder.write_all(&tar).await.unwrap();
+        encoder.shutdown().await.unwrap();
+
+        encoder.into_inner()
+    }
+
+    #[tokio::test]
+    async fn tar_gz_download_is_extracted() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let archive = tar_gz_bytes(&[("a.log", "a"), ("logs/b.log", "b")]).await;
+        let download_url = serve_zip(archive, Duration::ZERO).await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+
+        assert!(
+            matches!(status, Status::Download(DownloadZipFileStatus::Exited)),
+            "{status:?}"
+        );
+        let project_dir = projects_dir.path().join("project");
+        assert_eq!(
+            std::fs::read_to_string(project_dir.join("a.log")).unwrap(),
+            "a"
+        );
+        // Directories are stripped like for zip files.
+        assert_eq!(
+            std::fs::read_to_string(project_dir.join("b.log")).unwrap(),
+            "b"
+        );
+    }
+
+    #[tokio::test]
+    async fn archive_entries_outside_of_the_project_are_rejected() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let projects_dir = dir.path().join("projects");
+        std::fs::create_dir(&projects_dir).unwrap();
+        let api_state = api_state_with_retries(&projects_dir, 0);
+
+        let zip = zip_bytes(&[("ok.log", "ok"), ("../../evil.log", "evil")]);
+        let download_url = serve_zip(zip, Duration::ZERO).await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+
+        assert!(
+            matches!(
+                &status,
+                Status::Failed { reason, kind: FailureKind::Download } if reason.contains("../../evil.log")
+            ),
+            "{status:?}"
+        );
+        assert!(!dir.path().join("evil.log").exists());
+        assert!(!projects_dir.join("evil.log").exists());
+        // The files extracted before the rejected entry are removed with the project directory.
+        assert!(!projects_dir.join("project").exists());
+    }
+
+    #[tokio::test]
+    async fn finished_download_reports_extracted_files() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let download_url =
+            serve_zip(zip_bytes(&[("a.log", "a"), ("b.log", "b")]), Duration::ZERO).await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        wait_for_download_to_terminate(&api_state, &id).await;
+
+        let details = api_state
+            .task_details(&id, "chat_id")
+            .await
+            .expect("Task not found");
+
+        assert!(matches!(
+            details.status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+        assert_eq!(details.work_dir, Some(projects_dir.path().join("project")));
+        assert_eq!(
+            details.artifacts,
+            Some(vec![String::from("a.log"), String::from("b.log")])
+        );
+    }
+
+    #[tokio::test]
+    async fn waiting_for_the_status_returns_when_the_task_is_done() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let download_url = serve_zip(
+            zip_bytes(&[("file.log", "content")]),
+            Duration::from_millis(300),
+        )
+        .await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let started = std::time::Instant::now();
+
+        // Every response is either a status transition or the final status, never the timeout.
+        let status = loop {
+            let details = api_state
+                .wait_for_task_details(&id, "chat_id", Duration::from_secs(30))
+                .await
+                .expect("Task not found");
+
+            if details.status.is_terminal() {
+                break details.status;
+            }
+        };
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(matches!(
+            status,
+            Status::Download(DownloadZipFileStatus::Exited)
+        ));
+
+        let details = api_state
+            .wait_for_task_details(&id, "chat_id", Duration::from_secs(30))
+            .await
+            .expect("Task not found");
+        assert!(details.status.is_terminal());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn bulk_statuses_only_include_tasks_of_the_chat() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let download_url = url::Url::parse("http://127.0.0.1:1/file.zip").expect("valid url");
+
+        let mut ids = Vec::new();
+        for (chat_id, project_name) in [("chat_id", "a"), ("chat_id", "b"), ("other", "c")] {
+            let id = api_state
+                .run_download_task(
+                    chat_id.to_string(),
+                    download_url.clone(),
+                    project_name.to_string(),
+                    None,
+                    Default::default(),
+                )
+                .await
+                .expect("Failed to start task");
+
+            ids.push(id);
+        }
+        ids.push(String::from("unknown"));
+
+        let statuses = api_state.task_statuses(&ids, "chat_id").await;
+
+        let returned: HashSet<&String> = statuses.keys().collect();
+        assert_eq!(returned, HashSet::from([&ids[0], &ids[1]]));
+    }
+
+    #[test]
+    fn concurrently_created_tasks_get_different_ids() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let ids: Vec<u32> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..1000)
+                            .map(|_| api_state.increment_current_task_id())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().expect("Thread panicked"))
+                .collect()
+        });
+
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 8 * 1000);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_status_queries_return_the_status_of_the_own_tasks() {
+        let api_state = ApiState::new(
+            Default::default(),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        let mut tasks = Vec::new();
+        for id in 0..10 {
+            let (task, handle) = Task::new(id.to_string());
+            let chat_id = if id % 2 == 0 { "chat_id" } else { "other" };
+
+            api_state
+                .tasks
+                .insert(
+                    id.to_string(),
+                    TaskData {
+                        chat_id: String::from(chat_id),
+                        project_name: String::from("project"),
+                        handle,
+                        output: Arc::new(TaskOutput::new(0)),
+                        work_dir: PathBuf::from("project"),
+                        artifacts: Default::default(),
+                        labels: Default::default(),
+                        params: converter_params(),
+                    },
+                )
+                .await;
+            tasks.push(task);
+        }
+
+        // Writers compete with the queries for the lock.
+        let writer = tokio::spawn({
+            let api_state = api_state.clone();
+            async move {
+                for _ in 0..100 {
+                    let (_task, handle) = Task::new(String::from("extra"));
+                    api_state
+                        .tasks
+                        .insert(
+                            String::from("extra"),
+                            TaskData {
+                                chat_id: String::from("chat_id"),
+                                project_name: String::from("project"),
+                                handle,
+                                output: Arc::new(TaskOutput::new(0)),
+                                work_dir: PathBuf::from("project"),
+                                artifacts: Default::default(),
+                                labels: Default::default(),
+                                params: converter_params(),
+                            },
+                        )
+                        .await;
+                    api_state.tasks.remove("extra").await;
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        let queries: Vec<_> = (0..500)
+            .map(|query| {
+                let api_state = api_state.clone();
+                tokio::spawn(async move {
+                    let id = (query % 10).to_string();
+                    (query % 10, api_state.task_status(&id, "chat_id").await)
+                })
+            })
+            .collect();
+
+        let results =
+            tokio::time::timeout(Duration::from_secs(10), futures::future::join_all(queries))
+                .await
+                .expect("Status queries did not finish");
+
+        for result in results {
+            let (id, status) = result.expect("Status query panicked");
+
+            if id % 2 == 0 {
+                assert!(matches!(status, Some(Process(ProcessStatus::Created))));
+            } else {
+                assert!(status.is_none());
+            }
+        }
+
+        writer.await.expect("Writer panicked");
+    }
+
+    #[tokio::test]
+    async fn deleting_a_task_cancels_it_and_removes_it_from_memory_and_disk() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        // A file of an earlier download, so the project directory is not empty after the cancel.
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir_all(&project_dir).unwrap();
+        std::fs::write(project_dir.join("earlier.log"), "earlier").unwrap();
+
+        let download_url = serve_zip(
+            zip_bytes(&[("file.log", "content")]),
+            Duration::from_secs(30),
+        )
+        .await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        assert!(matches!(
+            api_state
+                .delete_task(&id, "other", Duration::from_secs(5))
+                .await,
+            Err(DeleteTaskError::NotFound)
+        ));
+
+        api_state
+            .delete_task(&id, "chat_id", Duration::from_secs(5))
+            .await
+            .expect("Failed to delete task");
+
+        assert!(api_state.task_status(&id, "chat_id").await.is_none());
+        assert!(!project_dir.exists());
+        // Nothing is left of the project under the name it was moved to.
+        assert_eq!(std::fs::read_dir(projects_dir.path()).unwrap().count(), 0);
+    }
+
+    /// Collects the formatted logs of a test.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn task_logs_carry_the_task_id_and_chat_id() {
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new("job_hub=debug"))
+            .with_ansi(false)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        // The test runtime is single threaded, so the spawned task logs to this subscriber as well.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let download_url = url::Url::parse("http://127.0.0.1:1/file.zip").expect("valid url");
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !logs.text().contains("Task finished") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Task did not finish");
+
+        let span = format!("task{{id={id} chat_id=chat_id}}");
+        let text = logs.text();
+        let finished = text
+            .lines()
+            .find(|line| line.contains("Task finished"))
+            .unwrap();
+
+        assert!(finished.contains(&span), "{finished}");
+        assert!(
+            text.lines()
+                .filter(|line| line.contains("Setting status"))
+                .all(|line| line.contains(&span)),
+            "{text}"
+        );
+    }
+
+    #[tokio::test]
+    async fn download_is_not_retried_after_client_errors() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 3);
+
+        let (download_url, requests) = serve_zip_after_failures(
+            zip_bytes(&[("file.log", "content")]),
+            axum::http::StatusCode::NOT_FOUND,
+            1,
+        )
+        .await;
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let status = wait_for_download_to_terminate(&api_state, &id).await;
+
+        assert!(matches!(
+            status,
+            Status::Failed {
+                kind: FailureKind::Download,
+                ..
+            }
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn idle_project_is_deleted() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("file.log"), "content").unwrap();
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        api_state
+            .delete_project("project")
+            .await
+            .expect("Failed to delete project");
+
+        assert!(!project_dir.exists());
+        assert!(matches!(
+            api_state.delete_project("project").await,
+            Err(DeleteProjectError::NotFound)
+        ));
+        assert!(matches!(
+            api_state.delete_project("..").await,
+            Err(DeleteProjectError::InvalidProjectName)
+        ));
+    }
+
+    #[tokio::test]
+    async fn project_of_running_task_is_not_deleted() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let download_url = serve_zip(
+            zip_bytes(&[("file.log", "content")]),
+            Duration::from_secs(60),
+        )
+        .await;
+
+        api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        assert!(matches!(
+            api_state.delete_project("project").await,
+            Err(DeleteProjectError::InUse)
+        ));
+        assert!(projects_dir.path().join("project").exists());
+    }
+
+    #[tokio::test]
+    async fn list_files_is_paginated() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        for name in ["e.log", "b.log", "d.log", "a.log", "c.log"] {
+            std::fs::write(project_dir.join(name), name).unwrap();
+        }
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let page = |offset, limit| {
+            let api_state = api_state.clone();
+            async move {
+                api_state
+                    .list_files("project".to_string(), None, FileSort::Name, offset, limit)
+                    .await
+                    .expect("Failed to list files")
+            }
+        };
+
+        let names = |page: &FilesPage| {
+            page.files
+                .iter()
+                .map(|file| file.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let first = page(0, Some(2)).await;
+        assert_eq!(names(&first), ["a.log", "b.log"]);
+        assert_eq!(first.total, 5);
+
+        let middle = page(2, Some(2)).await;
+        assert_eq!(names(&middle), ["c.log", "d.log"]);
+        assert_eq!(middle.total, 5);
+
+        let out_of_range = page(10, Some(2)).await;
+        assert!(out_of_range.files.is_empty());
+        assert_eq!(out_of_range.total, 5);
+    }
+
+    #[tokio::test]
+    async fn list_files_reports_file_sizes() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("empty.log"), "").unwrap();
+        std::fs::write(project_dir.join("full.log"), vec![b'x'; 4096]).unwrap();
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let page = api_state
+            .list_files("project".to_string(), None, FileSort::Name, 0, None)
+            .await
+            .expect("Failed to list files");
+
+        let sizes: Vec<(&str, u64)> = page
+            .files
+            .iter()
+            .map(|file| (file.name.as_str(), file.size_bytes))
+            .collect();
+
+        assert_eq!(sizes, [("empty.log", 0), ("full.log", 4096)]);
+        assert!(page.files.iter().all(|file| file.modified.is_some()));
+    }
+
+    #[tokio::test]
+    async fn list_files_is_filtered_by_pattern() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        for name in ["a.log", "b.csv", "c.log", "d.txt"] {
+            std::fs::write(project_dir.join(name), name).unwrap();
+        }
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let page = api_state
+            .list_files(
+                "project".to_string(),
+                Some("*.log"),
+                FileSort::Name,
+                0,
+                None,
+            )
+            .await
+            .expect("Failed to list files");
+
+        let names: Vec<&str> = page.files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, ["a.log", "c.log"]);
+        assert_eq!(page.total, 2);
+
+        let result = api_state
+            .list_files(
+                "project".to_string(),
+                Some("[*.log"),
+                FileSort::Name,
+                0,
+                None,
+            )
+            .await;
+
+        assert!(matches!(result, Err(ListFilesError::InvalidPattern)));
+    }
+
+    #[test]
+    fn api_token_validation() {
+        let api_state = ApiState::new(
+            HashSet::from([String::from("secret")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        assert!(api_state.api_token_valid("secret"));
+        assert!(!api_state.api_token_valid("secreT"));
+        assert!(!api_state.api_token_valid("secret "));
+        assert!(!api_state.api_token_valid(""));
+    }
+
+    #[test]
+    fn every_configured_api_token_is_valid() {
+        let api_state = ApiState::new(
+            HashSet::from([String::from("old-token"), String::from("new-token")]),
+            String::from("projects"),
+            ApiStateConfig::default(),
+        );
+
+        assert!(api_state.api_token_valid("old-token"));
+        assert!(api_state.api_token_valid("new-token"));
+        assert!(!api_state.api_token_valid("unknown-token"));
+    }
+
+    #[tokio::test]
+    async fn tail_of_short_file_returns_all_lines() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+
+        let old = project_dir.join("old.log");
+        std::fs::write(&old, "old\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        std::fs::write(project_dir.join("new.log"), "first\nsecond\n").unwrap();
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let tailed = api_state
+            .tail_newest_file("project".to_string(), 10)
+            .await
+            .expect("Failed to tail");
+
+        assert_eq!(tailed.file_name, "new.log");
+        assert_eq!(tailed.lines, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn tail_of_large_file_returns_last_lines() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+
+        let content: String = (0..100_000).map(|i| format!("line {i}\n")).collect();
+        std::fs::write(project_dir.join("big.log"), content).unwrap();
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let tailed = api_state
+            .tail_newest_file("project".to_string(), 3)
+            .await
+            .expect("Failed to tail");
+
+        assert_eq!(tailed.lines, ["line 99997", "line 99998", "line 99999"]);
+    }
+
+    #[tokio::test]
+    async fn tail_is_limited_to_the_projects_and_to_max_lines() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+
+        let content: String = (0..MAX_TAIL_LINES + 10)
+            .map(|i| format!("line {i}\n"))
+            .collect();
+        std::fs::write(project_dir.join("big.log"), content).unwrap();
+
+        let api_state = ApiState::new(
+            Default::default(),
+            project_dir.to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        // The log file is in the parent of the projects directory.
+        for project_name in ["..", ".", "../project", "a/b", ""] {
+            assert!(
+                matches!(
+                    api_state
+                        .tail_newest_file(project_name.to_string(), 10)
+                        .await,
+                    Err(TailError::InvalidProjectName)
+                ),
+                "{project_name}"
+            );
+        }
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let tailed = api_state
+            .tail_newest_file("project".to_string(), usize::MAX)
+            .await
+            .expect("Failed to tail");
+
+        assert_eq!(tailed.lines.len(), MAX_TAIL_LINES);
+        assert_eq!(tailed.lines.first().unwrap(), "line 10");
+    }
+
+    #[tokio::test]
+    async fn tailed_file_streams_appended_lines() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let project_dir = projects_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+
+        let log = project_dir.join("file.log");
+        std::fs::write(&log, "old 1\nold 2\n").unwrap();
+
+        let api_state = ApiState::new(
+            Default::default(),
+            projects_dir.path().to_string_lossy().to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let (_task, handle) = Task::new(String::from("0"));
+        api_state
+            .tasks
+            .insert(
+                String::from("0"),
+                TaskData {
+                    chat_id: String::from("chat_id"),
+                    project_name: String::from("project"),
+                    handle,
+                    output: Arc::new(TaskOutput::new(0)),
+                    work_dir: project_dir.clone(),
+                    artifacts: Default::default(),
+                    labels: Default::default(),
+                    params: converter_params(),
+                },
+            )
+            .await;
+
+        let (connection_id, mut rx) = api_state
+            .connection_manager
+            .add_connection(String::from("chat_id"))
+            .await;
+
+        let tail_file = |chat_id: &'static str, file: &'static str| {
+            api_state.handle_client_message(
+                connection_id,
+                chat_id,
+                ClientMessage::TailFile {
+                    project: String::from("project"),
+                    file: String::from(file),
+                },
+            )
+        };
+
+        // Other chats and paths leaving the project can not be tailed.
+        tail_file("other_chat_id", "file.log").await;
+        tail_file("chat_id", "../project/file.log").await;
+        assert!(rx.try_recv().is_err());
+
+        tail_file("chat_id", "file.log").await;
+
+        let mut received = Vec::new();
+        let mut append = Some("new 1\nnew 2\n");
+        while received.len() < 4 {
+            let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .expect("No lines received")
+                .expect("Connection closed");
+
+            let ServerMessage::FileAppend {
+                project,
+                file,
+                lines,
+            } = message
+            else {
+                panic!("Unexpected message: {message:?}");
+            };
+            assert_eq!((project.as_str(), file.as_str()), ("project", "file.log"));
+            received.extend(lines);
+
+            // Append once the existing lines arrived.
+            if let Some(append) = append.take() {
+                std::fs::File::options()
+                    .append(true)
+                    .open(&log)
+                    .unwrap()
+                    .write_all(append.as_bytes())
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(received, ["old 1", "old 2", "new 1", "new 2"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn only_terminal_tasks_are_evicted() {
+        let api_state = ApiState::new(
+            Default::default(),
+            "projects".to_string(),
+            ApiStateConfig::default(),
+        );
+
+        let mut tasks = HashMap::new();
+        let mut handles = HashMap::new();
+        for id in ["created", "running", "exited", "failed"] {
+            let (task, handle) = Task::new(String::from(id));
+            api_state
+                .tasks
+                .insert(
+                    String::from(id),
+                    TaskData {
+                        chat_id: String::from("chat_id"),
+                        project_name: String::from("project"),
+                        handle: handle.clone(),
+                        output: Arc::new(TaskOutput::new(64)),
+                        work_dir: PathBuf::from("project"),
+                        artifacts: Default::default(),
+                        labels: Default::default(),
+                        params: converter_params(),
+                    },
+                )
+                .await;
+            tasks.insert(id, task);
+            handles.insert(id, handle);
+        }
+
+        let run = |task: Task, command: &'static str| {
+            task.run_os_process(
+                command,
+                ["30"],
+                Duration::from_secs(60),
+                Duration::ZERO,
+                None::<tokio::io::Sink>,
+                None::<tokio::io::Sink>,
+            )
+        };
+        run(tasks.remove("exited").unwrap(), "true").await;
+        run(
+            tasks.remove("failed").unwrap(),
+            "job-hub-command-that-does-not-exist",
+        )
+        .await;
+        let running = tokio::spawn(run(tasks.remove("running").unwrap(), "sleep"));
+        while !matches!(
+            handles["running"].status().await,
+            Status::Process(ProcessStatus::Running)
+        ) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(api_state.evict_completed_tasks().await, 2);
+
+        let mut remaining: Vec<_> = api_state
+            .tasks
+            .read_all()
+            .await
+            .iter()
+            .flat_map(|shard| shard.keys().cloned().collect::<Vec<_>>())
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, ["created", "running"]);
+
+        handles["running"].send_cancel_signal().await;
+        running.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resubmitted_task_is_started_with_the_same_parameters() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        let download_url = url::Url::parse("http://127.0.0.1:1/file.zip").expect("valid url");
+        let sha256 = "a".repeat(64);
+
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url.clone(),
+                "project".to_string(),
+                Some(sha256.clone()),
+                TaskOptions {
+                    labels: HashMap::from([(String::from("env"), String::from("prod"))]),
+                    priority: 3,
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("Failed to start task");
+        assert!(wait_for_download_to_terminate(&api_state, &id)
+            .await
+            .is_terminal());
+
+        assert!(matches!(
+            api_state.resubmit_task(&id, "other_chat_id").await,
+            Err(ResubmitTaskError::NotFound)
+        ));
+
+        let new_id = api_state
+            .resubmit_task(&id, "chat_id")
+            .await
+            .expect("Failed to resubmit task");
+        assert_ne!(new_id, id);
+
+        let tasks = api_state.tasks.read(&new_id).await;
+        let task_data = tasks.get(&new_id).expect("Task not found");
+        assert_eq!(task_data.chat_id, "chat_id");
+        assert_eq!(
+            task_data.params.command,
+            TaskCommand::Download {
+                download_url,
+                project_name: String::from("project"),
+                expected_sha256: Some(sha256),
+            }
+        );
+        assert_eq!(task_data.params.options.priority, 3);
+        assert_eq!(task_data.labels["env"], "prod");
+    }
+
+    #[tokio::test]
+    async fn huge_content_length_does_not_size_the_download_buffer() {
+        let projects_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let api_state = api_state_with_retries(projects_dir.path(), 0);
+
+        // Claims a petabyte, then sends a few bytes and closes the connection.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get local addr");
+        tokio::spawn(async move {
+            while let Ok((mut connection, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = tokio::io::AsyncReadExt::read(&mut connection, &mut request).await;
+                let _ = tokio::io::AsyncWriteExt::write_all(
+                    &mut connection,
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 1125899906842624\r\n\r\nPK",
+                )
+                .await;
+            }
+        });
+
+        let download_url = url::Url::parse(&format!("http://{addr}/file.zip")).expect("valid url");
+        let id = api_state
+            .run_download_task(
+                "chat_id".to_string(),
+                download_url,
+                "project".to_string(),
+                None,
+                Default::default(),
+            )
+            .await
+            .expect("Failed to start task");
+
+        let status = tokio::time::timeout(
+            Duration::from_secs(10),
+            wait_for_download_to_terminate(&api_state, &id),
+        )
+        .await
+        .expect("Download did not terminate");
+        assert!(
+            matches!(
+                status,
+                Status::Failed {
+                    kind: FailureKind::Download,
+                    ..
+                }
+            ),
+            "{status:?}"
+        );
     }
 }
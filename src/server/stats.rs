@@ -0,0 +1,71 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Counters over the lifetime of the server, for [`ApiStateInner::stats`](super::state::ApiStateInner::stats).
+pub struct ServerStats {
+    started_at: Instant,
+    tasks_total: AtomicU64,
+    tasks_queued: AtomicU64,
+    tasks_running: AtomicU64,
+    bytes_downloaded: AtomicU64,
+}
+
+/// A snapshot of [`ServerStats`].
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub tasks_total: u64,
+    pub tasks_queued: u64,
+    pub tasks_running: u64,
+    pub bytes_downloaded: u64,
+    pub uptime: Duration,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            tasks_total: AtomicU64::new(0),
+            tasks_queued: AtomicU64::new(0),
+            tasks_running: AtomicU64::new(0),
+            bytes_downloaded: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ServerStats {
+    /// The task was created and waits to be run.
+    pub fn task_queued(&self) {
+        self.tasks_total.fetch_add(1, Ordering::Relaxed);
+        self.tasks_queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn task_running(&self) {
+        self.tasks_queued.fetch_sub(1, Ordering::Relaxed);
+        self.tasks_running.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The task ended before it ran, e.g. because it was canceled while queued.
+    pub fn task_dequeued(&self) {
+        self.tasks_queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn task_finished(&self) {
+        self.tasks_running.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            tasks_total: self.tasks_total.load(Ordering::Relaxed),
+            tasks_queued: self.tasks_queued.load(Ordering::Relaxed),
+            tasks_running: self.tasks_running.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            uptime: self.started_at.elapsed(),
+        }
+    }
+}
@@ -1,9 +1,22 @@
+use super::shutdown::DownloadShutdown;
+use async_compression::tokio::bufread::GzipDecoder;
+use futures::StreamExt;
 use serde::Serialize;
-use std::{ffi::OsStr, process::ExitStatus, sync::Arc, time::Duration};
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::OsStr,
+    future::Future,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
-    process::Command,
-    sync::{mpsc, RwLock},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    process::{Child, Command},
+    sync::{mpsc, watch, Notify, RwLock},
 };
 use utoipa::ToSchema;
 
@@ -12,15 +25,40 @@ use utoipa::ToSchema;
 pub enum Status {
     Download(DownloadZipFileStatus),
     Process(ProcessStatus),
+    /// The task errored out, e.g. its command could not be started or its download failed
+    Failed {
+        reason: String,
+        kind: FailureKind,
+    },
+}
+
+/// What a [`Status::Failed`] task failed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub enum FailureKind {
+    /// The OS process could not be started, e.g. because the command was not found
+    Spawn,
+    /// Waiting for or killing the OS process failed
+    Process,
+    Download,
 }
 
 #[derive(Debug, Clone, Serialize, ToSchema)]
 #[serde(tag = "status", content = "content")]
 pub enum DownloadZipFileStatus {
     Created,
-    Failed { reason: String },
     Running,
+    /// Downloading the zip file. `total` is known if the server sent a `Content-Length`
+    Downloading {
+        bytes: u64,
+        total: Option<u64>,
+    },
+    /// Extracting the downloaded zip file
+    Unzipping {
+        entries_done: usize,
+    },
     Canceled,
+    /// Aborted because the server is shutting down
+    Aborted,
     Exited,
     Timeout,
 }
@@ -29,30 +67,16 @@ pub enum DownloadZipFileStatus {
 #[serde(tag = "status", content = "content")]
 pub enum ProcessStatus {
     Created,
-    Failed { operation: FailOperation },
     Running,
     Canceled,
-    Exited { exit_status: ExitedStatus },
+    /// Canceled, but did not exit within the cancel grace period and was killed
+    Killed,
+    Exited {
+        exit_status: ExitedStatus,
+    },
     Timeout,
 }
 
-/// Where did the task fail
-#[derive(Debug, Clone, Serialize, ToSchema)]
-pub enum FailOperation {
-    /// Failed to spawn OS process
-    OnSpawn,
-    /// Failed after timeout while killing OS process
-    AfterTimeoutOnKill,
-    /// Failed after timeout while waiting for OS process
-    AfterTimeoutOnWait,
-    /// Failed after cancel while killing OS process
-    AfterCancelOnKill,
-    /// Failed after cancel while waiting for OS process
-    AfterCancelOnWait,
-    /// Failed during wait
-    OnWait,
-}
-
 #[derive(Debug, Clone, Serialize, ToSchema)]
 #[serde(tag = "exit_status", content = "content")]
 pub enum ExitedStatus {
@@ -62,6 +86,36 @@ pub enum ExitedStatus {
     Failure { code: Option<i32> },
 }
 
+impl Status {
+    /// Whether the task is done and its status will not change anymore
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            Status::Download(status) => !matches!(
+                status,
+                DownloadZipFileStatus::Created
+                    | DownloadZipFileStatus::Running
+                    | DownloadZipFileStatus::Downloading { .. }
+                    | DownloadZipFileStatus::Unzipping { .. }
+            ),
+            Status::Process(status) => {
+                !matches!(status, ProcessStatus::Created | ProcessStatus::Running)
+            }
+            Status::Failed { .. } => true,
+        }
+    }
+
+    /// Exit code of the OS process, if it exited
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Status::Process(ProcessStatus::Exited { exit_status }) => match exit_status {
+                ExitedStatus::Success => Some(0),
+                ExitedStatus::Failure { code } => *code,
+            },
+            _ => None,
+        }
+    }
+}
+
 impl From<ExitStatus> for ExitedStatus {
     fn from(exit_status: ExitStatus) -> Self {
         if exit_status.success() {
@@ -81,11 +135,225 @@ impl From<ExitStatus> for ProcessStatus {
     }
 }
 
+/// How often a failed download is retried.
+///
+/// Only network errors and server errors (5xx) are retried.
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadRetryPolicy {
+    pub max_retries: u32,
+    /// Wait before the first retry. Doubled for every further retry
+    pub backoff: Duration,
+}
+
+/// Limits of the OS process of a task, so a runaway job can not starve the host.
+///
+/// Only applied on Linux, with `setrlimit` right before the process starts.
+/// A limit above the hard limit of the server is lowered to it, since raising a hard limit needs privileges.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceLimits {
+    /// CPU time in seconds. The process is killed once it used it up
+    pub cpu_secs: Option<u64>,
+    /// Address space in bytes. Allocations beyond it fail
+    pub memory_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    #[cfg(target_os = "linux")]
+    fn apply(self, command: &mut Command, errors: &PreExecErrors) {
+        use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+        let within_hard_limit = |resource: Resource, limit: Option<u64>| {
+            let limit = limit?;
+
+            match getrlimit(resource) {
+                Ok((_, hard)) if limit > hard => {
+                    tracing::warn!(?resource, %limit, %hard, "Limit is above the hard limit of the server. Lowering it");
+
+                    Some(hard)
+                }
+                _ => Some(limit),
+            }
+        };
+
+        let cpu_secs = within_hard_limit(Resource::RLIMIT_CPU, self.cpu_secs);
+        let memory_bytes = within_hard_limit(Resource::RLIMIT_AS, self.memory_bytes);
+
+        if cpu_secs.is_none() && memory_bytes.is_none() {
+            return;
+        }
+
+        let errors = errors.reporter();
+
+        // SAFETY: Between fork and exec only `setrlimit` and `write` are called, which are async-signal-safe.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(cpu_secs) = cpu_secs {
+                    setrlimit(Resource::RLIMIT_CPU, cpu_secs, cpu_secs)
+                        .map_err(|errno| errors.report(errno.into()))?;
+                }
+
+                if let Some(memory_bytes) = memory_bytes {
+                    setrlimit(Resource::RLIMIT_AS, memory_bytes, memory_bytes)
+                        .map_err(|errno| errors.report(errno.into()))?;
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn apply(self, _command: &mut Command, _errors: &PreExecErrors) {
+        if self.cpu_secs.is_some() || self.memory_bytes.is_some() {
+            tracing::warn!("Resource limits are only applied on Linux");
+        }
+    }
+}
+
+/// Niceness of OS processes that is accepted by [`Task::with_nice`]. Only lowering the priority needs no privileges.
+pub const NICE_RANGE: std::ops::RangeInclusive<i32> = 0..=19;
+
+/// Tells a failing `pre_exec` hook apart from a failing `exec`. Both fail the spawn with a bare OS error.
+///
+/// A failing hook writes its error to a close-on-exec pipe first.
+#[cfg(unix)]
+struct PreExecErrors {
+    read: std::os::fd::OwnedFd,
+    write: std::os::fd::OwnedFd,
+}
+
+#[cfg(unix)]
+impl PreExecErrors {
+    fn new() -> std::io::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        let mut fds = [0; 2];
+
+        // SAFETY: `fds` has room for both ends of the pipe.
+        if unsafe { nix::libc::pipe2(fds.as_mut_ptr(), nix::libc::O_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // SAFETY: Both ends were just opened and are owned by nothing else.
+        unsafe {
+            Ok(Self {
+                read: std::os::fd::OwnedFd::from_raw_fd(fds[0]),
+                write: std::os::fd::OwnedFd::from_raw_fd(fds[1]),
+            })
+        }
+    }
+
+    fn reporter(&self) -> PreExecErrorReporter {
+        use std::os::fd::AsRawFd;
+
+        PreExecErrorReporter(self.write.as_raw_fd())
+    }
+
+    /// The error a hook reported before the spawn failed, if any.
+    fn take(self) -> Option<std::io::Error> {
+        // Otherwise reading would not end without a reported error.
+        drop(self.write);
+
+        let mut errno = [0; 4];
+        std::io::Read::read_exact(&mut std::fs::File::from(self.read), &mut errno).ok()?;
+
+        Some(std::io::Error::from_raw_os_error(i32::from_ne_bytes(errno)))
+    }
+}
+
+/// Used by the `pre_exec` hooks in the child, so it only makes async-signal-safe calls.
+#[cfg(unix)]
+#[derive(Clone, Copy)]
+struct PreExecErrorReporter(std::os::fd::RawFd);
+
+#[cfg(unix)]
+impl PreExecErrorReporter {
+    fn report(self, err: std::io::Error) -> std::io::Error {
+        if let Some(errno) = err.raw_os_error() {
+            let errno = errno.to_ne_bytes();
+
+            // SAFETY: `errno` outlives the call.
+            unsafe { nix::libc::write(self.0, errno.as_ptr().cast(), errno.len()) };
+        }
+
+        err
+    }
+}
+
+/// Without `pre_exec` hooks no error is ever reported.
+#[cfg(not(unix))]
+struct PreExecErrors;
+
+#[cfg(not(unix))]
+impl PreExecErrors {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self)
+    }
+
+    fn take(self) -> Option<std::io::Error> {
+        None
+    }
+}
+
 pub struct Data {
     pub id: String,
     pub status: RwLock<Status>,
+    /// Whether [`Data::status`] is terminal. Read without waiting for the lock of the status
+    terminal: AtomicBool,
+    /// Progress of a running download.
+    ///
+    /// Not part of [`Data::status`] because the unzipping runs on a blocking thread.
+    progress: std::sync::Mutex<Option<DownloadZipFileStatus>>,
+    /// Bytes received by all download attempts
+    bytes_downloaded: AtomicU64,
+    /// Notified whenever [`Data::status`] is set
+    status_changed: Arc<Notify>,
+    /// Input for the stdin of the OS process. Only set while it runs
+    stdin: std::sync::Mutex<Option<mpsc::Sender<Vec<u8>>>>,
 }
 
+impl Data {
+    fn set_progress(&self, progress: DownloadZipFileStatus) {
+        *self.progress.lock().expect("Progress lock poisoned") = Some(progress);
+    }
+
+    fn set_stdin(&self, stdin: Option<mpsc::Sender<Vec<u8>>>) {
+        *self.stdin.lock().expect("Stdin lock poisoned") = stdin;
+    }
+
+    fn progress(&self) -> Option<DownloadZipFileStatus> {
+        self.progress
+            .lock()
+            .expect("Progress lock poisoned")
+            .clone()
+    }
+}
+
+/// Where the OS process started by [`Task::run_os_process`] reads its input from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TaskStdin {
+    /// The process reads EOF right away
+    #[default]
+    Null,
+    /// The stdin of this process, e.g. the terminal of the `run` subcommand
+    Inherit,
+    /// Written with [`Handle::write_stdin`] until [`Handle::close_stdin`]
+    Piped,
+}
+
+/// Input chunks buffered for the stdin of an OS process, before [`Handle::write_stdin`] fails.
+const STDIN_BUFFER: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StdinError {
+    #[error("Task is not a running OS process")]
+    NotRunning,
+    #[error("The OS process does not read its input as fast as it is written")]
+    Full,
+}
+
+/// Clones share the task. It is canceled once every clone is dropped.
+#[derive(Clone)]
 pub struct Handle {
     /// Used to send cancel signal to the task
     ///
@@ -95,14 +363,64 @@ pub struct Handle {
 }
 
 impl Handle {
+    /// Like [`Status::is_terminal`] of [`Handle::status`], without waiting. Useful while holding other locks.
+    pub fn is_terminal(&self) -> bool {
+        self.data.terminal.load(Ordering::Acquire)
+    }
+
     pub async fn status(&self) -> Status {
-        self.data.status.read().await.clone()
+        let status = self.data.status.read().await.clone();
+
+        match (status, self.data.progress()) {
+            (Status::Download(DownloadZipFileStatus::Running), Some(progress)) => {
+                Status::Download(progress)
+            }
+            (status, _) => status,
+        }
     }
 
     pub fn id(&self) -> &str {
         &self.data.id
     }
 
+    /// Notified on every status transition of the task.
+    ///
+    /// Progress updates of a running download are not status transitions.
+    pub fn status_changed(&self) -> Arc<Notify> {
+        self.data.status_changed.clone()
+    }
+
+    /// Write `data` to the stdin of the running OS process, in the order of the calls.
+    ///
+    /// Fails with [`StdinError::Full`] instead of waiting, if the process does not read its input as fast as it is written.
+    pub fn write_stdin(&self, data: Vec<u8>) -> Result<(), StdinError> {
+        let stdin = self
+            .data
+            .stdin
+            .lock()
+            .expect("Stdin lock poisoned")
+            .clone()
+            .ok_or(StdinError::NotRunning)?;
+
+        stdin.try_send(data).map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => StdinError::Full,
+            mpsc::error::TrySendError::Closed(_) => StdinError::NotRunning,
+        })
+    }
+
+    /// Close the stdin of the running OS process, once the input written before is delivered.
+    ///
+    /// The process reads EOF. Further writes fail.
+    pub fn close_stdin(&self) -> Result<(), StdinError> {
+        self.data
+            .stdin
+            .lock()
+            .expect("Stdin lock poisoned")
+            .take()
+            .map(drop)
+            .ok_or(StdinError::NotRunning)
+    }
+
     /// If called before running the task, the task will be canceled immediately after spawning.
     ///
     /// This will not wait for the task to finish. Waiting for the task to finish may cause a bad response times for the api.
@@ -122,6 +440,10 @@ impl Handle {
 pub struct Task {
     rx: mpsc::Receiver<()>,
     data: Arc<Data>,
+    resource_limits: ResourceLimits,
+    /// Niceness of the OS process. Inherited from the server if not set
+    nice: Option<i32>,
+    stdin: TaskStdin,
 }
 
 impl Task {
@@ -131,6 +453,11 @@ impl Task {
         let data = Arc::new(Data {
             id,
             status: RwLock::new(Status::Process(ProcessStatus::Created)),
+            terminal: AtomicBool::new(false),
+            progress: std::sync::Mutex::new(None),
+            bytes_downloaded: AtomicU64::new(0),
+            status_changed: Arc::new(Notify::new()),
+            stdin: std::sync::Mutex::new(None),
         });
 
         let handle = Handle {
@@ -138,17 +465,92 @@ impl Task {
             data: data.clone(),
         };
 
-        let task = Self { rx, data };
+        let task = Self {
+            rx,
+            data,
+            resource_limits: ResourceLimits::default(),
+            nice: None,
+            stdin: TaskStdin::default(),
+        };
 
         (task, handle)
     }
 
+    /// Limits of the OS process started by [`Task::run_os_process`].
+    pub fn with_resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.resource_limits = resource_limits;
+
+        self
+    }
+
+    /// Niceness of the OS process started by [`Task::run_os_process`], clamped to [`NICE_RANGE`].
+    ///
+    /// Added to the niceness of the server, so the process never runs at a higher priority than the server.
+    pub fn with_nice(mut self, nice: i32) -> Self {
+        self.nice = Some(nice.clamp(*NICE_RANGE.start(), *NICE_RANGE.end()));
+
+        self
+    }
+
+    /// Stdin of the OS process started by [`Task::run_os_process`].
+    pub fn with_stdin(mut self, stdin: TaskStdin) -> Self {
+        self.stdin = stdin;
+
+        self
+    }
+
+    #[cfg(unix)]
+    fn apply_nice(&self, command: &mut Command, errors: &PreExecErrors) {
+        let Some(nice) = self.nice else {
+            return;
+        };
+
+        // `-1` is a valid niceness, only `errno` tells it apart from an error.
+        nix::errno::Errno::clear();
+        // SAFETY: Only reads the niceness of this process.
+        let current = unsafe { nix::libc::getpriority(nix::libc::PRIO_PROCESS, 0) };
+        let current = if current == -1 && nix::errno::Errno::last_raw() != 0 {
+            tracing::warn!(err = %nix::errno::Errno::last(), "Failed to get the niceness of the server. Assuming 0");
+
+            0
+        } else {
+            current
+        };
+
+        // Only ever lowers the priority, which needs no privileges.
+        let nice = current.saturating_add(nice).min(*NICE_RANGE.end());
+
+        let errors = errors.reporter();
+
+        // SAFETY: Between fork and exec only `setpriority` and `write` are called, which are async-signal-safe.
+        unsafe {
+            command.pre_exec(move || {
+                if nix::libc::setpriority(nix::libc::PRIO_PROCESS, 0, nice) == -1 {
+                    return Err(errors.report(std::io::Error::last_os_error()));
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn apply_nice(&self, _command: &mut Command, _errors: &PreExecErrors) {
+        if self.nice.is_some() {
+            tracing::warn!("Niceness is only applied on Unix");
+        }
+    }
+
     fn id(&self) -> &str {
         &self.data.id
     }
 
     async fn set_status(&self, status: Status) {
-        *self.data.status.write().await = status
+        let terminal = status.is_terminal();
+        *self.data.status.write().await = status;
+        self.data.terminal.store(terminal, Ordering::Release);
+
+        self.data.status_changed.notify_waiters();
     }
 
     #[tracing::instrument(name = "status", skip_all)]
@@ -199,12 +601,66 @@ impl Task {
         Self::copy_io(reader, writter).await;
     }
 
+    /// Only removes the directory if nothing else is in it, so files of earlier downloads into the same project stay.
+    async fn remove_empty_project_dir(project_dir: &std::path::Path) {
+        if tokio::fs::remove_dir(project_dir).await.is_ok() {
+            tracing::debug!(?project_dir, "Removed empty project directory");
+        }
+    }
+
+    /// Wait for `permit`, e.g. a scheduler slot, while the download into `project_dir` is queued.
+    ///
+    /// `None` if the task is canceled or the server starts shutting down first. The task is then canceled or aborted without running.
+    pub async fn wait_for_download_permit<T>(
+        &mut self,
+        permit: impl Future<Output = T>,
+        project_dir: &std::path::Path,
+        shutdown: &mut DownloadShutdown,
+    ) -> Option<T> {
+        let status = tokio::select! {
+            biased;
+            _ = self.wait_for_cancel_signal() => Status::Download(DownloadZipFileStatus::Canceled),
+            _ = shutdown.started() => Status::Download(DownloadZipFileStatus::Aborted),
+            permit = permit => return Some(permit),
+        };
+
+        tracing::debug!("Ended while queued");
+
+        Self::remove_empty_project_dir(project_dir).await;
+
+        self.set_status_and_log(status).await;
+
+        None
+    }
+
+    /// Wait for `permit`, e.g. a scheduler slot, while the OS process is queued.
+    ///
+    /// `None` if the task is canceled first. The task is then canceled without spawning the process.
+    pub async fn wait_for_process_permit<T>(
+        &mut self,
+        permit: impl Future<Output = T>,
+    ) -> Option<T> {
+        tokio::select! {
+            biased;
+            _ = self.wait_for_cancel_signal() => {},
+            permit = permit => return Some(permit),
+        };
+
+        tracing::debug!("Canceled while queued");
+
+        self.set_status_and_log(Status::Process(ProcessStatus::Canceled))
+            .await;
+
+        None
+    }
+
     #[tracing::instrument(skip_all, fields(id=self.id(), timeout))]
     pub async fn run_os_process<S, I, O, E>(
         mut self,
         command: S,
         args: I,
         timeout: Duration,
+        cancel_grace: Duration,
         stdout_writer: Option<O>,
         stderr_writer: Option<E>,
     ) where
@@ -225,26 +681,86 @@ impl Task {
             std::process::Stdio::null()
         };
 
-        let child = Command::new(command)
+        let stdin = match self.stdin {
+            TaskStdin::Null => std::process::Stdio::null(),
+            TaskStdin::Inherit => std::process::Stdio::inherit(),
+            TaskStdin::Piped => std::process::Stdio::piped(),
+        };
+
+        let program = command.as_ref().to_string_lossy().to_string();
+
+        let mut process = Command::new(command);
+        process
             .args(args)
+            .stdin(stdin)
             .stdout(stdout)
-            .stderr(stderr)
-            .spawn();
+            .stderr(stderr);
+
+        let pre_exec_errors = match PreExecErrors::new() {
+            Ok(errors) => errors,
+            Err(err) => {
+                tracing::error!(
+                    ?err,
+                    "Failed to create pipe for errors of the process setup"
+                );
+
+                self.set_status_and_log(Status::Failed {
+                    reason: format!("Failed to start `{program}`: {err}"),
+                    kind: FailureKind::Spawn,
+                })
+                .await;
+
+                return;
+            }
+        };
+        self.resource_limits.apply(&mut process, &pre_exec_errors);
+        self.apply_nice(&mut process, &pre_exec_errors);
+
+        let child = process.spawn();
 
         let mut child = match child {
             Ok(child) => child,
             Err(err) => {
-                tracing::error!(?err, "Failed to spawn OS process");
+                tracing::error!(?err, %program, "Failed to spawn OS process");
+
+                let reason = match pre_exec_errors.take() {
+                    // Not about the command, which was never executed.
+                    Some(err) => format!("Failed to set up the process of `{program}`: {err}"),
+                    None => match err.kind() {
+                        std::io::ErrorKind::NotFound => format!("Command `{program}` not found"),
+                        std::io::ErrorKind::PermissionDenied => {
+                            format!("Command `{program}` is not executable")
+                        }
+                        _ => format!("Failed to start `{program}`: {err}"),
+                    },
+                };
 
-                self.set_status_and_log(Status::Process(ProcessStatus::Failed {
-                    operation: FailOperation::OnSpawn,
-                }))
+                self.set_status_and_log(Status::Failed {
+                    reason,
+                    kind: FailureKind::Spawn,
+                })
                 .await;
 
                 return;
             }
         };
 
+        if let Some(mut stdin) = child.stdin.take() {
+            let (tx, mut rx) = mpsc::channel::<Vec<u8>>(STDIN_BUFFER);
+            self.data.set_stdin(Some(tx));
+
+            // Ends with the last sender, closing the stdin of the OS process.
+            tokio::spawn(async move {
+                while let Some(data) = rx.recv().await {
+                    if let Err(err) = stdin.write_all(&data).await {
+                        tracing::debug!(?err, "Failed to write to stdin");
+
+                        break;
+                    }
+                }
+            });
+        }
+
         if let Some(mut write) = stdout_writer {
             let id = self.id().to_string();
             let stdout = child.stdout.take();
@@ -279,161 +795,892 @@ impl Task {
                         match child.wait().await {
                             Ok(exit_status) => {
                                 tracing::debug!(?exit_status, "OS process exited with status");
-                                ProcessStatus::Timeout
+                                Status::Process(ProcessStatus::Timeout)
                             },
                             Err(err) => {
                                 tracing::error!(?err, "Failed to wait for OS process");
-                                ProcessStatus::Failed{ operation: FailOperation::AfterTimeoutOnWait }
+                                Status::Failed { reason: format!("Failed to wait for the OS process after the timeout: {err}"), kind: FailureKind::Process }
                             }
                         }
                     },
 
                     Err(err) => {
                         tracing::error!(?err, "Failed to kill OS process");
-                        ProcessStatus::Failed{ operation: FailOperation::AfterTimeoutOnKill }
+                        Status::Failed { reason: format!("Failed to kill the OS process after the timeout: {err}"), kind: FailureKind::Process }
                     }
                 }
             },
             _ = self.wait_for_cancel_signal() => {
-
-                match child.kill().await {
-                    Ok(_) => {
-                        tracing::debug!("Killed OS process");
-
-                        match child.wait().await {
-                            Ok(_) => {
-                                ProcessStatus::Canceled
-                            },
-                            Err(err) => {
-                                tracing::error!(?err, "Failed to wait for OS process");
-                                ProcessStatus::Failed{ operation: FailOperation::AfterCancelOnWait }
-                            }
-                        }
-                    },
-
-                    Err(err) => {
-                        tracing::error!(?err, "Failed to kill OS process");
-                        ProcessStatus::Failed{ operation: FailOperation::AfterCancelOnKill }
-                    }
-                }
+                Self::cancel_os_process(&mut child, cancel_grace).await
             },
             res = child.wait() => {
                 match res {
                     Ok(exit_status) => {
                         tracing::debug!(?exit_status, "OS process exited with status");
-                        ProcessStatus::from(exit_status)
+                        Status::Process(ProcessStatus::from(exit_status))
                     },
                     Err(err) => {
                         tracing::error!(?err, "Failed to wait for OS process");
-                        ProcessStatus::Failed{ operation: FailOperation::OnWait }
+                        Status::Failed { reason: format!("Failed to wait for the OS process: {err}"), kind: FailureKind::Process }
                     }
                 }
             }
         };
 
-        self.set_status_and_log(Status::Process(status)).await;
+        self.data.set_stdin(None);
+        self.set_status_and_log(status).await;
 
         tracing::debug!("Terminated");
     }
 
+    /// Ask the OS process to terminate and kill it, if it did not exit within `grace`.
+    ///
+    /// A zero `grace` kills the OS process right away.
+    async fn cancel_os_process(child: &mut Child, grace: Duration) -> Status {
+        if !grace.is_zero() && Self::send_terminate_signal(child) {
+            match tokio::time::timeout(grace, child.wait()).await {
+                Ok(Ok(exit_status)) => {
+                    tracing::debug!(?exit_status, "OS process exited after terminate signal");
+                    return Status::Process(ProcessStatus::Canceled);
+                }
+                Ok(Err(err)) => {
+                    tracing::error!(?err, "Failed to wait for OS process");
+                    return Status::Failed {
+                        reason: format!(
+                            "Failed to wait for the OS process after canceling it: {err}"
+                        ),
+                        kind: FailureKind::Process,
+                    };
+                }
+                Err(_) => {
+                    tracing::warn!(?grace, "OS process did not exit within cancel grace period");
+                }
+            }
+
+            return match Self::kill_os_process(child).await {
+                Status::Process(ProcessStatus::Canceled) => Status::Process(ProcessStatus::Killed),
+                status => status,
+            };
+        }
+
+        Self::kill_os_process(child).await
+    }
+
+    async fn kill_os_process(child: &mut Child) -> Status {
+        match child.kill().await {
+            Ok(_) => {
+                tracing::debug!("Killed OS process");
+
+                match child.wait().await {
+                    Ok(_) => Status::Process(ProcessStatus::Canceled),
+                    Err(err) => {
+                        tracing::error!(?err, "Failed to wait for OS process");
+                        Status::Failed {
+                            reason: format!(
+                                "Failed to wait for the OS process after canceling it: {err}"
+                            ),
+                            kind: FailureKind::Process,
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to kill OS process");
+                Status::Failed {
+                    reason: format!("Failed to kill the OS process after canceling it: {err}"),
+                    kind: FailureKind::Process,
+                }
+            }
+        }
+    }
+
+    /// Sends `SIGTERM`. Returns whether the signal was sent.
+    #[cfg(unix)]
+    fn send_terminate_signal(child: &Child) -> bool {
+        use nix::{
+            sys::signal::{kill, Signal},
+            unistd::Pid,
+        };
+
+        let Some(pid) = child.id() else {
+            return false;
+        };
+
+        match kill(Pid::from_raw(pid as i32), Signal::SIGTERM) {
+            Ok(_) => {
+                tracing::debug!("Sent terminate signal to OS process");
+                true
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to send terminate signal to OS process");
+                false
+            }
+        }
+    }
+
+    /// There is no graceful termination on this platform, the OS process is killed right away.
+    #[cfg(not(unix))]
+    fn send_terminate_signal(_child: &Child) -> bool {
+        false
+    }
+
+    /// On cancel, timeout or shutdown the download is aborted and the files it already extracted are removed.
+    ///
+    /// Returns the number of bytes downloaded, including the ones of failed attempts.
     #[tracing::instrument(skip_all, fields(id=self.id(), timeout))]
     pub async fn run_download_and_unzip_from_download_url(
         mut self,
         timeout: Duration,
         download_url: url::Url,
         project_dir: std::path::PathBuf,
-    ) {
+        expected_sha256: Option<String>,
+        retry_policy: DownloadRetryPolicy,
+        mut shutdown: DownloadShutdown,
+    ) -> u64 {
         self.set_status_and_log(Status::Download(DownloadZipFileStatus::Running))
             .await;
 
+        let (abort_tx, abort_rx) = watch::channel(false);
+
+        let download = Self::download_and_unzip_from_download_url(
+            download_url,
+            project_dir.clone(),
+            expected_sha256,
+            retry_policy,
+            abort_rx,
+            self.data.clone(),
+        );
+        tokio::pin!(download);
+
+        let mut download_finished = false;
+
         let status = tokio::select! {
             _ = tokio::time::sleep(timeout) => {
                 tracing::debug!("Timeout");
 
-                DownloadZipFileStatus::Timeout
+                Status::Download(DownloadZipFileStatus::Timeout)
             },
             _ = self.wait_for_cancel_signal() => {
 
-                DownloadZipFileStatus::Canceled
+                Status::Download(DownloadZipFileStatus::Canceled)
+            },
+            _ = shutdown.wait() => {
+
+                Status::Download(DownloadZipFileStatus::Aborted)
             },
-            result = Self::download_and_unzip_from_download_url(download_url, project_dir) => {
+            result = &mut download => {
+                download_finished = true;
+
                 match result {
                     Ok(_) => {
-                        DownloadZipFileStatus::Exited
+                        Status::Download(DownloadZipFileStatus::Exited)
                     },
                     Err(err) => {
-                        DownloadZipFileStatus::Failed { reason: err.to_string() }
+                        Status::Failed { reason: err.to_string(), kind: FailureKind::Download }
                     }
                 }
             },
         };
 
-        self.set_status_and_log(Status::Download(status)).await;
+        if !download_finished {
+            tracing::debug!("Aborting download");
+
+            abort_tx.send_replace(true);
+
+            // Wait for the extraction to stop and clean up after itself.
+            let _ = download.await;
+        }
+
+        if !matches!(status, Status::Download(DownloadZipFileStatus::Exited)) {
+            // The extracted files are gone by now.
+            Self::remove_empty_project_dir(&project_dir).await;
+        }
+
+        self.set_status_and_log(status).await;
 
         tracing::debug!("Terminated");
+
+        self.data.bytes_downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Download the file, continuing from the bytes of an earlier attempt in `partial` if the server supports it.
+    ///
+    /// The received bytes are kept in `partial` if the transfer is interrupted.
+    async fn download(
+        download_url: &url::Url,
+        data: &Data,
+        partial: &mut PartialDownload,
+    ) -> Result<(), DownloadError> {
+        let client = reqwest::Client::new();
+
+        let resume_from = partial.resumable().then_some(partial.len);
+        let mut request = client.get(download_url.clone());
+        if let Some(offset) = resume_from {
+            tracing::debug!(%offset, "Resuming download");
+
+            request = request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+        }
+
+        let mut response = request.send().await.map_err(DownloadError::Reqwest)?;
+
+        if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE && resume_from.is_some()
+        {
+            // E.g. the file changed in the meantime.
+            tracing::debug!("Range not satisfiable. Downloading the whole file");
+
+            response = client
+                .get(download_url.clone())
+                .send()
+                .await
+                .map_err(DownloadError::Reqwest)?;
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(DownloadError::Status(status));
+        }
+
+        let resumed = status == reqwest::StatusCode::PARTIAL_CONTENT
+            && resume_from.is_some_and(|offset| content_range_start(&response) == Some(offset));
+
+        if resumed {
+            partial.total = partial
+                .total
+                .or_else(|| Some(partial.len + response.content_length()?));
+        } else {
+            // The server sent the whole file, e.g. because it ignored the range.
+            partial.truncate().await.map_err(DownloadError::Io)?;
+            partial.content_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from);
+            partial.total = response.content_length();
+            partial.accepts_ranges = response
+                .headers()
+                .get(reqwest::header::ACCEPT_RANGES)
+                .is_some_and(|value| value.as_bytes() == b"bytes");
+        }
+
+        let total = partial.total;
+
+        data.set_progress(DownloadZipFileStatus::Downloading {
+            bytes: partial.len,
+            total,
+        });
+
+        while let Some(chunk) = response.chunk().await.map_err(DownloadError::Bytes)? {
+            partial.append(&chunk).await.map_err(DownloadError::Io)?;
+            data.bytes_downloaded
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+
+            data.set_progress(DownloadZipFileStatus::Downloading {
+                bytes: partial.len,
+                total,
+            });
+        }
+
+        partial.file.flush().await.map_err(DownloadError::Io)?;
+
+        Ok(())
     }
 
+    /// The archive is downloaded to a hidden file in `project_dir`, which is removed again once it is extracted or the download failed.
     async fn download_and_unzip_from_download_url(
         download_url: url::Url,
         project_dir: std::path::PathBuf,
+        expected_sha256: Option<String>,
+        retry_policy: DownloadRetryPolicy,
+        abort: watch::Receiver<bool>,
+        data: Arc<Data>,
     ) -> Result<(), DownloadError> {
-        let response = reqwest::get(download_url)
+        let path = project_dir.join(format!(".download_{}.part", uuid::Uuid::new_v4()));
+
+        let result = Self::download_and_extract(
+            download_url,
+            project_dir,
+            &path,
+            expected_sha256,
+            retry_policy,
+            abort,
+            data,
+        )
+        .await;
+
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => tracing::warn!(?err, ?path, "Failed to remove downloaded archive"),
+        }
+
+        result
+    }
+
+    async fn download_and_extract(
+        download_url: url::Url,
+        project_dir: std::path::PathBuf,
+        path: &std::path::Path,
+        expected_sha256: Option<String>,
+        retry_policy: DownloadRetryPolicy,
+        mut abort: watch::Receiver<bool>,
+        data: Arc<Data>,
+    ) -> Result<(), DownloadError> {
+        let mut retries = 0;
+        let mut partial = PartialDownload::create(path)
             .await
-            .map_err(DownloadError::Reqwest)?;
+            .map_err(DownloadError::Io)?;
+
+        loop {
+            let result = tokio::select! {
+                result = Self::download(&download_url, &data, &mut partial) => result,
+                _ = abort.wait_for(|abort| *abort) => return Err(DownloadError::Aborted),
+            };
+
+            let err = match result {
+                Ok(()) => break,
+                Err(err) if err.is_retryable() && retries < retry_policy.max_retries => err,
+                Err(err) => return Err(err),
+            };
+
+            let backoff = retry_policy
+                .backoff
+                .saturating_mul(2u32.saturating_pow(retries));
+            retries += 1;
+
+            tracing::warn!(%err, ?backoff, %retries, "Download failed. Retrying");
+
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {},
+                _ = abort.wait_for(|abort| *abort) => return Err(DownloadError::Aborted),
+            }
+        }
+
+        tracing::debug!("Archive downloaded");
 
-        let bytes = response.bytes().await.map_err(DownloadError::Bytes)?;
-        tracing::debug!("Zip file downloaded");
+        if let Some(expected) = expected_sha256 {
+            let actual = format!("{:x}", std::mem::take(&mut partial.sha256).finalize());
 
-        let zip = zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(DownloadError::Zip)?;
+            if actual != expected {
+                return Err(DownloadError::ChecksumMismatch { expected, actual });
+            }
+        }
 
-        tracing::debug!("Unzipping files");
+        let mut file = partial.file;
+        file.seek(std::io::SeekFrom::Start(0))
+            .await
+            .map_err(DownloadError::Io)?;
 
-        // ZipFile is not Send -> spawn_blocking
-        tokio::task::spawn_blocking(move || Self::unzip(zip, project_dir))
+        let mut magic = Vec::with_capacity(2);
+        (&mut file)
+            .take(2)
+            .read_to_end(&mut magic)
             .await
-            .map_err(|_| DownloadError::BlockingTask)?
+            .map_err(DownloadError::Io)?;
+        file.seek(std::io::SeekFrom::Start(0))
+            .await
+            .map_err(DownloadError::Io)?;
+
+        let kind = ArchiveKind::detect(&magic, partial.content_type.as_deref());
+
+        tracing::debug!(?kind, "Extracting files");
+
+        data.set_progress(DownloadZipFileStatus::Unzipping { entries_done: 0 });
+
+        match kind {
+            ArchiveKind::Zip => {
+                let file = file.into_std().await;
+
+                // ZipFile is not Send -> spawn_blocking
+                tokio::task::spawn_blocking(move || {
+                    let zip = zip::ZipArchive::new(file).map_err(DownloadError::Zip)?;
+
+                    Self::unzip(zip, project_dir, abort, &data)
+                })
+                .await
+                .map_err(|_| DownloadError::BlockingTask)?
+            }
+            ArchiveKind::TarGz => Self::untar_gz(file, &project_dir, &abort, &data).await,
+        }
+    }
+
+    /// Extracts every regular file of a `.tar.gz` into `project_dir`, like [`Task::unzip`].
+    async fn untar_gz(
+        archive: tokio::fs::File,
+        project_dir: &std::path::Path,
+        abort: &watch::Receiver<bool>,
+        data: &Data,
+    ) -> Result<(), DownloadError> {
+        let mut written_files = Vec::new();
+
+        let result =
+            Self::untar_gz_entries(archive, project_dir, abort, data, &mut written_files).await;
+
+        if result.is_err() {
+            for file_name in written_files {
+                if let Err(err) = tokio::fs::remove_file(&file_name).await {
+                    tracing::warn!(?err, ?file_name, "Failed to remove extracted file");
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn untar_gz_entries(
+        archive: tokio::fs::File,
+        project_dir: &std::path::Path,
+        abort: &watch::Receiver<bool>,
+        data: &Data,
+        written_files: &mut Vec<std::path::PathBuf>,
+    ) -> Result<(), DownloadError> {
+        let decoder = GzipDecoder::new(tokio::io::BufReader::new(archive));
+        let mut archive = tokio_tar::Archive::new(decoder);
+        let mut entries = archive.entries().map_err(DownloadError::Io)?;
+
+        let mut entries_done = 0;
+
+        while let Some(entry) = entries.next().await {
+            if *abort.borrow() {
+                return Err(DownloadError::Aborted);
+            }
+
+            let mut entry = entry.map_err(DownloadError::Io)?;
+
+            // Directories, links and devices are not extracted
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let path = entry.path().map_err(DownloadError::Io)?.into_owned();
+            let file_name = extracted_file_path(project_dir, &path)?;
+
+            let mut outfile = tokio::fs::File::create(&file_name)
+                .await
+                .map_err(DownloadError::Io)?;
+            written_files.push(file_name.clone());
+
+            tokio::io::copy(&mut entry, &mut outfile)
+                .await
+                .map_err(DownloadError::Io)?;
+
+            tracing::debug!(?file_name, "Extracted file");
+
+            entries_done += 1;
+            data.set_progress(DownloadZipFileStatus::Unzipping { entries_done });
+        }
+
+        Ok(())
     }
 
+    /// Extracts every entry into `project_dir`.
+    ///
+    /// If extracting fails or `abort` is set, the files written so far are removed again.
     fn unzip(
-        mut zip: zip::ZipArchive<std::io::Cursor<axum::body::Bytes>>,
+        zip: zip::ZipArchive<std::fs::File>,
         project_dir: std::path::PathBuf,
+        abort: watch::Receiver<bool>,
+        data: &Data,
     ) -> Result<(), DownloadError> {
-        for i in 0..zip.len() {
-            let mut file = zip.by_index(i).map_err(DownloadError::Zip)?;
-            let file_name = std::path::PathBuf::from(file.name());
+        let mut written_files = Vec::new();
 
-            // Strip all directories
-            let file_name = file_name
-                .file_name()
-                .ok_or(DownloadError::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "Invalid file name",
-                )))?;
+        let result = Self::unzip_entries(zip, &project_dir, &abort, data, &mut written_files);
 
-            let file_name = project_dir.join(file_name);
+        if result.is_err() {
+            for file_name in written_files {
+                if let Err(err) = std::fs::remove_file(&file_name) {
+                    tracing::warn!(?err, ?file_name, "Failed to remove extracted file");
+                }
+            }
+        }
+
+        result
+    }
+
+    fn unzip_entries(
+        mut zip: zip::ZipArchive<std::fs::File>,
+        project_dir: &std::path::Path,
+        abort: &watch::Receiver<bool>,
+        data: &Data,
+        written_files: &mut Vec<std::path::PathBuf>,
+    ) -> Result<(), DownloadError> {
+        for i in 0..zip.len() {
+            if *abort.borrow() {
+                return Err(DownloadError::Aborted);
+            }
+
+            let mut file = zip.by_index(i).map_err(DownloadError::Zip)?;
+            let file_name = extracted_file_path(project_dir, std::path::Path::new(file.name()))?;
 
             let mut outfile = std::fs::File::create(&file_name).map_err(DownloadError::Io)?;
+            written_files.push(file_name.clone());
 
             let _ = std::io::copy(&mut file, &mut outfile).map_err(DownloadError::Io)?;
 
             tracing::debug!(?file_name, "Unzipped file");
+
+            data.set_progress(DownloadZipFileStatus::Unzipping {
+                entries_done: i + 1,
+            });
         }
 
         Ok(())
     }
 }
 
+/// What an interrupted download attempt already received, written to a file so it is not kept in memory.
+struct PartialDownload {
+    file: tokio::fs::File,
+    /// Bytes written to [`PartialDownload::file`]
+    len: u64,
+    /// The server answered with `Accept-Ranges: bytes`
+    accepts_ranges: bool,
+    /// Size of the whole file, if known
+    total: Option<u64>,
+    /// Digest of the written bytes, updated as they arrive
+    sha256: Sha256,
+    content_type: Option<String>,
+}
+
+impl PartialDownload {
+    async fn create(path: &std::path::Path) -> std::io::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await?;
+
+        Ok(Self {
+            file,
+            len: 0,
+            accepts_ranges: false,
+            total: None,
+            sha256: Sha256::new(),
+            content_type: None,
+        })
+    }
+
+    fn resumable(&self) -> bool {
+        self.accepts_ranges && self.len > 0
+    }
+
+    async fn append(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(chunk).await?;
+        self.len += chunk.len() as u64;
+        self.sha256.update(chunk);
+
+        Ok(())
+    }
+
+    /// Start over, e.g. because the server sent the whole file again.
+    async fn truncate(&mut self) -> std::io::Result<()> {
+        self.file.set_len(0).await?;
+        self.file.seek(std::io::SeekFrom::Start(0)).await?;
+        self.len = 0;
+        self.sha256 = Sha256::new();
+
+        Ok(())
+    }
+}
+
+/// Where an archive entry is extracted to: directly in `project_dir`, with all directories stripped.
+///
+/// Entries with `..`, a root or a drive prefix in their name are rejected, so a crafted archive
+/// can not write outside of `project_dir` (zip slip).
+fn extracted_file_path(
+    project_dir: &std::path::Path,
+    entry_path: &std::path::Path,
+) -> Result<std::path::PathBuf, DownloadError> {
+    use std::path::Component;
+
+    let unsafe_entry = || DownloadError::UnsafeEntryPath(entry_path.to_string_lossy().to_string());
+
+    if !entry_path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(unsafe_entry());
+    }
+
+    let file_name = entry_path.file_name().ok_or_else(unsafe_entry)?;
+
+    Ok(project_dir.join(file_name))
+}
+
+/// Format of a downloaded archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// By the file signature, then by the `Content-Type`. Defaults to zip.
+    fn detect(bytes: &[u8], content_type: Option<&str>) -> Self {
+        if bytes.starts_with(b"PK") {
+            return Self::Zip;
+        }
+
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            return Self::TarGz;
+        }
+
+        let mime = content_type.and_then(|content_type| content_type.split(';').next());
+
+        match mime.map(str::trim) {
+            Some("application/gzip" | "application/x-gzip" | "application/x-gtar") => Self::TarGz,
+            _ => Self::Zip,
+        }
+    }
+}
+
+/// First byte of a `Content-Range: bytes <start>-<end>/<total>` response.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .parse()
+        .ok()
+}
+
 /// Inner error type for [`Task::download_and_unzip_from_download_url`]
 #[derive(Debug, thiserror::Error)]
 enum DownloadError {
     #[error("Reqwest error: {0}")]
     Reqwest(reqwest::Error),
+    #[error("Unexpected response status: {0}")]
+    Status(reqwest::StatusCode),
     #[error("Failed to extract bytes: {0}")]
     Bytes(reqwest::Error),
     #[error("Zip error: {0}")]
     Zip(zip::result::ZipError),
     #[error("Io error: {0}")]
     Io(std::io::Error),
+    #[error("Archive entry {0:?} points outside of the project directory")]
+    UnsafeEntryPath(String),
+    #[error("Sha256 mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
     #[error("Failed to spawn blocking task")]
     BlockingTask,
+    #[error("Download aborted")]
+    Aborted,
+}
+
+impl DownloadError {
+    /// Network errors and server errors may go away on their own, client errors will not.
+    fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Reqwest(_) | DownloadError::Bytes(_) => true,
+            DownloadError::Status(status) => status.is_server_error(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    async fn cancel_shell(script: &'static str, cancel_grace: Duration) -> Status {
+        let (task, handle) = Task::new(String::from("0"));
+
+        let running = tokio::spawn(task.run_os_process(
+            "sh",
+            ["-c", script],
+            Duration::from_secs(60),
+            cancel_grace,
+            None::<tokio::io::Sink>,
+            None::<tokio::io::Sink>,
+        ));
+
+        // Give the shell time to set up its trap.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        handle.send_cancel_signal().await;
+        running.await.unwrap();
+
+        handle.status().await
+    }
+
+    #[tokio::test]
+    #[cfg_attr(
+        not(target_os = "linux"),
+        ignore = "Resource limits are only applied on Linux"
+    )]
+    async fn process_exceeding_the_memory_limit_fails() {
+        // Keeps the last 100 MB of its input in memory.
+        let run = |resource_limits| async move {
+            let (task, handle) = Task::new(String::from("0"));
+
+            task.with_resource_limits(resource_limits)
+                .run_os_process(
+                    "sh",
+                    [
+                        "-c",
+                        "head -c 200000000 /dev/zero | tail -c 100000000 > /dev/null",
+                    ],
+                    Duration::from_secs(60),
+                    Duration::ZERO,
+                    None::<tokio::io::Sink>,
+                    None::<tokio::io::Sink>,
+                )
+                .await;
+
+            handle.status().await
+        };
+
+        let status = run(ResourceLimits::default()).await;
+        assert_eq!(status.exit_code(), Some(0), "{status:?}");
+
+        let status = run(ResourceLimits {
+            memory_bytes: Some(64 * 1024 * 1024),
+            ..Default::default()
+        })
+        .await;
+        assert!(status.is_terminal());
+        assert_ne!(status.exit_code(), Some(0), "{status:?}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn process_runs_with_the_requested_niceness() {
+        let run = |nice| async move {
+            let (task, _handle) = Task::new(String::from("0"));
+            let (stdout_tx, mut stdout_rx) = tokio::io::duplex(64);
+
+            task.with_nice(nice)
+                .run_os_process(
+                    "sh",
+                    ["-c", "ps -o nice= -p $$"],
+                    Duration::from_secs(10),
+                    Duration::ZERO,
+                    Some(stdout_tx),
+                    None::<tokio::io::Sink>,
+                )
+                .await;
+
+            let mut stdout = String::new();
+            tokio::io::AsyncReadExt::read_to_string(&mut stdout_rx, &mut stdout)
+                .await
+                .unwrap();
+
+            stdout.trim().parse::<i32>().expect("Niceness is printed")
+        };
+
+        // SAFETY: Only reads the niceness of this process.
+        let current = unsafe { nix::libc::getpriority(nix::libc::PRIO_PROCESS, 0) };
+
+        // Relative to the niceness of the server.
+        assert_eq!(run(10).await, (current + 10).min(19));
+        // Raising the priority is not allowed, so it is clamped.
+        assert_eq!(run(-5).await, current);
+        assert_eq!(run(100).await, 19);
+    }
+
+    #[test]
+    fn failing_pre_exec_hook_is_told_apart_from_failing_exec() {
+        use std::os::unix::process::CommandExt;
+
+        let errors = PreExecErrors::new().unwrap();
+        let reporter = errors.reporter();
+        let mut command = std::process::Command::new("true");
+        // SAFETY: The hook only reports an error.
+        unsafe {
+            command.pre_exec(move || {
+                Err(reporter.report(std::io::Error::from_raw_os_error(nix::libc::EACCES)))
+            });
+        }
+        assert!(command.spawn().is_err());
+        assert_eq!(
+            errors.take().and_then(|err| err.raw_os_error()),
+            Some(nix::libc::EACCES)
+        );
+
+        let errors = PreExecErrors::new().unwrap();
+        assert!(std::process::Command::new("/nonexistent").spawn().is_err());
+        assert!(errors.take().is_none());
+    }
+
+    #[tokio::test]
+    async fn process_reading_stdin_gets_eof_unless_it_is_piped() {
+        let (task, handle) = Task::new(String::from("0"));
+        let (stdout_tx, mut stdout_rx) = tokio::io::duplex(1024);
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            task.run_os_process(
+                "cat",
+                Vec::<&str>::new(),
+                Duration::from_secs(60),
+                Duration::ZERO,
+                Some(stdout_tx),
+                None::<tokio::io::Sink>,
+            ),
+        )
+        .await
+        .expect("Process waited for input");
+
+        let mut stdout = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stdout_rx, &mut stdout)
+            .await
+            .unwrap();
+        assert_eq!(stdout, "");
+        assert!(matches!(
+            handle.status().await,
+            Status::Process(ProcessStatus::Exited {
+                exit_status: ExitedStatus::Success
+            })
+        ));
+        assert!(matches!(
+            handle.write_stdin(b"input".to_vec()),
+            Err(StdinError::NotRunning)
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_command_fails_with_a_reason() {
+        let (task, handle) = Task::new(String::from("0"));
+        assert!(!handle.is_terminal());
+
+        task.run_os_process(
+            "job-hub-command-that-does-not-exist",
+            ["--flag"],
+            Duration::from_secs(60),
+            Duration::ZERO,
+            None::<tokio::io::Sink>,
+            None::<tokio::io::Sink>,
+        )
+        .await;
+
+        let status = handle.status().await;
+        assert!(status.is_terminal());
+        assert!(handle.is_terminal());
+        assert!(
+            matches!(
+                &status,
+                Status::Failed { reason, kind: FailureKind::Spawn }
+                    if reason == "Command `job-hub-command-that-does-not-exist` not found"
+            ),
+            "{status:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn process_ignoring_terminate_signal_is_killed_after_grace() {
+        let status = cancel_shell("trap '' TERM; sleep 30", Duration::from_millis(200)).await;
+
+        assert!(matches!(status, Status::Process(ProcessStatus::Killed)));
+    }
+
+    #[tokio::test]
+    async fn process_exiting_on_terminate_signal_is_canceled() {
+        let status = cancel_shell("sleep 30", Duration::from_secs(10)).await;
+
+        assert!(matches!(status, Status::Process(ProcessStatus::Canceled)));
+    }
 }
@@ -0,0 +1,148 @@
+use super::ws::TaskIoChunk;
+use std::{collections::VecDeque, sync::Mutex};
+
+/// The most recent IO chunks of a task, so clients that connect late can catch up.
+///
+/// Holds at most `capacity` chunks and `max_bytes` bytes of output.
+/// The oldest chunks are dropped to make room for a new one.
+pub struct TaskOutput {
+    capacity: usize,
+    max_bytes: usize,
+    buffer: Mutex<Buffer>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    chunks: VecDeque<TaskIoChunk>,
+    /// Bytes of all chunks in [`Buffer::chunks`]
+    bytes: usize,
+    /// Bytes of output dropped to stay within the limits
+    dropped_bytes: u64,
+}
+
+impl TaskOutput {
+    /// Without a limit on the bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_max_bytes(capacity, usize::MAX)
+    }
+
+    pub fn with_max_bytes(capacity: usize, max_bytes: usize) -> Self {
+        Self {
+            capacity,
+            max_bytes,
+            buffer: Mutex::new(Buffer::default()),
+        }
+    }
+
+    pub fn push(&self, mut chunk: TaskIoChunk) {
+        let mut buffer = self.buffer.lock().expect("Task output lock poisoned");
+
+        if self.capacity == 0 || self.max_bytes == 0 {
+            buffer.dropped_bytes += chunk.chunk.len() as u64;
+
+            return;
+        }
+
+        // A chunk larger than the limit keeps only its end.
+        if chunk.chunk.len() > self.max_bytes {
+            let mut start = chunk.chunk.len() - self.max_bytes;
+            while !chunk.chunk.is_char_boundary(start) {
+                start += 1;
+            }
+
+            buffer.dropped_bytes += start as u64;
+            chunk.chunk.drain(..start);
+        }
+
+        while buffer.chunks.len() >= self.capacity
+            || buffer.bytes + chunk.chunk.len() > self.max_bytes
+        {
+            let Some(oldest) = buffer.chunks.pop_front() else {
+                break;
+            };
+
+            buffer.bytes -= oldest.chunk.len();
+            buffer.dropped_bytes += oldest.chunk.len() as u64;
+        }
+
+        buffer.bytes += chunk.chunk.len();
+        buffer.chunks.push_back(chunk);
+    }
+
+    /// The last `n` chunks, oldest first.
+    pub fn last(&self, n: usize) -> Vec<TaskIoChunk> {
+        let buffer = self.buffer.lock().expect("Task output lock poisoned");
+
+        let skip = buffer.chunks.len().saturating_sub(n);
+
+        buffer.chunks.iter().skip(skip).cloned().collect()
+    }
+
+    /// Bytes of output that are not buffered anymore. `0` if nothing was dropped.
+    pub fn dropped_bytes(&self) -> u64 {
+        self.buffer
+            .lock()
+            .expect("Task output lock poisoned")
+            .dropped_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ws::IoType;
+
+    fn chunk(chunk: &str) -> TaskIoChunk {
+        TaskIoChunk {
+            id: String::from("0"),
+            chunk: chunk.to_string(),
+            io_type: IoType::Stdout,
+        }
+    }
+
+    #[test]
+    fn oldest_chunks_are_dropped() {
+        let output = TaskOutput::new(2);
+
+        output.push(chunk("a"));
+        output.push(chunk("b"));
+        output.push(chunk("c"));
+
+        let chunks: Vec<String> = output.last(10).into_iter().map(|c| c.chunk).collect();
+        assert_eq!(chunks, ["b", "c"]);
+
+        let chunks: Vec<String> = output.last(1).into_iter().map(|c| c.chunk).collect();
+        assert_eq!(chunks, ["c"]);
+    }
+
+    #[test]
+    fn oldest_bytes_are_dropped_over_the_byte_limit() {
+        let output = TaskOutput::with_max_bytes(100, 8);
+
+        output.push(chunk("aaaa"));
+        output.push(chunk("bbbb"));
+        assert_eq!(output.dropped_bytes(), 0);
+
+        output.push(chunk("cc"));
+
+        let chunks: Vec<String> = output.last(10).into_iter().map(|c| c.chunk).collect();
+        assert_eq!(chunks, ["bbbb", "cc"]);
+        assert_eq!(output.dropped_bytes(), 4);
+
+        // Only the end of a chunk larger than the limit is kept, without splitting a character.
+        output.push(chunk("0123456789ü"));
+
+        let chunks: Vec<String> = output.last(10).into_iter().map(|c| c.chunk).collect();
+        assert_eq!(chunks, ["456789ü"]);
+        assert_eq!(output.dropped_bytes(), 4 + 6 + 4);
+
+        // Without room, every chunk is dropped and counted.
+        for output in [TaskOutput::new(0), TaskOutput::with_max_bytes(100, 0)] {
+            output.push(chunk("aaaa"));
+            output.push(chunk("bb"));
+
+            assert!(output.last(10).is_empty());
+            assert_eq!(output.dropped_bytes(), 6);
+        }
+    }
+}
@@ -15,6 +15,11 @@ pub enum GoogleConvertLinkError {
     NoSegments,
 }
 
+/// Whole seconds for a `Retry-After` header. Rounded up, so clients do not come back too early.
+pub fn retry_after_secs(retry_after: std::time::Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
 pub fn convert_google_share_or_view_url_to_download_url(
     share_url: url::Url,
 ) -> Result<url::Url, GoogleConvertLinkError> {
@@ -10,13 +10,49 @@ use serde::{Deserialize, Serialize};
 // }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ClientMessage {}
+#[serde(tag = "client_message", content = "content")]
+pub enum ClientMessage {
+    /// Ask for the most recent IO chunks of a task, to catch up after connecting late
+    Replay {
+        task_id: String,
+        /// Number of chunks. Defaults to every buffered chunk
+        last_n: Option<usize>,
+    },
+    /// Only receive the IO of the subscribed tasks.
+    /// A connection without subscriptions receives the IO of every task of its chat
+    Subscribe {
+        task_id: String,
+        /// Only receive this kind of IO. Defaults to both
+        io_type: Option<IoType>,
+    },
+    /// Receive the last lines of a file of a project, then every line appended to it, as [`ServerMessage::FileAppend`].
+    /// Only projects of the chat's tasks can be tailed
+    TailFile { project: String, file: String },
+    /// Write `data` to the stdin of a running process task of the chat. No newline is appended
+    Stdin { task_id: String, data: String },
+    /// Close the stdin of a running process task of the chat, so it reads EOF
+    CloseStdin { task_id: String },
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "server_message", content = "content")]
 pub enum ServerMessage {
     /// A Chunk of IO output from a task
     TaskIoChunk(TaskIoChunk),
+    /// Sent before the chunks of a [`ClientMessage::Replay`] if older output of the task was dropped from its buffer
+    ReplayTruncated {
+        id: String,
+        /// Bytes of output that can not be replayed anymore
+        dropped_bytes: u64,
+    },
+    /// Lines of a file tailed with [`ClientMessage::TailFile`]
+    FileAppend {
+        project: String,
+        file: String,
+        lines: Vec<String>,
+    },
+    /// The `data` of a [`ClientMessage::Stdin`] was not written, e.g. because the process does not read its input fast enough
+    StdinRejected { id: String, reason: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,8 +62,10 @@ pub struct TaskIoChunk {
     pub io_type: IoType,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IoType {
+    #[serde(alias = "stdout")]
     Stdout,
+    #[serde(alias = "stderr")]
     Stderr,
 }